@@ -5,26 +5,12 @@ use leptos_router::{
     StaticSegment,
 };
 
-#[cfg(feature = "hydrate")]
-use crate::types::MouseEvent;
-
-#[cfg(feature = "hydrate")]
-use wasm_bindgen::prelude::*;
-
-#[cfg(feature = "hydrate")]
-use wasm_bindgen_futures::JsFuture;
+use crate::tracker::{CaptureMode, MouseTracker, TrackerHandle};
 
 #[cfg(feature = "hydrate")]
-use web_sys::{Request, RequestInit, RequestMode, Headers, Response, EventTarget, HtmlElement, KeyboardEvent};
-
-#[cfg(feature = "hydrate")]
-use std::cell::RefCell;
-#[cfg(feature = "hydrate")]
-use std::rc::Rc;
+use crate::tracker::{reset_participant_token, send_consent, send_unlink};
 #[cfg(feature = "hydrate")]
-use js_sys::Function;
-#[cfg(feature = "hydrate")]
-use std::collections;
+use crate::types::ConsentDecision;
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
     view! {
@@ -71,221 +57,34 @@ pub fn App() -> impl IntoView {
 /// Renders the home page of your application.
 #[component]
 fn HomePage() -> impl IntoView {
-    let event_count = RwSignal::new(0u32);
-
-    #[cfg(feature = "hydrate")]
-    let (event_buffer, debounced_send, tracking_state) = create_tracking_system();
-
-    // 为每个事件处理器创建克隆的引用
-    #[cfg(feature = "hydrate")]
-    let buffer_mv = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_mv = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_mv = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_md = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_md = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_md = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_mu = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_mu = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_mu = tracking_state.clone();
-    #[cfg(feature = "hydrate")]
-    let state_wh = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_wh = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_wh = debounced_send.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_ds = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_ds = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_ds = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_d = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_d = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_d = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_de = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_de = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_de = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_kd = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_kd = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_kd = tracking_state.clone();
-
-    #[cfg(feature = "hydrate")]
-    let buffer_ku = event_buffer.clone();
-    #[cfg(feature = "hydrate")]
-    let sender_ku = debounced_send.clone();
-    #[cfg(feature = "hydrate")]
-    let state_ku = tracking_state.clone();
+    // 同意状态：None=尚未决定，Some(true)=已同意，Some(false)=已拒绝或撤回（经服务器确认）
+    let consent = RwSignal::new(None::<bool>);
+    // 已点击撤回、服务器尚未确认：立即停止记录
+    let withdrawing = RwSignal::new(false);
+    let tracker = TrackerHandle::new();
 
     view! {
         <div style="padding: 20px;">
             <h1>"Mouse Tracker Demo - IL Training Data"</h1>
             <p>"移动鼠标、点击、拖拽、按键以记录交互事件"</p>
-            <p>"已记录事件数: " {event_count}</p>
-            <p>"会话 ID: " {move || {
-                #[cfg(feature = "hydrate")]
-                return tracking_state.session_id.clone();
-                #[cfg(not(feature = "hydrate"))]
-                return "N/A".to_string();
+            <p>"已记录事件数: " {move || tracker.stats().recorded}</p>
+            <p>"会话 ID: " {move || match tracker.session_id() {
+                id if id.is_empty() => "N/A".to_string(),
+                id => id,
             }}</p>
 
-            // 全局键盘事件监听
-            <div
-                style="width: 100%; height: 500px; background: #f0f0f0; border: 2px solid #ccc; position: relative; user-select: none;"
-                tabindex="0"
-                on:mousemove=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("buttons".to_string(), e.buttons().to_string());
-
-                        let mouse_event = create_mouse_event("mousemove", e.client_x(), e.client_y(), &state_mv, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_mv, &sender_mv);
-                    }
-                }
-                on:mousedown=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let button = match e.button() {
-                            0 => "left",
-                            1 => "middle",
-                            2 => "right",
-                            _ => "unknown",
-                        };
-
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("button".to_string(), button.to_string());
-                        extra.insert("buttons".to_string(), e.buttons().to_string());
-
-                        let mouse_event = create_mouse_event("mousedown", e.client_x(), e.client_y(), &state_md, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_md, &sender_md);
-                    }
-                }
-                on:mouseup=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let button = match e.button() {
-                            0 => "left",
-                            1 => "middle",
-                            2 => "right",
-                            _ => "unknown",
-                        };
-
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("button".to_string(), button.to_string());
-                        extra.insert("buttons".to_string(), e.buttons().to_string());
-
-                        let mouse_event = create_mouse_event("mouseup", e.client_x(), e.client_y(), &state_mu, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_mu, &sender_mu);
-                    }
-                }
-                on:wheel=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("scroll_y".to_string(), e.delta_y().to_string());
-                        extra.insert("scroll_x".to_string(), e.delta_x().to_string());
-
-                        let mouse_event = create_mouse_event("wheel", e.client_x(), e.client_y(), &state_wh, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_wh, &sender_wh);
-                    }
-                }
-                on:dragstart=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let mouse_event = create_mouse_event("dragstart", e.client_x(), e.client_y(), &state_ds, None, &e);
-
-                        // 记录拖拽开始事件 ID
-                        *state_ds.drag_state.borrow_mut() = Some(mouse_event.event_id.clone());
-
-                        queue_enriched_event(mouse_event, &buffer_ds, &sender_ds);
-                    }
-                }
-                on:drag=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let mouse_event = create_mouse_event("drag", e.client_x(), e.client_y(), &state_d, None, &e);
-                        queue_enriched_event(mouse_event, &buffer_d, &sender_d);
-                    }
-                }
-                on:dragend=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let mouse_event = create_mouse_event("dragend", e.client_x(), e.client_y(), &state_de, None, &e);
+            <ConsentBanner consent withdrawing tracker/>
 
-                        // 清除拖拽状态
-                        *state_de.drag_state.borrow_mut() = None;
-
-                        queue_enriched_event(mouse_event, &buffer_de, &sender_de);
-                    }
-                }
-                on:keydown=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let keyboard_evt = e.dyn_ref::<web_sys::KeyboardEvent>().unwrap();
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("key".to_string(), keyboard_evt.key());
-                        extra.insert("code".to_string(), keyboard_evt.code());
-                        extra.insert("ctrl_key".to_string(), keyboard_evt.ctrl_key().to_string());
-                        extra.insert("shift_key".to_string(), keyboard_evt.shift_key().to_string());
-                        extra.insert("alt_key".to_string(), keyboard_evt.alt_key().to_string());
-                        extra.insert("meta_key".to_string(), keyboard_evt.meta_key().to_string());
-
-                        let mouse_event = create_mouse_event("keydown", 0, 0, &state_kd, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_kd, &sender_kd);
-                    }
-                }
-                on:keyup=move |e| {
-                    event_count.update(|n| *n += 1);
-                    #[cfg(feature = "hydrate")]
-                    {
-                        let keyboard_evt = e.dyn_ref::<web_sys::KeyboardEvent>().unwrap();
-                        let mut extra = std::collections::HashMap::new();
-                        extra.insert("key".to_string(), keyboard_evt.key());
-                        extra.insert("code".to_string(), keyboard_evt.code());
-                        extra.insert("ctrl_key".to_string(), keyboard_evt.ctrl_key().to_string());
-                        extra.insert("shift_key".to_string(), keyboard_evt.shift_key().to_string());
-                        extra.insert("alt_key".to_string(), keyboard_evt.alt_key().to_string());
-                        extra.insert("meta_key".to_string(), keyboard_evt.meta_key().to_string());
-
-                        let mouse_event = create_mouse_event("keyup", 0, 0, &state_ku, Some(extra), &e);
-                        queue_enriched_event(mouse_event, &buffer_ku, &sender_ku);
-                    }
-                }
+            // 撤回同意后停止记录并丢弃尚未发送的事件；整页监听，拖出灰色区域也会记录
+            <MouseTracker
+                handle=tracker
+                capture=CaptureMode::Document
+                suppress_context_menu=true
+                enabled=Signal::derive(move || consent.get() == Some(true) && !withdrawing.get())
+                attr:style="width: 100%; height: 500px; background: #f0f0f0; border: 2px solid #ccc; position: relative; user-select: none; touch-action: none;"
             >
                 <p style="padding: 20px; text-align: center; color: #666;">
-                    "在此区域内移动鼠标、点击、拖拽或按键"
+                    "在此区域内移动鼠标、点击、拖拽或按键（区域外的事件也会记录，并标记 in_tracking_area=false）"
                 </p>
                 <div
                     style="width: 100px; height: 100px; background: #4CAF50; color: white; display: flex; align-items: center; justify-content: center; position: absolute; top: 50%; left: 50%; transform: translate(-50%, -50%); cursor: move;"
@@ -293,304 +92,138 @@ fn HomePage() -> impl IntoView {
                 >
                     "拖拽我!"
                 </div>
-            </div>
+            </MouseTracker>
 
             <p style="margin-top: 20px; font-size: 14px; color: #666;">
                 "事件将保存到服务器上的 mouse_events.jsonl 文件"
             </p>
             <p style="font-size: 12px; color: #999;">
-                "支持的事件: mousemove, mousedown, mouseup, wheel, dragstart, drag, dragend, keydown, keyup"
+                "支持的事件: mousemove, mousedown, mouseup, pointercancel, click, dblclick, auxclick, contextmenu, wheel, dragstart, drag, dragend, keydown, keyup, touch*（鼠标和笔通过 pointer_type 区分，触摸输入记为 touch* 事件）"
             </p>
         </div>
     }
 }
 
-// ============================================================================
-// IL 训练数据收集 - 会话和事件管理
-// ============================================================================
-
-#[cfg(feature = "hydrate")]
-#[derive(Clone)]
-pub struct TrackingState {
-    pub session_id: String,
-    pub event_counter: Rc<RefCell<u64>>,
-    pub last_event: Rc<RefCell<Option<(MouseEvent, f64)>>>, // (上一次事件, 上一次时间戳)
-    pub drag_state: Rc<RefCell<Option<String>>>, // 当前拖拽的事件 ID
-}
-
-#[cfg(feature = "hydrate")]
-impl TrackingState {
-    pub fn new() -> Self {
-        let session_id = format!("session_{}", js_sys::Date::now());
-        Self {
-            session_id,
-            event_counter: Rc::new(RefCell::new(0)),
-            last_event: Rc::new(RefCell::new(None)),
-            drag_state: Rc::new(RefCell::new(None)),
-        }
-    }
-
-    pub fn generate_event_id(&self) -> String {
-        let count = *self.event_counter.borrow();
-        *self.event_counter.borrow_mut() += 1;
-        format!("event_{}_{}", self.session_id, count)
-    }
-}
-
-/// 创建防抖发送器和追踪状态
-/// 返回：(事件缓冲区, 防抖发送函数, 追踪状态)
-#[cfg(feature = "hydrate")]
-fn create_tracking_system() -> (Rc<RefCell<Vec<MouseEvent>>>, Rc<impl Fn()>, TrackingState) {
-    let event_buffer = Rc::new(RefCell::new(Vec::new()));
-    let timeout_handle = Rc::new(RefCell::new(None::<i32>));
-    let tracking_state = TrackingState::new();
-
-    let buffer_clone = event_buffer.clone();
-    let handle_clone = timeout_handle.clone();
-
-    let debounced_send = move || {
-        // 清除之前的定时器
-        if let Some(handle) = *handle_clone.borrow() {
-            let window = web_sys::window().expect("Window not available");
-            window.clear_timeout_with_handle(handle);
-        }
-
-        // 设置新的定时器（500ms）
-        let buffer = buffer_clone.clone();
-        let handle = handle_clone.clone();
-        let window = web_sys::window().expect("Window not available");
-
-        let callback = Closure::wrap(Box::new(move || {
-            let events = buffer.borrow_mut().drain(..).collect::<Vec<_>>();
-
-            if !events.is_empty() {
-                web_sys::console::log_1(&format!("Sending {} events after debounce", events.len()).into());
-
-                wasm_bindgen_futures::spawn_local(async move {
-                    if let Err(e) = send_events_batch(&events).await {
-                        web_sys::console::log_1(&format!("Failed to send events: {:?}", e).into());
-                    }
-                });
+/// 知情同意提示：同意前不记录任何事件，同意后可随时撤回
+///
+/// 决定在服务器确认后才生效；提交失败时显示错误和重试按钮。
+#[component]
+fn ConsentBanner(consent: RwSignal<Option<bool>>, withdrawing: RwSignal<bool>, tracker: TrackerHandle) -> impl IntoView {
+    // 服务器端渲染时不会提交决定
+    #[cfg(not(feature = "hydrate"))]
+    let _ = tracker;
+
+    // 解除跨会话关联的结果：None=未操作，Some(true)=成功，Some(false)=失败
+    let unlinked = RwSignal::new(None::<bool>);
+    let unlink = move |_| {
+        #[cfg(feature = "hydrate")]
+        wasm_bindgen_futures::spawn_local(async move {
+            match send_unlink(&tracker.endpoint()).await {
+                Ok(()) => {
+                    reset_participant_token();
+                    unlinked.set(Some(true));
+                }
+                Err(e) => {
+                    web_sys::console::log_1(&format!("Failed to unlink participant: {:?}", e).into());
+                    unlinked.set(Some(false));
+                }
             }
-        }) as Box<dyn FnMut()>);
-
-        let callback_ptr = callback.as_ref().dyn_ref::<Function>().unwrap();
-        let timeout_id = window.set_timeout_with_callback_and_timeout_and_arguments_0(
-            callback_ptr,
-            500,
-        ).expect("Failed to set timeout");
-
-        callback.forget();
-        *handle.borrow_mut() = Some(timeout_id);
+        });
     };
 
-    (event_buffer, Rc::new(debounced_send), tracking_state)
-}
-
-/// 获取目标元素信息
-#[cfg(feature = "hydrate")]
-fn get_target_info(event: &web_sys::Event) -> (Option<String>, Option<String>, Option<String>, Option<String>) {
-    let target = event.target();
-    let mut tag_name = None;
-    let mut id = None;
-    let mut class_name = None;
-    let mut text = None;
-
-    if let Some(target) = target {
-        if let Some(element) = target.dyn_ref::<web_sys::Element>() {
-            tag_name = Some(element.tag_name().to_lowercase());
-            id = element.get_attribute("id");
-            class_name = element.get_attribute("class");
-
-            // 获取文本内容（限制长度）
-            if let Some(html_element) = element.dyn_ref::<web_sys::HtmlElement>() {
-                let txt = html_element.inner_text();
-                // 按字符而非字节截断，避免在中文字符中间切分
-                let truncated: String = txt.chars().take(50).collect();
-                if txt.chars().count() > 50 {
-                    text = Some(format!("{}...", truncated));
-                } else if !txt.is_empty() {
-                    text = Some(txt);
-                }
-            }
+    // 同意时服务器签发的撤回密钥，只保存在本页内存中，之后的决定需要附上
+    #[cfg(feature = "hydrate")]
+    let withdraw_token = StoredValue::new(None::<String>);
+    // 已提交、等待服务器确认的决定
+    let pending = RwSignal::new(None::<bool>);
+    // 提交失败、可以重试的决定
+    let failed = RwSignal::new(None::<bool>);
+    // 服务器已确认撤回并删除了本会话的数据
+    let deleted = RwSignal::new(false);
+
+    let decide = move |granted: bool| {
+        // 撤回立即停止记录，但在服务器确认前不显示为已撤回，数据此时仍在服务器上；
+        // 同意需等服务器确认，否则事件会被拒绝
+        if !granted && consent.get_untracked() == Some(true) {
+            withdrawing.set(true);
         }
-    }
-
-    (tag_name, id, class_name, text)
-}
-
-/// 计算速度和距离
-#[cfg(feature = "hydrate")]
-fn calculate_velocity_and_distance(
-    last_event: &Option<(MouseEvent, f64)>,
-    current_x: i32,
-    current_y: i32,
-    current_time: f64,
-) -> (Option<f64>, Option<f64>, Option<f64>) {
-    if let Some((last, last_time)) = last_event {
-        let dx = (current_x - last.x) as f64;
-        let dy = (current_y - last.y) as f64;
-        let dt = current_time - last_time;
-
-        if dt > 0.0 {
-            let distance = (dx * dx + dy * dy).sqrt();
-            let velocity_x = dx / dt;
-            let velocity_y = dy / dt;
-
-            return (Some(velocity_x), Some(velocity_y), Some(distance));
+        pending.set(Some(granted));
+        failed.set(None);
+
+        #[cfg(feature = "hydrate")]
+        {
+            // 按钮只在取得服务器设置后显示，版本与服务器要求的一致
+            let decision = ConsentDecision {
+                session_id: tracker.session_id(),
+                version: tracker.client_config().unwrap_or_default().consent_version,
+                granted,
+                withdraw_token: withdraw_token.get_value(),
+            };
+            wasm_bindgen_futures::spawn_local(async move {
+                match send_consent(&tracker.endpoint(), &decision).await {
+                    Ok(response) => {
+                        if response.withdraw_token.is_some() {
+                            withdraw_token.set_value(response.withdraw_token);
+                        }
+                        deleted.set(!granted && withdrawing.get_untracked());
+                        consent.set(Some(granted));
+                        withdrawing.set(false);
+                    }
+                    Err(e) => {
+                        web_sys::console::log_1(&format!("Failed to send consent: {:?}", e).into());
+                        failed.set(Some(granted));
+                    }
+                }
+                pending.set(None);
+            });
         }
-    }
-
-    (None, None, None)
-}
-
-/// 创建完整的事件对象
-#[cfg(feature = "hydrate")]
-fn create_mouse_event(
-    event_type: &str,
-    x: i32,
-    y: i32,
-    tracking_state: &TrackingState,
-    extra: Option<std::collections::HashMap<String, String>>,
-    event: &web_sys::Event,
-) -> MouseEvent {
-    let timestamp = js_sys::Date::now();
-    let current_time = timestamp as f64;
-
-    // 生成事件 ID
-    let event_id = tracking_state.generate_event_id();
-
-    // 获取目标元素信息
-    let (target_tag, target_id, target_class, target_text) = get_target_info(event);
-
-    // 安全地获取上一个事件数据（使用 try_borrow 避免冲突）
-    let last_event_data = tracking_state.last_event.try_borrow().ok().and_then(|b| b.as_ref().cloned());
-
-    // 计算速度和距离
-    let (vel_x, vel_y, dist) = calculate_velocity_and_distance(
-        &last_event_data,
-        x,
-        y,
-        current_time,
-    );
-
-    // 获取视口信息
-    let window = web_sys::window().expect("Window not available");
-    let viewport_width = Some(window.inner_width().unwrap().as_f64().unwrap() as u32);
-    let viewport_height = Some(window.inner_height().unwrap().as_f64().unwrap() as u32);
-
-    // 安全地获取拖拽状态
-    let parent_event_id = tracking_state.drag_state.try_borrow().ok().and_then(|b| b.as_ref().cloned());
-
-    // 处理额外信息
-    let mut button = None;
-    let mut buttons = None;
-    let mut scroll_y = None;
-    let mut scroll_x = None;
-    let mut key = None;
-    let mut code = None;
-    let mut ctrl_key = None;
-    let mut shift_key = None;
-    let mut alt_key = None;
-    let mut meta_key = None;
-
-    if let Some(extra) = extra {
-        if let Some(v) = extra.get("button") { button = Some(v.clone()); }
-        if let Some(v) = extra.get("buttons") { buttons = v.parse::<u16>().ok(); }
-        if let Some(v) = extra.get("scroll_y") { scroll_y = v.parse::<f64>().ok(); }
-        if let Some(v) = extra.get("scroll_x") { scroll_x = v.parse::<f64>().ok(); }
-        if let Some(v) = extra.get("key") { key = Some(v.clone()); }
-        if let Some(v) = extra.get("code") { code = Some(v.clone()); }
-        if let Some(v) = extra.get("ctrl_key") { ctrl_key = v.parse::<bool>().ok(); }
-        if let Some(v) = extra.get("shift_key") { shift_key = v.parse::<bool>().ok(); }
-        if let Some(v) = extra.get("alt_key") { alt_key = v.parse::<bool>().ok(); }
-        if let Some(v) = extra.get("meta_key") { meta_key = v.parse::<bool>().ok(); }
-    }
-
-    let mouse_event = MouseEvent {
-        event_type: event_type.to_string(),
-        timestamp: timestamp as u64,
-        x,
-        y,
-        screen_x: Some(x),  // 简化处理，使用 client_x/y
-        screen_y: Some(y),
-        page_x: Some(x),
-        page_y: Some(y),
-        button,
-        buttons,
-        scroll_y,
-        scroll_x,
-        target: None,
-        target_tag,
-        target_id,
-        target_class,
-        target_text,
-        session_id: tracking_state.session_id.clone(),
-        event_id: event_id.clone(),
-        parent_event_id,
-        velocity_x: vel_x,
-        velocity_y: vel_y,
-        distance: dist,
-        key,
-        code,
-        ctrl_key,
-        shift_key,
-        alt_key,
-        meta_key,
-        viewport_width,
-        viewport_height,
-        metadata: None,
     };
 
-    // 尝试更新上一个事件（如果借用失败则跳过）
-    if let Ok(mut last) = tracking_state.last_event.try_borrow_mut() {
-        *last = Some((mouse_event.clone(), current_time));
-    }
-
-    mouse_event
-}
-
-/// 将事件添加到缓冲区并触发防抖
-#[cfg(feature = "hydrate")]
-fn queue_enriched_event(
-    mouse_event: MouseEvent,
-    buffer: &Rc<RefCell<Vec<MouseEvent>>>,
-    debounced_send: &Rc<impl Fn()>,
-) {
-    buffer.borrow_mut().push(mouse_event);
-    debounced_send();
-}
-
-/// 批量发送事件到服务器
-#[cfg(feature = "hydrate")]
-async fn send_events_batch(events: &[MouseEvent]) -> Result<(), JsValue> {
-    let events_json = serde_json::to_string(events).unwrap();
-    send_to_server(&events_json).await
-}
-
-/// 发送 JSON 数据到服务器
-#[cfg(feature = "hydrate")]
-async fn send_to_server(event_json: &str) -> Result<(), JsValue> {
-    let opts = RequestInit::new();
-    opts.set_method("POST");
-    opts.set_mode(RequestMode::Cors);
-
-    let headers = Headers::new()?;
-    headers.append("Content-Type", "application/json")?;
-    opts.set_headers(&headers);
-
-    opts.set_body(&JsValue::from_str(event_json));
-
-    let request = Request::new_with_str_and_init("/api/mouse", &opts)?;
-    let window = web_sys::window().ok_or(JsValue::from_str("Window not available"))?;
-
-    let promise = window.fetch_with_request(&request);
-    let resp_value: JsValue = JsFuture::from(promise).await?;
-    let resp: Response = resp_value.dyn_into()?;
-
-    if resp.ok() {
-        Ok(())
-    } else {
-        Err(JsValue::from_str("Request failed"))
+    view! {
+        <div style="margin: 10px 0; padding: 12px; background: #fff8e1; border: 1px solid #ffe082;">
+            {move || match (pending.get(), failed.get(), consent.get(), tracker.client_config()) {
+                (Some(true), _, _, _) => view! {
+                    <p>"正在提交…"</p>
+                }.into_any(),
+                (Some(false), _, _, _) if withdrawing.get() => view! {
+                    <p>"正在撤回同意并删除本会话的数据…"</p>
+                }.into_any(),
+                (Some(false), _, _, _) => view! {
+                    <p>"正在提交…"</p>
+                }.into_any(),
+                (None, Some(false), _, _) if withdrawing.get() => view! {
+                    <p>"撤回未能提交，本会话已收集的数据尚未删除。记录已暂停。"</p>
+                    <button on:click=move |_| decide(false)>"重试"</button>
+                }.into_any(),
+                (None, Some(granted), _, _) => view! {
+                    <p>"提交失败，您的选择尚未记录。"</p>
+                    <button on:click=move |_| decide(granted)>"重试"</button>
+                }.into_any(),
+                (None, None, None, None) => view! {
+                    <p>"正在加载同意书…"</p>
+                }.into_any(),
+                (None, None, None, Some(config)) => view! {
+                    <p>"此页面会收集您的鼠标和键盘交互数据用于研究目的（同意书版本 " {config.consent_version} "）。"</p>
+                    <button on:click=move |_| decide(true)>"同意"</button>
+                    <button on:click=move |_| decide(false)>"拒绝"</button>
+                }.into_any(),
+                (None, None, Some(true), _) => view! {
+                    <p>"您已同意数据收集。撤回后将停止记录并删除本会话已收集的数据。"</p>
+                    <button on:click=move |_| decide(false)>"撤回同意"</button>
+                    <button on:click=unlink>"解除跨会话关联"</button>
+                    {move || match unlinked.get() {
+                        Some(true) => "已解除关联，之后的会话将使用新的参与者标识。",
+                        Some(false) => "解除关联失败，请稍后重试。",
+                        None => "",
+                    }}
+                }.into_any(),
+                (None, None, Some(false), _) => view! {
+                    <p>{move || match deleted.get() {
+                        true => "已撤回同意，本会话已收集的数据已删除。",
+                        false => "未记录任何数据。",
+                    }}</p>
+                }.into_any(),
+            }}
+        </div>
     }
 }
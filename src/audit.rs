@@ -0,0 +1,247 @@
+//! 审计日志
+//!
+//! 每次读取 API 调用、导出、删除、同意变更和配置加载都在审计日志（JSONL，只追加）中记录一条
+//! [`AuditEntry`]：谁、何时、做了什么、参数和结果计数。`mouse-tracker audit` 按条件查询。
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 一条审计记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// 记录时间（Unix 毫秒）
+    pub at: u64,
+    /// 操作者，如 `http:127.0.0.1`、`cli:alice`、`system:retention`
+    pub actor: String,
+    pub action: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    /// 结果计数，如导出的事件数、删除的事件数
+    #[serde(default)]
+    pub counts: BTreeMap<String, u64>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(at: u64, actor: &str, action: &str) -> Self {
+        Self {
+            at,
+            actor: actor.to_string(),
+            action: action.to_string(),
+            params: BTreeMap::new(),
+            counts: BTreeMap::new(),
+            ok: true,
+            error: None,
+        }
+    }
+
+    pub fn param(mut self, name: &str, value: impl ToString) -> Self {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn count(mut self, name: &str, value: u64) -> Self {
+        self.counts.insert(name.to_string(), value);
+        self
+    }
+
+    /// 记录操作失败
+    pub fn failed(mut self, error: impl ToString) -> Self {
+        self.ok = false;
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// 审计查询条件，未设置的条件不过滤
+#[derive(Debug, Default)]
+pub struct AuditQuery {
+    pub actions: Vec<String>,
+    pub actor: Option<String>,
+    /// 起止时间（Unix 毫秒，含起点不含终点）
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub failed_only: bool,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        (self.actions.is_empty() || self.actions.contains(&entry.action))
+            && self.actor.as_ref().is_none_or(|a| entry.actor.contains(a.as_str()))
+            && self.since.is_none_or(|t| entry.at >= t)
+            && self.until.is_none_or(|t| entry.at < t)
+            && (!self.failed_only || !entry.ok)
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// 追加一条记录
+    ///
+    /// 审计失败不影响被审计的操作本身，只输出到标准错误。
+    pub fn record(&self, entry: &AuditEntry) {
+        if let Err(e) = self.append(entry) {
+            eprintln!("audit: failed to write {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// 记录操作结果：成功时用 `counts` 从结果中补充计数，失败时记录错误
+    pub fn record_result<T, E: std::fmt::Display>(
+        &self,
+        entry: AuditEntry,
+        result: &Result<T, E>,
+        counts: impl FnOnce(AuditEntry, &T) -> AuditEntry,
+    ) {
+        let entry = match result {
+            Ok(value) => counts(entry, value),
+            Err(e) => entry.failed(e),
+        };
+        self.record(&entry);
+    }
+
+    fn append(&self, entry: &AuditEntry) -> Result<(), Box<dyn std::error::Error>> {
+        let _guard = self.lock.lock().map_err(|_| "audit log poisoned")?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// 按条件读取审计记录；文件不存在时返回空列表
+pub fn query(path: &Path, query: &AuditQuery) -> Result<Vec<AuditEntry>, Box<dyn std::error::Error>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry =
+            serde_json::from_str(&line).map_err(|e| format!("{}: line {}: {}", path.display(), i + 1, e))?;
+        if query.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mouse_handler::*;
+    use crate::test_util::{cli, config_in, event, run, temp_dir};
+    use crate::types::{ConsentDecision, CONSENT_VERSION};
+    use axum::extract::{ConnectInfo, Path as UrlPath, State};
+    use axum::http::HeaderMap;
+    use axum::Json;
+    use std::sync::Arc;
+
+    /// 断言上次检查之后恰好追加了一条 `action` 记录
+    fn one_new_record(path: &Path, seen: &mut usize, action: &str) -> AuditEntry {
+        let entries = query(path, &AuditQuery::default()).unwrap();
+        assert_eq!(entries.len(), *seen + 1, "{}", action);
+        *seen += 1;
+        let entry = entries.last().unwrap().clone();
+        assert_eq!(entry.action, action);
+        entry
+    }
+
+    fn decision(session_id: &str, granted: bool, withdraw_token: Option<String>) -> Json<ConsentDecision> {
+        Json(ConsentDecision {
+            session_id: session_id.to_string(),
+            version: CONSENT_VERSION.to_string(),
+            granted,
+            withdraw_token,
+        })
+    }
+
+    #[test]
+    fn every_audited_action_appends_exactly_one_record() {
+        let dir = temp_dir("audit");
+        let config = config_in(&dir);
+        let audit_path = config.audit_path.clone();
+        let log = config.log_path.display().to_string();
+        let state: AppState = Arc::new(ServerState::new(config.clone()).unwrap());
+        let addr = || ConnectInfo(std::net::SocketAddr::from(([127, 0, 0, 1], 3000)));
+        let mut seen = 0;
+
+        run(async {
+            let Json(client) = handle_client_config(State(state.clone()), addr()).await;
+            assert_eq!(client.consent_version, CONSENT_VERSION);
+            one_new_record(&audit_path, &mut seen, "read_client_config");
+
+            let Json(granted) = handle_consent(State(state.clone()), addr(), decision("s1", true, None)).await.unwrap();
+            one_new_record(&audit_path, &mut seen, "consent");
+
+            // 写入事件不审计
+            let events = vec![event("s1", "e1", 1), event("s2", "e2", 2), event("s3", "e3", 3), event("s4", "e4", 4)];
+            handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(events)).await.unwrap();
+            assert_eq!(query(&audit_path, &AuditQuery::default()).unwrap().len(), seen);
+
+            let Json(report) = handle_redaction_report(State(state.clone()), addr(), None).await;
+            assert_eq!(one_new_record(&audit_path, &mut seen, "read_redaction_report").counts["events"], report.events);
+
+            let Json(receipt) = handle_delete_session(State(state.clone()), addr(), None, UrlPath("s2".to_string())).await.unwrap();
+            assert_eq!(receipt.session_id.as_deref(), Some("s2"));
+            let entry = one_new_record(&audit_path, &mut seen, "delete_session");
+            assert_eq!(entry.counts["removed"], 1);
+
+            // 撤回同意同时删除数据，仍只记一条
+            handle_consent(State(state.clone()), addr(), decision("s1", false, Some("guess".to_string()))).await.unwrap_err();
+            assert!(!one_new_record(&audit_path, &mut seen, "consent").ok);
+            let Json(withdrawn) = handle_consent(State(state.clone()), addr(), decision("s1", false, granted.withdraw_token)).await.unwrap();
+            assert!(!withdrawn.granted);
+            let entry = one_new_record(&audit_path, &mut seen, "consent");
+            assert!(entry.ok && entry.counts["removed"] == 1);
+
+            state.audit_config_load("system", "", &dir.join("mouse_tracker.json"));
+            one_new_record(&audit_path, &mut seen, "config_load");
+        });
+
+        let csv = dir.join("out.csv").display().to_string();
+        cli(&config, &["export-csv", &log, &csv]).unwrap();
+        assert_eq!(one_new_record(&audit_path, &mut seen, "export-csv").counts["events"], 2);
+
+        let parquet = dir.join("out.parquet").display().to_string();
+        cli(&config, &["export-parquet", &log, &parquet]).unwrap();
+        one_new_record(&audit_path, &mut seen, "export-parquet");
+
+        let dp = dir.join("dp.json").display().to_string();
+        cli(&config, &["export-dp", &log, &dp, "--epsilon", "0.5"]).unwrap();
+        one_new_record(&audit_path, &mut seen, "export-dp");
+
+        cli(&config, &["delete-session", "s3"]).unwrap();
+        assert_eq!(one_new_record(&audit_path, &mut seen, "delete-session").params["arg0"], "s3");
+
+        cli(&config, &["retention", "--max-age-days", "30"]).unwrap();
+        one_new_record(&audit_path, &mut seen, "retention");
+
+        // 失败的命令同样记录一条
+        let missing = dir.join("missing.jsonl").display().to_string();
+        cli(&config, &["export-csv", &missing, &csv]).unwrap_err();
+        assert!(!one_new_record(&audit_path, &mut seen, "export-csv").ok);
+    }
+}
@@ -0,0 +1,461 @@
+//! 防篡改的哈希链日志
+//!
+//! 开启后每批写入的事件行之后追加一行 `CHAIN1 <seq> <hash>`，
+//! 其中 `hash = SHA256("<上一批 hash> <seq>\n" + 本批存储的原始字节)`，第一批的上一批 hash 为 64 个 0。
+//! 每隔若干批再追加一行 `CKPT1 <seq> <hash> <时间> <hmac>`，用检查点密钥对链头签名。
+//!
+//! 哈希覆盖落盘的字节（加密时即密文），校验不需要解密密钥。
+//! 只改动某一批会使该批的链哈希不符；删除整批会使序号出现缺口；
+//! 改动后重新计算后续链哈希则与之后的签名检查点不符。
+//! 最后一个检查点之后的批没有签名保护，整体截掉无法发现，因此服务器关闭和日志重写时都会补写检查点，
+//! [`verify`] 报告这类批的数量。
+
+use crate::encryption::{load_secret_key, write_secret_key};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+pub const CHAIN_MAGIC: &str = "CHAIN1";
+pub const CHECKPOINT_MAGIC: &str = "CKPT1";
+
+/// 链的起点
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// 该行是否为链记录或检查点（读取事件时跳过）
+pub fn is_chain_line(line: &[u8]) -> bool {
+    [CHAIN_MAGIC, CHECKPOINT_MAGIC].iter().any(|magic| {
+        line.strip_prefix(magic.as_bytes())
+            .is_some_and(|rest| rest.first() == Some(&b' '))
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 链头：最后一批的序号和哈希
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHead {
+    pub seq: u64,
+    pub hash: String,
+}
+
+impl Default for ChainHead {
+    fn default() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS.to_string(),
+        }
+    }
+}
+
+impl ChainHead {
+    /// 从已有日志中找到最后一条链记录；文件不存在时返回起点
+    pub fn scan(path: &Path) -> std::io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut head = Self::default();
+        for line in BufReader::new(file).split(b'\n') {
+            if let Some((seq, hash)) = parse_chain(&line?) {
+                head = Self { seq, hash };
+            }
+        }
+        Ok(head)
+    }
+
+    /// 开始计算下一批的哈希
+    fn hasher(&self) -> Sha256 {
+        Sha256::new().chain_update(format!("{} {}\n", self.hash, self.seq + 1))
+    }
+}
+
+/// 解析 `CHAIN1 <seq> <hash>`
+fn parse_chain(line: &[u8]) -> Option<(u64, String)> {
+    let text = std::str::from_utf8(line).ok()?.trim_end();
+    let mut parts = text.split(' ');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(CHAIN_MAGIC), Some(seq), Some(hash), None) => Some((seq.parse().ok()?, hash.to_string())),
+        _ => None,
+    }
+}
+
+/// 检查点签名密钥（HMAC-SHA256）
+#[derive(Clone)]
+pub struct CheckpointKey(Vec<u8>);
+
+impl CheckpointKey {
+    /// 读取密钥文件（Base64 编码的密钥）
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self(load_secret_key(path)?))
+    }
+
+    /// 生成新密钥并写入 `path`，文件已存在时报错（覆盖会使旧检查点无法校验）
+    pub fn generate(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self(write_secret_key(path, false)?))
+    }
+
+    fn sign(&self, seq: u64, hash: &str, at: u64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        mac.update(format!("{} {} {} {}", CHECKPOINT_MAGIC, seq, hash, at).as_bytes());
+        hex(&mac.finalize().into_bytes())
+    }
+}
+
+/// 链式写入的设置
+#[derive(Clone)]
+pub struct ChainOptions {
+    pub key: CheckpointKey,
+    /// 每隔多少批写一个检查点
+    pub checkpoint_every: u64,
+}
+
+/// 计算写入字节的哈希，在每批结束时追加链记录
+///
+/// 没有链头时只透传写入。
+pub struct ChainWriter<W: Write> {
+    inner: W,
+    head: Option<ChainHead>,
+    hasher: Sha256,
+    dirty: bool,
+}
+
+impl<W: Write> ChainWriter<W> {
+    pub fn new(inner: W, head: Option<ChainHead>) -> Self {
+        let hasher = head.as_ref().map(ChainHead::hasher).unwrap_or_default();
+        Self {
+            inner,
+            head,
+            hasher,
+            dirty: false,
+        }
+    }
+
+    /// 结束当前批：追加链记录，序号到达间隔时再追加检查点
+    pub fn end_batch(&mut self, options: &ChainOptions, now: u64) -> std::io::Result<()> {
+        let Some(head) = self.head.as_mut() else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let hasher = std::mem::take(&mut self.hasher);
+        *head = ChainHead {
+            seq: head.seq + 1,
+            hash: hex(&hasher.finalize()),
+        };
+        writeln!(self.inner, "{} {} {}", CHAIN_MAGIC, head.seq, head.hash)?;
+        self.hasher = head.hasher();
+        self.dirty = false;
+
+        if options.checkpoint_every > 0 && head.seq % options.checkpoint_every == 0 {
+            self.checkpoint(options, now)?;
+        }
+        Ok(())
+    }
+
+    /// 追加一个签名检查点
+    pub fn checkpoint(&mut self, options: &ChainOptions, now: u64) -> std::io::Result<()> {
+        if let Some(head) = &self.head {
+            let mac = options.key.sign(head.seq, &head.hash, now);
+            writeln!(self.inner, "{} {} {} {} {}", CHECKPOINT_MAGIC, head.seq, head.hash, now, mac)?;
+        }
+        Ok(())
+    }
+
+    /// 返回底层写入器和最新链头
+    pub fn into_inner(self) -> (W, Option<ChainHead>) {
+        (self.inner, self.head)
+    }
+}
+
+impl<W: Write> Write for ChainWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if self.head.is_some() && n > 0 {
+            self.hasher.update(&buf[..n]);
+            self.dirty = true;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 校验发现的问题类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// 某一批的内容与链哈希不符（行被修改、插入或删除）
+    Modified,
+    /// 链序号出现缺口（整批被删除）
+    Missing,
+    /// 链序号倒退或重复（插入或重排了整批）
+    Inserted,
+    /// 链头与签名检查点不符（之前的数据被修改并重新计算了链）
+    CheckpointMismatch,
+    /// 检查点签名无效
+    BadSignature,
+    /// 无法解析的链记录或检查点
+    Malformed,
+    /// 最后一条链记录之后的行（写入中断或事后追加）
+    Unchained,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// 涉及的行范围（从 1 开始，含两端）
+    pub first_line: usize,
+    pub last_line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "lines {}-{}: {:?}: {}",
+            self.first_line, self.last_line, self.kind, self.message
+        )
+    }
+}
+
+/// 校验结果
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub batches: u64,
+    pub checkpoints: u64,
+    /// 最后一个签名有效且与链一致的检查点：(序号, 时间)
+    pub last_checkpoint: Option<(u64, u64)>,
+    /// 最后一个有效检查点之后的批数；这些批被整体截掉时无法发现（没有密钥时为全部批数）
+    pub unsigned_batches: u64,
+    pub problems: Vec<Problem>,
+}
+
+/// 校验哈希链日志
+///
+/// 没有检查点密钥时只校验链本身，不校验签名。
+pub fn verify(path: &Path, key: Option<&CheckpointKey>) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut report = VerifyReport::default();
+
+    let mut head = ChainHead::default();
+    let mut hasher = head.hasher();
+    // 当前批的首行，没有待校验的行时为 None
+    let mut batch_start: Option<usize> = None;
+    let mut line_no = 0;
+
+    loop {
+        let mut buf = Vec::new();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line_no += 1;
+        let text = String::from_utf8_lossy(&buf);
+        let problem = |kind, first_line, message: String| Problem {
+            kind,
+            first_line,
+            last_line: line_no,
+            message,
+        };
+
+        if text.starts_with(&format!("{} ", CHAIN_MAGIC)) {
+            let first = batch_start.take().unwrap_or(line_no);
+            let Some((seq, hash)) = parse_chain(&buf) else {
+                report.problems.push(problem(ProblemKind::Malformed, line_no, "malformed chain record".to_string()));
+                continue;
+            };
+            let expected = head.seq + 1;
+
+            if seq > expected {
+                let missing = if seq - 1 == expected {
+                    format!("batch {} is missing", expected)
+                } else {
+                    format!("batches {}-{} are missing", expected, seq - 1)
+                };
+                report.problems.push(problem(ProblemKind::Missing, first, missing));
+            } else if seq < expected {
+                report.problems.push(problem(
+                    ProblemKind::Inserted,
+                    first,
+                    format!("batch {} follows batch {}", seq, head.seq),
+                ));
+            } else if hex(&std::mem::take(&mut hasher).finalize()) != hash {
+                report.problems.push(problem(ProblemKind::Modified, first, format!("batch {} does not match its hash", seq)));
+            }
+
+            // 以存储的链记录为准继续，后续的批各自独立校验
+            report.batches += 1;
+            report.unsigned_batches += 1;
+            head = ChainHead { seq, hash };
+            hasher = head.hasher();
+        } else if text.starts_with(&format!("{} ", CHECKPOINT_MAGIC)) {
+            let parts: Vec<&str> = text.trim_end().split(' ').collect();
+            let parsed = match parts.as_slice() {
+                [_, seq, hash, at, mac] => seq
+                    .parse::<u64>()
+                    .ok()
+                    .zip(at.parse::<u64>().ok())
+                    .map(|(seq, at)| (seq, hash.to_string(), at, mac.to_string())),
+                _ => None,
+            };
+            let Some((seq, hash, at, mac)) = parsed else {
+                report.problems.push(problem(ProblemKind::Malformed, line_no, "malformed checkpoint".to_string()));
+                continue;
+            };
+            report.checkpoints += 1;
+
+            let signed = match key {
+                Some(key) if key.sign(seq, &hash, at) != mac => {
+                    report.problems.push(problem(ProblemKind::BadSignature, line_no, format!("checkpoint {} has an invalid signature", seq)));
+                    false
+                }
+                Some(_) => true,
+                None => false,
+            };
+            if seq != head.seq || hash != head.hash {
+                report.problems.push(problem(
+                    ProblemKind::CheckpointMismatch,
+                    line_no,
+                    format!("checkpoint {} does not match the chain (head is batch {})", seq, head.seq),
+                ));
+            } else if signed {
+                report.last_checkpoint = Some((seq, at));
+                report.unsigned_batches = 0;
+            }
+        } else {
+            if batch_start.is_none() {
+                batch_start = Some(line_no);
+            }
+            hasher.update(&buf);
+        }
+    }
+
+    if let Some(first) = batch_start {
+        report.problems.push(Problem {
+            kind: ProblemKind::Unchained,
+            first_line: first,
+            last_line: line_no,
+            message: "lines after the last chain record".to_string(),
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    /// 写 `batches` 批（每批两行），返回日志路径和签名设置
+    fn chained_log(name: &str, batches: u64, checkpoint_every: u64) -> (std::path::PathBuf, ChainOptions) {
+        let dir = temp_dir(name);
+        let options = ChainOptions {
+            key: CheckpointKey::generate(&dir.join("checkpoint.key")).unwrap(),
+            checkpoint_every,
+        };
+        let mut writer = ChainWriter::new(Vec::new(), Some(ChainHead::default()));
+        for batch in 1..=batches {
+            writeln!(writer, "event {batch}a\nevent {batch}b").unwrap();
+            writer.end_batch(&options, 1000 + batch).unwrap();
+        }
+        let path = dir.join("events.jsonl");
+        std::fs::write(&path, writer.into_inner().0).unwrap();
+        (path, options)
+    }
+
+    fn edit(path: &Path, f: impl FnOnce(&mut Vec<String>)) {
+        let mut lines: Vec<String> = std::fs::read_to_string(path).unwrap().lines().map(String::from).collect();
+        f(&mut lines);
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    fn kinds(report: &VerifyReport) -> Vec<ProblemKind> {
+        report.problems.iter().map(|p| p.kind).collect()
+    }
+
+    #[test]
+    fn untouched_log_verifies() {
+        let (path, options) = chained_log("chain-ok", 5, 2);
+        let report = verify(&path, Some(&options.key)).unwrap();
+        assert!(report.problems.is_empty(), "{:?}", report.problems);
+        assert_eq!((report.batches, report.checkpoints), (5, 2));
+        assert_eq!(report.last_checkpoint, Some((4, 1004)));
+        assert_eq!(report.unsigned_batches, 1);
+        assert_eq!(ChainHead::scan(&path).unwrap().seq, 5);
+    }
+
+    #[test]
+    fn modified_line_breaks_its_batch() {
+        let (path, options) = chained_log("chain-modified", 3, 0);
+        // 第 2 批：行 4-5，链记录在行 6
+        edit(&path, |lines| lines[3] = "event 2x".to_string());
+        let report = verify(&path, Some(&options.key)).unwrap();
+        assert_eq!(kinds(&report), [ProblemKind::Modified]);
+        assert_eq!((report.problems[0].first_line, report.problems[0].last_line), (4, 6));
+    }
+
+    #[test]
+    fn deleted_batch_leaves_a_gap() {
+        let (path, options) = chained_log("chain-missing", 3, 0);
+        edit(&path, |lines| {
+            lines.drain(3..6);
+        });
+        let report = verify(&path, Some(&options.key)).unwrap();
+        assert_eq!(kinds(&report), [ProblemKind::Missing]);
+        assert!(report.problems[0].message.contains("batch 2"));
+    }
+
+    #[test]
+    fn rechained_edit_is_caught_by_the_checkpoint() {
+        let (path, options) = chained_log("chain-rechained", 2, 2);
+        // 改动第 1 批后重新计算整条链，但没有密钥无法重签检查点
+        let unsigned = ChainOptions { checkpoint_every: 0, ..options.clone() };
+        let mut writer = ChainWriter::new(Vec::new(), Some(ChainHead::default()));
+        writeln!(writer, "event 1x\nevent 1b").unwrap();
+        writer.end_batch(&unsigned, 0).unwrap();
+        writeln!(writer, "event 2a\nevent 2b").unwrap();
+        writer.end_batch(&unsigned, 0).unwrap();
+        let forged: Vec<String> = String::from_utf8(writer.into_inner().0).unwrap().lines().map(String::from).collect();
+        edit(&path, |lines| lines[..6].clone_from_slice(&forged));
+
+        let report = verify(&path, Some(&options.key)).unwrap();
+        assert_eq!(kinds(&report), [ProblemKind::CheckpointMismatch]);
+        assert_eq!(report.last_checkpoint, None);
+        assert_eq!(report.unsigned_batches, 2);
+    }
+
+    #[test]
+    fn checkpoint_signed_with_another_key_is_rejected() {
+        let (path, _) = chained_log("chain-bad-signature", 2, 2);
+        let other = CheckpointKey::generate(&temp_dir("chain-other-key").join("checkpoint.key")).unwrap();
+        let report = verify(&path, Some(&other)).unwrap();
+        assert_eq!(kinds(&report), [ProblemKind::BadSignature]);
+        assert_eq!(report.unsigned_batches, 2);
+
+        // 没有密钥时不校验签名，所有批都视为未签名
+        let report = verify(&path, None).unwrap();
+        assert!(report.problems.is_empty());
+        assert_eq!((report.last_checkpoint, report.unsigned_batches), (None, 2));
+    }
+
+    #[test]
+    fn trailing_lines_are_unchained() {
+        let (path, options) = chained_log("chain-unchained", 1, 1);
+        edit(&path, |lines| lines.push("event appended".to_string()));
+        let report = verify(&path, Some(&options.key)).unwrap();
+        assert_eq!(kinds(&report), [ProblemKind::Unchained]);
+        assert_eq!(report.unsigned_batches, 0);
+    }
+}
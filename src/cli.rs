@@ -0,0 +1,566 @@
+//! 命令行工具
+//!
+//! 不带参数运行时启动服务器；带子命令时执行对应的离线数据处理任务。
+
+use crate::audit::{query as query_audit, AuditEntry, AuditLog, AuditQuery};
+use crate::chain::{verify, CheckpointKey};
+use crate::config::ServerConfig;
+use crate::encryption::{rotate_key, write_secret_key, Keyring, SegmentWriter};
+use crate::export::aggregate::{export_aggregates, AggregateExportOptions, BudgetLedger, Mechanism, PrivacyBudget};
+use crate::export::csv::{export_csv, CsvExportOptions};
+use crate::export::parquet::{export_parquet, ParquetExportOptions};
+use crate::export::{parse_utc_date, utc_date, COLUMNS};
+use crate::log_reader::{repair, LogReader};
+use crate::merge::merge_logs;
+use crate::mouse_handler::{now_ms, ServerState};
+use crate::projects::{generate_token, token_hash};
+use crate::redaction::{RedactionPolicy, Redactor};
+use crate::retention::{apply_retention, delete_session, unlink_participant, DeletionReceipt};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+用法: mouse-tracker [子命令]
+
+不带子命令时启动服务器。配置文件路径由 MOUSE_TRACKER_CONFIG 指定（默认 mouse_tracker.json）。
+读取日志的子命令都接受 --key-file <路径> 解密加密段，未指定时使用配置文件中的 encryption.key_file；
+写出日志的子命令在有密钥时用当前密钥加密输出。
+使用配置文件的子命令都接受 --project <名称>，改用该项目的配置（路径相对于项目的存储根目录）。
+
+子命令:
+  schema                          输出导出列定义
+  export-parquet <输入> <输出>    导出为 Parquet
+      --columns a,b,c             只导出指定列
+      --row-group-size N          每个 row group 的行数（默认 65536）
+      --partition none|session|date
+                                  分区方式（分区时 <输出> 为目录）
+      --max-open N                同时打开的分区文件上限（默认 64）
+  export-csv <输入> <输出>        导出为 CSV
+      --columns a,b,c             只导出指定基础列
+      --derived session_time,normalized,one_hot
+                                  追加派生列
+  export-dp <输入> <输出>         导出加差分隐私噪声的聚合结果（热力图、事件速率直方图、会话数，JSON）
+      --epsilon E                 本次花费的 ε（必填），从输入数据的预算中扣除
+      --mechanism laplace|gaussian
+                                  噪声机制（默认 laplace）
+      --delta D                   本次花费的 δ（gaussian 必填）
+      --dataset <名称>            发布结果中的数据集名称（默认为输入文件名）；预算按输入文件的路径和内容记账
+      --grid N                    热力图每边格数（默认 32）
+      --max-events N              每个会话计入热力图的最大事件数（默认 1000）
+      --rate-edges a,b,c          事件速率（事件/秒）分箱边界
+  privacy-budget                  列出每份输入数据已花费的隐私预算
+  check <输入>                    列出日志中无法解析的行
+      --limit N                   最多显示 N 条（默认 20）
+  repair <输入> <输出>            将好记录复制到 <输出>，坏记录写入隔离文件
+      --quarantine <路径>         隔离文件（默认 <输出>.quarantine.jsonl）
+  redact <输入> <输出>            按脱敏规则处理已有日志并输出统计
+      --policy <路径>             规则文件（默认取配置文件中的 redaction）
+  delete-session <会话 ID>         删除会话的全部数据并写入删除回执
+      --reason <原因>
+  unlink-participant <假名>        清除已存储事件中的参与者假名
+  retention                       删除超过保留期限的事件
+      --max-age-days N            保留天数（默认取配置文件中的 retention.max_age_days）
+
+  注意：服务器运行时请改用 DELETE /api/sessions/{id}（项目为 /api/projects/<名称>/sessions/{id}），
+  避免与写入冲突。
+
+  merge --output <输出> <输入>... 合并多个有序日志，会话内按 event_id 去重
+      --conflicts <路径>          冲突报告（默认 <输出>.conflicts.jsonl）
+  keygen <密钥文件>               生成新密钥并设为当前密钥（文件不存在时创建）
+      --key-id <ID>               密钥 ID（默认为当天日期）
+  checkpoint-key <密钥文件>       生成哈希链检查点的签名密钥
+  pseudonym-key <密钥文件>        生成参与者假名密钥；覆盖已有文件即重置所有假名
+  read-token <名称>               生成读取接口的访问令牌，输出令牌和写入 read_tokens 的哈希
+  verify <输入>                   校验哈希链，定位被修改、插入或删除的记录
+      --checkpoint-key <路径>     签名密钥（默认取配置文件中的 integrity.key_file）
+  audit                           查询审计日志（除 schema、help 外的子命令执行也会被记录）
+      --action a,b                只显示指定操作
+      --actor <文本>              只显示操作者包含该文本的记录
+      --since / --until <时间>    时间范围（Unix 毫秒或 YYYY-MM-DD，UTC）
+      --failed true               只显示失败的操作
+      --limit N                   最多显示最近 N 条（默认 50）
+";
+
+/// 解析后的命令行参数：位置参数 + `--name value` 形式的选项
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            if let Some(name) = arg.strip_prefix("--") {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("missing value for --{}", name))?;
+                options.insert(name.to_string(), value.clone());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+
+        Ok(Self { positional, options })
+    }
+
+    fn path(&self, index: usize, name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        self.positional
+            .get(index)
+            .map(PathBuf::from)
+            .ok_or_else(|| format!("missing argument <{}>\n\n{}", name, USAGE).into())
+    }
+
+    fn list(&self, name: &str) -> Vec<String> {
+        self.options
+            .get(name)
+            .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    fn parse_option<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, Box<dyn std::error::Error>>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.options
+            .get(name)
+            .map(|v| v.parse::<T>().map_err(|e| format!("invalid --{}: {}", name, e).into()))
+            .transpose()
+    }
+}
+
+/// 服务器配置；指定 `--project` 时为该项目的配置
+fn server_config(args: &Args) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let config = ServerConfig::load()?;
+    match args.options.get("project") {
+        Some(project) => config.project(project),
+        None => Ok(config),
+    }
+}
+
+/// 读取 `--key-file` 指定的密钥；未指定时使用配置文件中的 `encryption.key_file`
+fn keyring(args: &Args) -> Result<Option<Keyring>, Box<dyn std::error::Error>> {
+    let path = match args.options.get("key-file") {
+        Some(path) => PathBuf::from(path),
+        None => match server_config(args)?.encryption {
+            Some(encryption) => encryption.key_file,
+            None => return Ok(None),
+        },
+    };
+    Keyring::load(&path).map(Some)
+}
+
+/// 提示有未经检查就原样保留的行，其中可能仍有目标数据
+fn warn_unchecked(receipt: &DeletionReceipt) {
+    if receipt.unchecked > 0 {
+        eprintln!(
+            "warning: {} unparseable lines were kept unchecked",
+            receipt.unchecked
+        );
+    }
+}
+
+/// 执行子命令
+///
+/// 除 `schema` 和 `help` 外，每次执行（包括失败）都写入审计日志。
+pub async fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    let args = Args::parse(rest)?;
+    if matches!(command.as_str(), "schema" | "help" | "--help" | "-h") {
+        return execute(command, &args, &mut BTreeMap::new()).await;
+    }
+
+    let audit = AuditLog::new(server_config(&args)?.audit_path);
+    let allowed = audit_params(command);
+    let recorded = |name: &str| COMMON_AUDIT_PARAMS.contains(&name) || allowed.contains(&name);
+    let mut entry = AuditEntry::new(now_ms(), &cli_actor(), command);
+    for (i, value) in args.positional.iter().enumerate() {
+        let name = format!("arg{}", i);
+        if allowed.contains(&"arg*") || recorded(&name) {
+            entry = entry.param(&name, value);
+        }
+    }
+    for (name, value) in &args.options {
+        if recorded(name) {
+            entry = entry.param(name, value);
+        }
+    }
+
+    let mut counts = BTreeMap::new();
+    let result = execute(command, &args, &mut counts).await;
+    audit.record_result(entry, &result, |entry, _| {
+        counts.into_iter().fold(entry, |entry, (name, value)| entry.count(name, value))
+    });
+    result
+}
+
+/// 所有子命令都写入审计日志的选项
+const COMMON_AUDIT_PARAMS: &[&str] = &["project", "key-file"];
+
+/// 子命令额外写入审计日志的参数，位置参数写作 `arg<序号>`，`arg*` 表示全部位置参数
+///
+/// 未列出的参数不记录，例如 `unlink-participant` 的假名。
+fn audit_params(command: &str) -> &'static [&'static str] {
+    match command {
+        "export-parquet" => &["arg0", "arg1", "columns", "row-group-size", "partition", "max-open"],
+        "export-csv" => &["arg0", "arg1", "columns", "derived"],
+        "export-dp" => &["arg0", "arg1", "epsilon", "mechanism", "delta", "dataset", "grid", "max-events", "rate-edges"],
+        "check" => &["arg0", "limit"],
+        "repair" => &["arg0", "arg1", "quarantine"],
+        "redact" => &["arg0", "arg1", "policy"],
+        "delete-session" => &["arg0", "reason"],
+        "retention" => &["max-age-days"],
+        "merge" => &["arg*", "output", "conflicts"],
+        "keygen" => &["arg0", "key-id"],
+        "checkpoint-key" | "pseudonym-key" | "read-token" => &["arg0"],
+        "verify" => &["arg0", "checkpoint-key"],
+        "audit" => &["action", "actor", "since", "until", "failed", "limit"],
+        _ => &[],
+    }
+}
+
+/// 命令行的操作者（审计用）
+fn cli_actor() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("cli:{}", user)
+}
+
+/// 执行一个子命令，把结果计数写入 `counts`
+async fn execute(
+    command: &str,
+    args: &Args,
+    counts: &mut BTreeMap<&'static str, u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        "schema" => {
+            for column in COLUMNS {
+                let null = if column.nullable { "nullable" } else { "required" };
+                println!("{:<18} {:<8} {:<9} {}", column.name, column.type_name(), null, column.doc);
+            }
+        }
+        "export-parquet" => {
+            let mut options = ParquetExportOptions {
+                columns: args.list("columns"),
+                keyring: keyring(args)?,
+                ..Default::default()
+            };
+            if let Some(size) = args.parse_option("row-group-size")? {
+                options.row_group_size = size;
+            }
+            if let Some(partition) = args.parse_option("partition")? {
+                options.partition_by = partition;
+            }
+            if let Some(max_open) = args.parse_option("max-open")? {
+                options.max_open_partitions = max_open;
+            }
+
+            let summary = export_parquet(&args.path(0, "输入")?, &args.path(1, "输出")?, &options)?;
+            println!("exported {} events to {} file(s)", summary.events, summary.files.len());
+            counts.insert("events", summary.events as u64);
+        }
+        "export-csv" => {
+            let mut options = CsvExportOptions {
+                columns: args.list("columns"),
+                keyring: keyring(args)?,
+                ..Default::default()
+            };
+            for derived in args.list("derived") {
+                match derived.as_str() {
+                    "session_time" => options.session_time = true,
+                    "normalized" => options.normalized = true,
+                    "one_hot" => options.one_hot = true,
+                    other => return Err(format!("unknown derived column: {}", other).into()),
+                }
+            }
+
+            let count = export_csv(&args.path(0, "输入")?, &args.path(1, "输出")?, &options)?;
+            println!("exported {} events", count);
+            counts.insert("events", count as u64);
+        }
+        "export-dp" => {
+            let input = args.path(0, "输入")?;
+            let config = server_config(args)?;
+            let mechanism = args.parse_option::<Mechanism>("mechanism")?.unwrap_or(Mechanism::Laplace);
+            let defaults = AggregateExportOptions::default();
+            let rate_edges = args
+                .list("rate-edges")
+                .iter()
+                .map(|edge| edge.parse::<f64>().map_err(|e| format!("invalid --rate-edges: {}", e)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let options = AggregateExportOptions {
+                dataset: match args.options.get("dataset") {
+                    Some(dataset) => dataset.clone(),
+                    None => input.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(),
+                },
+                mechanism,
+                epsilon: args.parse_option("epsilon")?.ok_or("missing --epsilon")?,
+                delta: match mechanism {
+                    Mechanism::Laplace => 0.0,
+                    Mechanism::Gaussian => args.parse_option("delta")?.ok_or("missing --delta")?,
+                },
+                grid: args.parse_option("grid")?.unwrap_or(defaults.grid),
+                max_events_per_session: args
+                    .parse_option("max-events")?
+                    .unwrap_or(defaults.max_events_per_session),
+                rate_edges: if rate_edges.is_empty() { defaults.rate_edges } else { rate_edges },
+                keyring: keyring(args)?,
+            };
+            let budget = PrivacyBudget {
+                epsilon: config.privacy.epsilon_budget,
+                delta: config.privacy.delta_budget,
+            };
+
+            let release = export_aggregates(
+                &input,
+                &args.path(1, "输出")?,
+                &options,
+                &BudgetLedger::new(config.privacy.ledger_path),
+                &budget,
+            )?;
+            println!("released aggregates of dataset {:?} (epsilon={}, delta={})", release.dataset, release.epsilon, release.delta);
+            counts.insert("sessions", release.sessions);
+        }
+        "privacy-budget" => {
+            let config = server_config(args)?;
+            for spent in BudgetLedger::new(config.privacy.ledger_path).spent()? {
+                println!(
+                    "{} ({}): {} releases, epsilon {}/{}, delta {}/{}",
+                    spent.source,
+                    spent.dataset,
+                    spent.releases,
+                    spent.epsilon,
+                    config.privacy.epsilon_budget,
+                    spent.delta,
+                    config.privacy.delta_budget
+                );
+            }
+        }
+        "check" => {
+            let limit = args.parse_option::<usize>("limit")?.unwrap_or(20);
+            let (mut good, mut bad) = (0usize, 0usize);
+            for item in LogReader::open(&args.path(0, "输入")?)?.with_keyring(keyring(args)?.as_ref()) {
+                match item {
+                    Ok(_) => good += 1,
+                    Err(diagnostic) => {
+                        if bad < limit {
+                            println!("{}", diagnostic);
+                        }
+                        bad += 1;
+                    }
+                }
+            }
+            println!("{} good records, {} bad lines", good, bad);
+            counts.insert("good", good as u64);
+            counts.insert("bad", bad as u64);
+        }
+        "repair" => {
+            let output = args.path(1, "输出")?;
+            let quarantine = args
+                .options
+                .get("quarantine")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| output.with_extension("quarantine.jsonl"));
+
+            let summary = repair(&args.path(0, "输入")?, &output, &quarantine, keyring(args)?.as_ref())?;
+            println!("{} good records copied to {}", summary.good, output.display());
+            println!("{} bad lines quarantined to {}", summary.bad, quarantine.display());
+            counts.insert("good", summary.good as u64);
+            counts.insert("bad", summary.bad as u64);
+            for (kind, count) in &summary.by_kind {
+                println!("  {:?}: {}", kind, count);
+            }
+        }
+        "redact" => {
+            let policy: RedactionPolicy = match args.options.get("policy") {
+                Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+                None => server_config(args)?.redaction,
+            };
+            let redactor = Redactor::new(&policy)?;
+            let keyring = keyring(args)?;
+
+            let file = std::io::BufWriter::new(std::fs::File::create(args.path(1, "输出")?)?);
+            let mut out = SegmentWriter::new(file, keyring.as_ref());
+            let mut report = crate::redaction::RedactionReport::default();
+            for event in crate::export::read_events(&args.path(0, "输入")?, keyring.as_ref())? {
+                let mut event = event?;
+                redactor.apply(&mut event, &mut report);
+                out.write_line(serde_json::to_string(&event)?.as_bytes())?;
+            }
+            out.finish()?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            counts.insert("events", report.events);
+        }
+        "delete-session" => {
+            let session_id = args.positional.first().ok_or_else(|| format!("missing argument <会话 ID>\n\n{}", USAGE))?;
+            let reason = args.options.get("reason").map(String::as_str).unwrap_or("cli request");
+            let state = ServerState::new(server_config(args)?)?;
+
+            let receipt = delete_session(&state, session_id, reason).await?;
+            println!("removed {} events, receipt {}", receipt.total_removed(), receipt.receipt_id);
+            warn_unchecked(&receipt);
+            counts.insert("removed", receipt.total_removed() as u64);
+            counts.insert("unchecked", receipt.unchecked as u64);
+        }
+        "unlink-participant" => {
+            let participant_id = args.positional.first().ok_or_else(|| format!("missing argument <假名>\n\n{}", USAGE))?;
+            let state = ServerState::new(server_config(args)?)?;
+
+            let receipt = unlink_participant(&state, participant_id, "cli request").await?;
+            println!("unlinked {} events, receipt {}", receipt.unlinked, receipt.receipt_id);
+            warn_unchecked(&receipt);
+            counts.insert("unlinked", receipt.unlinked as u64);
+            counts.insert("unchecked", receipt.unchecked as u64);
+        }
+        "retention" => {
+            let config = server_config(args)?;
+            let max_age_days = match args.parse_option::<u64>("max-age-days")? {
+                Some(days) => days,
+                None => config
+                    .retention
+                    .as_ref()
+                    .map(|r| r.max_age_days)
+                    .ok_or("no --max-age-days given and no retention configured")?,
+            };
+            let state = ServerState::new(config)?;
+
+            match apply_retention(&state, max_age_days).await? {
+                Some(receipt) => {
+                    println!("removed {} events, receipt {}", receipt.total_removed(), receipt.receipt_id);
+                    warn_unchecked(&receipt);
+                    counts.insert("removed", receipt.total_removed() as u64);
+                    counts.insert("unchecked", receipt.unchecked as u64);
+                }
+                None => println!("nothing older than {} days", max_age_days),
+            }
+        }
+        "merge" => {
+            let output = args
+                .options
+                .get("output")
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("missing --output\n\n{}", USAGE))?;
+            let conflicts = args
+                .options
+                .get("conflicts")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| output.with_extension("conflicts.jsonl"));
+            let inputs: Vec<PathBuf> = args.positional.iter().map(PathBuf::from).collect();
+            if inputs.is_empty() {
+                return Err(format!("no input files\n\n{}", USAGE).into());
+            }
+
+            let summary = merge_logs(&inputs, &output, &conflicts, keyring(args)?.as_ref())?;
+            println!(
+                "read {} events, wrote {}, dropped {} duplicates",
+                summary.read, summary.written, summary.duplicates
+            );
+            counts.insert("read", summary.read as u64);
+            counts.insert("written", summary.written as u64);
+            if summary.conflicts > 0 {
+                println!("{} conflicting records written to {}", summary.conflicts, conflicts.display());
+            }
+        }
+        "keygen" => {
+            let path = args.path(0, "密钥文件")?;
+            let key_id = args
+                .options
+                .get("key-id")
+                .cloned()
+                .unwrap_or_else(|| utc_date(now_ms()));
+
+            rotate_key(&path, &key_id)?;
+            println!("active key is now {} in {}", key_id, path.display());
+        }
+        "checkpoint-key" => {
+            let path = args.path(0, "密钥文件")?;
+            CheckpointKey::generate(&path)?;
+            println!("checkpoint key written to {}", path.display());
+        }
+        "pseudonym-key" => {
+            let path = args.path(0, "密钥文件")?;
+            let existed = path.exists();
+            write_secret_key(&path, true)?;
+            if existed {
+                println!("pseudonym key in {} replaced; new participant IDs cannot be linked to old ones", path.display());
+            } else {
+                println!("pseudonym key written to {}", path.display());
+            }
+        }
+        "read-token" => {
+            let name = args.positional.first().ok_or_else(|| format!("missing argument <名称>\n\n{}", USAGE))?;
+            let token = generate_token();
+            println!("token: {}", token);
+            println!("config: \"read_tokens\": {{ \"{}\": \"{}\" }}", name, token_hash(&token));
+        }
+        "verify" => {
+            let key = match args.options.get("checkpoint-key") {
+                Some(path) => Some(CheckpointKey::load(&PathBuf::from(path))?),
+                None => match server_config(args)?.integrity {
+                    Some(integrity) => Some(CheckpointKey::load(&integrity.key_file)?),
+                    None => None,
+                },
+            };
+            if key.is_none() {
+                println!("no checkpoint key given, signatures are not checked");
+            }
+
+            let report = verify(&args.path(0, "输入")?, key.as_ref())?;
+            for problem in &report.problems {
+                println!("{}", problem);
+            }
+            println!("{} batches, {} checkpoints", report.batches, report.checkpoints);
+            counts.insert("batches", report.batches);
+            counts.insert("problems", report.problems.len() as u64);
+            if let Some((seq, at)) = report.last_checkpoint {
+                println!("last valid checkpoint: batch {} at {}", seq, at);
+            }
+            if report.unsigned_batches > 0 {
+                println!(
+                    "{} batches after the last valid checkpoint are not signed; truncating them would go undetected",
+                    report.unsigned_batches
+                );
+            }
+            counts.insert("unsigned_batches", report.unsigned_batches);
+            if !report.problems.is_empty() {
+                return Err(format!("{} problem(s) found", report.problems.len()).into());
+            }
+        }
+        "audit" => {
+            let parse_time = |name: &str| -> Result<Option<u64>, Box<dyn std::error::Error>> {
+                args.options
+                    .get(name)
+                    .map(|v| {
+                        v.parse::<u64>()
+                            .ok()
+                            .or_else(|| parse_utc_date(v))
+                            .ok_or_else(|| format!("invalid --{}: {}", name, v).into())
+                    })
+                    .transpose()
+            };
+            let query = AuditQuery {
+                actions: args.list("action"),
+                actor: args.options.get("actor").cloned(),
+                since: parse_time("since")?,
+                until: parse_time("until")?,
+                failed_only: args.parse_option::<bool>("failed")?.unwrap_or(false),
+            };
+            let limit = args.parse_option::<usize>("limit")?.unwrap_or(50);
+
+            let entries = query_audit(&server_config(args)?.audit_path, &query)?;
+            for entry in &entries[entries.len().saturating_sub(limit)..] {
+                println!("{}", serde_json::to_string(entry)?);
+            }
+            counts.insert("entries", entries.len() as u64);
+        }
+        "help" | "--help" | "-h" => print!("{}", USAGE),
+        other => return Err(format!("unknown command: {}\n\n{}", other, USAGE).into()),
+    }
+
+    Ok(())
+}
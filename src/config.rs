@@ -0,0 +1,248 @@
+//! 服务器配置
+//!
+//! 从 JSON 文件读取，路径由环境变量 `MOUSE_TRACKER_CONFIG` 指定，默认为 `mouse_tracker.json`。
+//! 文件不存在时使用默认配置，所有字段均可省略。
+//!
+//! `projects` 中的每个项目有自己的一份配置（格式相同），其中的相对路径相对于项目的存储根目录。
+
+use crate::redaction::RedactionPolicy;
+use crate::types::CONSENT_VERSION;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_ENV: &str = "MOUSE_TRACKER_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "mouse_tracker.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// 事件日志路径
+    pub log_path: PathBuf,
+    /// 删除回执路径（JSONL，只追加）
+    pub receipts_path: PathBuf,
+    /// 审计日志路径（JSONL，只追加）
+    pub audit_path: PathBuf,
+    /// 按时间的保留策略，未配置时不自动删除
+    pub retention: Option<RetentionConfig>,
+    /// 写入存储前的脱敏规则
+    pub redaction: RedactionPolicy,
+    pub consent: ConsentConfig,
+    /// 允许跨域提交事件的来源
+    pub cors: CorsConfig,
+    /// 事件日志静态加密，未配置时写明文
+    pub encryption: Option<EncryptionConfig>,
+    /// 哈希链与签名检查点，未配置时不写链记录
+    pub integrity: Option<IntegrityConfig>,
+    /// 参与者假名，未配置时不记录 participant_id
+    pub pseudonym: Option<PseudonymConfig>,
+    /// 差分隐私聚合导出的预算
+    pub privacy: PrivacyConfig,
+    /// 下发给采集页面的设置
+    pub client: ClientSettings,
+    /// 读取接口（脱敏统计、删除会话）的访问令牌：名称 -> 令牌的 SHA-256（十六进制）。
+    /// 非空时这些接口需要 `Authorization: Bearer <令牌>`
+    pub read_tokens: BTreeMap<String, String>,
+    /// 其他项目，路由为 `/api/projects/<名称>/...`
+    pub projects: BTreeMap<String, ProjectConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            log_path: PathBuf::from("mouse_events.jsonl"),
+            receipts_path: PathBuf::from("deletion_receipts.jsonl"),
+            audit_path: PathBuf::from("audit.jsonl"),
+            retention: None,
+            redaction: RedactionPolicy::default(),
+            consent: ConsentConfig::default(),
+            cors: CorsConfig::default(),
+            encryption: None,
+            integrity: None,
+            pseudonym: None,
+            privacy: PrivacyConfig::default(),
+            client: ClientSettings::default(),
+            read_tokens: BTreeMap::new(),
+            projects: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsentConfig {
+    /// 是否拒绝没有有效同意的会话提交的事件
+    pub required: bool,
+    /// 当前同意书版本
+    pub version: String,
+    /// 同意记录路径（JSONL，只追加）
+    pub path: PathBuf,
+}
+
+impl Default for ConsentConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            version: CONSENT_VERSION.to_string(),
+            path: PathBuf::from("consents.jsonl"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// 允许的来源，如 `https://example.com`；`https://*.example.com` 匹配子域名，`*` 匹配任意来源。
+    /// 为空时只接受同源请求
+    pub allowed_origins: Vec<String>,
+    /// 浏览器缓存预检结果的时间（秒）
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// 密钥文件路径，格式见 [`crate::encryption`]
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityConfig {
+    /// 检查点签名密钥文件路径
+    pub key_file: PathBuf,
+    /// 每隔多少批写一个检查点
+    #[serde(default = "default_checkpoint_every")]
+    pub checkpoint_every: u64,
+}
+
+fn default_checkpoint_every() -> u64 {
+    100
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PseudonymConfig {
+    /// 假名密钥文件路径；更换密钥后新旧假名无法对应
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// 每个数据集可花费的总 ε
+    pub epsilon_budget: f64,
+    /// 每个数据集可花费的总 δ
+    pub delta_budget: f64,
+    /// 预算账本路径（JSONL，只追加）
+    pub ledger_path: PathBuf,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            epsilon_budget: 1.0,
+            delta_budget: 1e-5,
+            ledger_path: PathBuf::from("privacy_ledger.jsonl"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientSettings {
+    /// 键盘事件只记录按键类别，不记录具体字符
+    pub keystroke_class_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    /// 存储根目录，项目配置中的相对路径都相对于它
+    pub root: PathBuf,
+    /// 项目自己的配置（日志、密钥、脱敏、同意、跨域、令牌等），不能再包含 `projects`
+    #[serde(default)]
+    pub config: ServerConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// 原始事件最长保留天数
+    pub max_age_days: u64,
+    /// 检查间隔（秒）
+    #[serde(default = "default_retention_interval")]
+    pub check_interval_secs: u64,
+}
+
+fn default_retention_interval() -> u64 {
+    3600
+}
+
+impl ServerConfig {
+    /// 配置文件路径
+    pub fn path() -> PathBuf {
+        std::env::var_os(CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+
+    /// 读取配置文件，文件不存在时返回默认配置
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load_from(&Self::path())
+    }
+
+    /// 项目的配置，路径已解析到项目的存储根目录
+    pub fn project(&self, name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let project = self
+            .projects
+            .get(name)
+            .ok_or_else(|| format!("unknown project {:?}", name))?;
+        if !project.config.projects.is_empty() {
+            return Err(format!("project {:?}: nested projects are not supported", name).into());
+        }
+        Ok(project.config.clone().rooted(&project.root))
+    }
+
+    /// 把相对路径解析到 `root` 之下
+    pub(crate) fn rooted(mut self, root: &Path) -> Self {
+        for path in [&mut self.log_path, &mut self.receipts_path, &mut self.audit_path, &mut self.consent.path, &mut self.privacy.ledger_path] {
+            *path = root.join(&*path);
+        }
+        if let Some(encryption) = &mut self.encryption {
+            encryption.key_file = root.join(&encryption.key_file);
+        }
+        if let Some(integrity) = &mut self.integrity {
+            integrity.key_file = root.join(&integrity.key_file);
+        }
+        if let Some(pseudonym) = &mut self.pseudonym {
+            pseudonym.key_file = root.join(&pseudonym.key_file);
+        }
+        self
+    }
+
+    /// 检查反序列化无法发现的错误，包括各项目的配置
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.redaction.validate()?;
+        for (name, project) in &self.projects {
+            project.config.validate().map_err(|e| format!("project {:?}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let config: Self = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+                config.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+                Ok(config)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e).into()),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! 知情同意记录
+//!
+//! 每次同意或撤回都追加到同意记录文件（JSONL），内存中保留每个会话的最新决定。
+//! 启用 `consent.required` 后，服务器拒绝没有当前版本有效同意的会话提交的事件；
+//! 撤回同意会立即删除该会话已存储的数据。
+//!
+//! 会话第一次同意时服务器签发一个随机的撤回密钥，只保存其哈希；此后该会话的任何决定
+//! （撤回、再次同意）都必须附上密钥，否则任何人只要知道会话 ID 就能删除别人的数据。
+
+use crate::projects::{generate_token, token_hash};
+use crate::types::ConsentDecision;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 存储的同意记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsentRecord {
+    pub session_id: String,
+    pub version: String,
+    pub granted: bool,
+    /// 服务器收到决定的时间（Unix 毫秒）
+    pub decided_at: u64,
+    /// 撤回密钥的 SHA-256，会话同意过之后一直保留
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdraw_token_hash: Option<String>,
+}
+
+/// 决定没有附上会话的有效撤回密钥
+#[derive(Debug)]
+pub struct InvalidWithdrawToken;
+
+impl std::fmt::Display for InvalidWithdrawToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "missing or invalid withdraw token")
+    }
+}
+
+impl std::error::Error for InvalidWithdrawToken {}
+
+pub struct ConsentStore {
+    path: PathBuf,
+    latest: Mutex<HashMap<String, ConsentRecord>>,
+}
+
+impl ConsentStore {
+    /// 打开同意记录文件并加载每个会话的最新决定
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut latest = HashMap::new();
+
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record: ConsentRecord = serde_json::from_str(&line)?;
+                    latest.insert(record.session_id.clone(), record);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            latest: Mutex::new(latest),
+        })
+    }
+
+    /// 校验撤回密钥并记录一次决定，同意时返回新签发的撤回密钥
+    ///
+    /// 会话已有撤回密钥而决定没有附上匹配的密钥时返回 [`InvalidWithdrawToken`]。
+    pub fn record(
+        &self,
+        decision: &ConsentDecision,
+        decided_at: u64,
+    ) -> Result<(ConsentRecord, Option<String>), Box<dyn std::error::Error>> {
+        let mut latest = self.latest.lock().map_err(|_| "consent store poisoned")?;
+        let current_hash = latest.get(&decision.session_id).and_then(|r| r.withdraw_token_hash.clone());
+        if let Some(hash) = &current_hash {
+            if decision.withdraw_token.as_deref().map(token_hash).as_ref() != Some(hash) {
+                return Err(InvalidWithdrawToken.into());
+            }
+        }
+
+        let withdraw_token = decision.granted.then(generate_token);
+        let record = ConsentRecord {
+            session_id: decision.session_id.clone(),
+            version: decision.version.clone(),
+            granted: decision.granted,
+            decided_at,
+            withdraw_token_hash: withdraw_token.as_deref().map(token_hash).or(current_hash),
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        file.flush()?;
+        latest.insert(record.session_id.clone(), record.clone());
+
+        Ok((record, withdraw_token))
+    }
+
+    /// 会话是否对 `version` 给出了有效同意
+    pub fn has_valid_consent(&self, session_id: &str, version: &str) -> bool {
+        self.latest
+            .lock()
+            .map(|latest| {
+                latest
+                    .get(session_id)
+                    .is_some_and(|r| r.granted && r.version == version)
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn decision(session_id: &str, granted: bool, withdraw_token: Option<&str>) -> ConsentDecision {
+        ConsentDecision {
+            session_id: session_id.to_string(),
+            version: "v1".to_string(),
+            granted,
+            withdraw_token: withdraw_token.map(String::from),
+        }
+    }
+
+    #[test]
+    fn later_decisions_require_the_withdraw_token() {
+        let path = temp_dir("consent-token").join("consent.jsonl");
+        let store = ConsentStore::open(&path).unwrap();
+
+        let (_, token) = store.record(&decision("s1", true, None), 1).unwrap();
+        let token = token.unwrap();
+        assert!(store.has_valid_consent("s1", "v1"));
+        assert!(!store.has_valid_consent("s1", "v2"));
+
+        for wrong in [None, Some("guess")] {
+            let err = store.record(&decision("s1", false, wrong), 2).unwrap_err();
+            assert!(err.is::<InvalidWithdrawToken>());
+        }
+        assert!(store.has_valid_consent("s1", "v1"));
+
+        // 撤回后保留原密钥的哈希，再次同意仍需该密钥并换发新密钥
+        let (record, none) = store.record(&decision("s1", false, Some(&token)), 3).unwrap();
+        assert_eq!((record.granted, none), (false, None));
+        assert!(!store.has_valid_consent("s1", "v1"));
+        assert!(store.record(&decision("s1", true, None), 4).is_err());
+        let (_, renewed) = store.record(&decision("s1", true, Some(&token)), 5).unwrap();
+        assert_ne!(renewed.as_deref(), Some(token.as_str()));
+        assert!(store.record(&decision("s1", false, Some(&token)), 6).is_err());
+    }
+
+    #[test]
+    fn reopened_store_keeps_latest_decisions_and_token_hashes() {
+        let path = temp_dir("consent-reopen").join("consent.jsonl");
+        let store = ConsentStore::open(&path).unwrap();
+        let (_, token) = store.record(&decision("s1", true, None), 1).unwrap();
+        store.record(&decision("s2", true, None), 2).unwrap();
+        store.record(&decision("s2", false, None), 3).unwrap_err();
+
+        let reopened = ConsentStore::open(&path).unwrap();
+        assert!(reopened.has_valid_consent("s1", "v1"));
+        assert!(reopened.has_valid_consent("s2", "v1"));
+        assert!(reopened.record(&decision("s1", false, None), 4).is_err());
+        reopened.record(&decision("s1", false, token.as_deref()), 5).unwrap();
+
+        // 文件中只保存密钥的哈希
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(!text.contains(&token.unwrap()));
+        assert_eq!(text.lines().count(), 3);
+        assert!(!ConsentStore::open(&path).unwrap().has_valid_consent("s1", "v1"));
+    }
+}
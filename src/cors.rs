@@ -0,0 +1,202 @@
+//! 跨域提交
+//!
+//! 采集接口（`/api/mouse`、`/api/consent`、`/api/participant/unlink`）允许配置中列出的来源跨域提交。
+//! 带 `Origin` 请求头的请求只有在来源与本服务器相同或在允许列表中时才会被处理，否则返回 403；
+//! 预检请求（`OPTIONS`）由这里直接应答。没有 `Origin` 的请求（非浏览器客户端）不受限制。
+//! 通过校验的来源放入请求扩展 [`RequestOrigin`]，随事件记录。
+
+use crate::config::CorsConfig;
+use crate::mouse_handler::AppState;
+use crate::types::PARTICIPANT_HEADER;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// 通过校验的请求来源
+#[derive(Debug, Clone)]
+pub struct RequestOrigin(pub String);
+
+impl CorsConfig {
+    /// 来源是否在允许列表中
+    pub fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|pattern| origin_matches(pattern, origin))
+    }
+}
+
+/// `*` 匹配任意来源；`https://*.example.com` 匹配 example.com 的任意子域名（含端口）
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let Some((prefix, domain)) = pattern.split_once("*.") else {
+        return pattern.eq_ignore_ascii_case(origin);
+    };
+
+    let origin = origin.to_ascii_lowercase();
+    let Some(host) = origin.strip_prefix(&prefix.to_ascii_lowercase()) else {
+        return false;
+    };
+    let host = host.split(':').next().unwrap_or(host);
+    host.strip_suffix(&domain.to_ascii_lowercase())
+        .and_then(|sub| sub.strip_suffix('.'))
+        .is_some_and(|sub| !sub.is_empty() && !sub.contains('/'))
+}
+
+/// 来源与请求的 `Host` 相同（同源请求也可能带 `Origin`）
+fn same_origin(origin: &str, headers: &HeaderMap) -> bool {
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+    let authority = origin.split_once("://").map(|(_, authority)| authority);
+    matches!((host, authority), (Some(host), Some(authority)) if host.eq_ignore_ascii_case(authority))
+}
+
+/// 校验来源、应答预检并为跨域响应加上 CORS 头
+pub async fn enforce(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(origin) = request.headers().get(header::ORIGIN).and_then(|o| o.to_str().ok()) else {
+        return next.run(request).await;
+    };
+    let origin = origin.to_string();
+
+    let cors = &state.config.cors;
+    let cross_origin = !same_origin(&origin, request.headers());
+    if cross_origin && !cors.allows(&origin) {
+        return (StatusCode::FORBIDDEN, "origin not allowed").into_response();
+    }
+
+    let preflight = request.method() == Method::OPTIONS
+        && request.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = if preflight {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("POST"));
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_HEADERS,
+            HeaderValue::from_str(&format!("content-type, {}", PARTICIPANT_HEADER)).expect("valid header value"),
+        );
+        headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(cors.max_age_secs));
+        response
+    } else {
+        request.extensions_mut().insert(RequestOrigin(origin.clone()));
+        next.run(request).await
+    };
+
+    if cross_origin {
+        let headers = response.headers_mut();
+        if let Ok(value) = HeaderValue::from_str(&origin) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::mouse_handler::ServerState;
+    use crate::test_util::{config_in, event, run, temp_dir};
+    use axum::body::Body;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn cors(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            ..CorsConfig::default()
+        }
+    }
+
+    #[test]
+    fn allowlist_matches_exact_wildcard_and_subdomain_patterns() {
+        let config = cors(&["https://example.com", "https://*.study.org"]);
+        assert!(config.allows("https://example.com"));
+        assert!(config.allows("HTTPS://Example.com"));
+        assert!(config.allows("https://a.study.org"));
+        assert!(config.allows("https://a.b.study.org:8443"));
+
+        assert!(!config.allows("http://example.com"));
+        assert!(!config.allows("https://example.com:8080"));
+        assert!(!config.allows("https://study.org"));
+        assert!(!config.allows("https://evilstudy.org"));
+        assert!(!config.allows("https://a.study.org.evil.com"));
+        assert!(!config.allows("http://a.study.org"));
+        assert!(!CorsConfig::default().allows("https://example.com"));
+        assert!(cors(&["*"]).allows("https://anything.test"));
+    }
+
+    fn router(config: ServerConfig) -> axum::Router {
+        let state = Arc::new(ServerState::new(config).unwrap());
+        crate::projects::routes(state, &[])
+    }
+
+    fn request(method: Method, origin: Option<&str>, headers: &[(header::HeaderName, &str)]) -> Request {
+        let mut builder = Request::builder().method(method).uri("/mouse").header(header::HOST, "collector.test");
+        if let Some(origin) = origin {
+            builder = builder.header(header::ORIGIN, origin);
+        }
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        let body = serde_json::to_string(&[event("s1", "e1", 1)]).unwrap();
+        builder.header(header::CONTENT_TYPE, "application/json").body(Body::from(body)).unwrap()
+    }
+
+    #[test]
+    fn preflight_is_answered_for_allowed_origins_only() {
+        let mut config = config_in(&temp_dir("cors-preflight"));
+        config.cors = cors(&["https://*.study.org"]);
+        let app = router(config);
+
+        run(async {
+            let preflight = |origin| {
+                request(Method::OPTIONS, Some(origin), &[(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")])
+            };
+            let response = app.clone().oneshot(preflight("https://a.study.org")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            let headers = response.headers();
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://a.study.org");
+            assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "POST");
+            assert!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains(PARTICIPANT_HEADER));
+            assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+            assert_eq!(headers[header::VARY], "origin");
+
+            let response = app.clone().oneshot(preflight("https://evil.test")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        });
+    }
+
+    #[test]
+    fn posts_are_checked_against_the_allowlist() {
+        let dir = temp_dir("cors-post");
+        let mut config = config_in(&dir);
+        config.cors = cors(&["https://example.com"]);
+        let log_path = config.log_path.clone();
+        let app = router(config);
+
+        run(async {
+            let response = app.clone().oneshot(request(Method::POST, Some("https://evil.test"), &[])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            assert!(!log_path.exists());
+
+            let response = app.clone().oneshot(request(Method::POST, Some("https://example.com"), &[])).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://example.com");
+
+            // 同源请求和没有 Origin 的请求不受允许列表限制，也不加 CORS 头
+            for origin in [Some("http://collector.test"), None] {
+                let response = app.clone().oneshot(request(Method::POST, origin, &[])).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert!(!response.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+            }
+        });
+
+        // 跨域提交的事件记录来源
+        let origins: Vec<_> = crate::export::read_events(&log_path, None)
+            .unwrap()
+            .map(|e| e.unwrap().origin)
+            .collect();
+        assert_eq!(origins, [Some("https://example.com".to_string()), Some("http://collector.test".to_string()), None]);
+    }
+}
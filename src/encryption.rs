@@ -0,0 +1,414 @@
+//! 事件日志静态加密
+//!
+//! 开启后事件日志按段加密：每段占一行，形如 `ENC1 <key_id> <nonce> <密文>`，
+//! 密文是若干行 JSONL 经 XChaCha20-Poly1305 加密后的 Base64，段头（`ENC1 <key_id>`）作为附加认证数据。
+//! 段头记录了所用密钥的 ID，轮换密钥后旧段仍用旧密钥解密；明文行和加密段可以出现在同一文件中。
+//!
+//! 密钥文件（JSON，密钥为 Base64 编码的 32 字节）：
+//!
+//! ```json
+//! { "active": "2025-02", "keys": { "2025-01": "...", "2025-02": "..." } }
+//! ```
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// 加密段的行首标记
+pub const SEGMENT_MAGIC: &str = "ENC1";
+
+/// 每段最多包含的事件行数
+pub const SEGMENT_LINES: usize = 1024;
+
+/// 密钥文件的结构
+#[derive(Default, Serialize, Deserialize)]
+struct KeyFile {
+    /// 新数据使用的密钥 ID
+    active: String,
+    keys: BTreeMap<String, String>,
+}
+
+/// 从密钥文件加载的全部密钥
+#[derive(Clone)]
+pub struct Keyring {
+    active: String,
+    keys: BTreeMap<String, Key>,
+}
+
+// 不输出密钥本身
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("active", &self.active)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// 该行是否为加密段
+pub fn is_segment(line: &[u8]) -> bool {
+    line.strip_prefix(SEGMENT_MAGIC.as_bytes())
+        .is_some_and(|rest| rest.first() == Some(&b' '))
+}
+
+impl Keyring {
+    /// 读取密钥文件
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let file: KeyFile = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut keys = BTreeMap::new();
+        for (id, encoded) in &file.keys {
+            if id.is_empty() || id.contains(char::is_whitespace) {
+                return Err(format!("{}: invalid key id {:?}", path.display(), id).into());
+            }
+            let bytes = STANDARD.decode(encoded)?;
+            if bytes.len() != 32 {
+                return Err(format!("{}: key {} must be 32 bytes", path.display(), id).into());
+            }
+            keys.insert(id.clone(), *Key::from_slice(&bytes));
+        }
+
+        if !keys.contains_key(&file.active) {
+            return Err(format!("{}: active key {:?} not found", path.display(), file.active).into());
+        }
+
+        Ok(Self {
+            active: file.active,
+            keys,
+        })
+    }
+
+    /// 新数据使用的密钥 ID
+    pub fn active_key_id(&self) -> &str {
+        &self.active
+    }
+
+    /// 用当前密钥加密，返回一行加密段（不含换行符）
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let header = format!("{} {}", SEGMENT_MAGIC, self.active);
+        let cipher = XChaCha20Poly1305::new(&self.keys[&self.active]);
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: header.as_bytes() })
+            .map_err(|_| "encryption failed")?;
+
+        Ok(format!("{} {} {}", header, STANDARD.encode(nonce), STANDARD.encode(ciphertext)))
+    }
+
+    /// 解密一行加密段，返回明文（若干行 JSONL）
+    pub fn open(&self, line: &str) -> Result<Vec<u8>, String> {
+        let mut parts = line.split(' ');
+        let (Some(SEGMENT_MAGIC), Some(key_id), Some(nonce), Some(ciphertext), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed encrypted segment".to_string());
+        };
+
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| format!("unknown key id {:?}", key_id))?;
+        let nonce = STANDARD.decode(nonce).map_err(|e| format!("bad nonce: {}", e))?;
+        if nonce.len() != 24 {
+            return Err("bad nonce length".to_string());
+        }
+        let ciphertext = STANDARD.decode(ciphertext).map_err(|e| format!("bad ciphertext: {}", e))?;
+        let header = format!("{} {}", SEGMENT_MAGIC, key_id);
+
+        XChaCha20Poly1305::new(key)
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload { msg: &ciphertext, aad: header.as_bytes() },
+            )
+            .map_err(|_| format!("authentication failed with key {:?}", key_id))
+    }
+}
+
+/// 生成新密钥并设为当前密钥；密钥文件不存在时创建
+///
+/// 旧密钥保留在文件中，用于解密轮换前写入的段。
+pub fn rotate_key(path: &Path, key_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if key_id.is_empty() || key_id.contains(char::is_whitespace) {
+        return Err(format!("invalid key id {:?}", key_id).into());
+    }
+
+    let mut file: KeyFile = match std::fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => KeyFile::default(),
+        Err(e) => return Err(e.into()),
+    };
+    if file.keys.contains_key(key_id) {
+        return Err(format!("key id {:?} already exists", key_id).into());
+    }
+
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    file.keys.insert(key_id.to_string(), STANDARD.encode(key));
+    file.active = key_id.to_string();
+
+    write_key_file(path, format!("{}\n", serde_json::to_string_pretty(&file)?).as_bytes(), true)
+}
+
+/// 原子地写入密钥文件（权限 0600）：先写同目录下的临时文件并落盘，再替换或链接到 `path`
+///
+/// 写入中途崩溃或磁盘已满时原文件保持不变，不会丢失已有的密钥。
+/// `overwrite` 为 false 时 `path` 已存在则报错。
+fn write_key_file(path: &Path, contents: &[u8], overwrite: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let name = path.file_name().ok_or_else(|| format!("{}: not a file path", path.display()))?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+
+    // 上次中断留下的临时文件可能权限不对，删掉后重新创建
+    match std::fs::remove_file(&tmp_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("{}: {}", tmp_path.display(), e).into()),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let result = (|| {
+        let mut out = options.open(&tmp_path)?;
+        out.write_all(contents)?;
+        out.sync_all()?;
+        match overwrite {
+            true => std::fs::rename(&tmp_path, path),
+            // 硬链接在目标已存在时失败，不会覆盖
+            false => std::fs::hard_link(&tmp_path, path).and_then(|_| std::fs::remove_file(&tmp_path)),
+        }
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(format!("{}: {}", path.display(), e).into());
+    }
+
+    // 让替换本身也落盘
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// 读取 Base64 编码的对称密钥文件（检查点签名、参与者假名等），密钥至少 32 字节
+pub fn load_secret_key(path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let key = STANDARD.decode(text.trim()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if key.len() < 32 {
+        return Err(format!("{}: key must be at least 32 bytes", path.display()).into());
+    }
+    Ok(key)
+}
+
+/// 生成 32 字节随机密钥并以 Base64 写入 `path`；`overwrite` 为 false 时文件已存在则报错
+pub fn write_secret_key(path: &Path, overwrite: bool) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut key = vec![0u8; 32];
+    OsRng.fill_bytes(&mut key);
+
+    write_key_file(path, format!("{}\n", STANDARD.encode(&key)).as_bytes(), overwrite)?;
+    Ok(key)
+}
+
+/// 按行写出事件日志
+///
+/// 没有密钥时直接写明文行；有密钥时先缓冲，满 [`SEGMENT_LINES`] 行或 [`finish`](Self::finish) 时封装为一个加密段。
+pub struct SegmentWriter<W: Write> {
+    inner: W,
+    keyring: Option<Keyring>,
+    pending: Vec<u8>,
+    lines: usize,
+}
+
+impl<W: Write> SegmentWriter<W> {
+    pub fn new(inner: W, keyring: Option<&Keyring>) -> Self {
+        Self {
+            inner,
+            keyring: keyring.cloned(),
+            pending: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    /// 写入一行事件（不含换行符）
+    pub fn write_line(&mut self, line: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if self.keyring.is_none() {
+            self.inner.write_all(line)?;
+            self.inner.write_all(b"\n")?;
+            return Ok(());
+        }
+
+        self.pending.extend_from_slice(line);
+        self.pending.push(b'\n');
+        self.lines += 1;
+        if self.lines >= SEGMENT_LINES {
+            self.seal_pending()?;
+        }
+        Ok(())
+    }
+
+    /// 把缓冲的事件封装为一个段（没有缓冲或没有密钥时什么也不做）
+    pub fn seal_pending(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let (Some(keyring), false) = (&self.keyring, self.pending.is_empty()) {
+            let segment = keyring.seal(&self.pending)?;
+            writeln!(self.inner, "{}", segment)?;
+            self.pending.clear();
+            self.lines = 0;
+        }
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// 封装剩余的缓冲并刷新，返回底层写入器
+    pub fn finish(mut self) -> Result<W, Box<dyn std::error::Error>> {
+        self.seal_pending()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_reader::{ErrorKind, LogReader};
+    use crate::test_util::temp_dir;
+
+    fn write_segments(keyring: &Keyring, lines: &[&str]) -> Vec<u8> {
+        let mut writer = SegmentWriter::new(Vec::new(), Some(keyring));
+        for line in lines {
+            writer.write_line(line.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let path = temp_dir("keyring").join("keys.json");
+        rotate_key(&path, "k1").unwrap();
+        let keyring = Keyring::load(&path).unwrap();
+
+        let segment = keyring.seal(b"a\nb\n").unwrap();
+        assert!(is_segment(segment.as_bytes()));
+        assert!(segment.starts_with("ENC1 k1 "));
+        assert_eq!(keyring.open(&segment).unwrap(), b"a\nb\n");
+    }
+
+    #[test]
+    fn tampered_segments_fail_authentication() {
+        let path = temp_dir("tamper-segment").join("keys.json");
+        rotate_key(&path, "k1").unwrap();
+        rotate_key(&path, "k2").unwrap();
+        let keyring = Keyring::load(&path).unwrap();
+        let segment = keyring.seal(b"secret").unwrap();
+
+        // 段头是附加认证数据，改写密钥 ID 也会认证失败
+        let relabeled = segment.replacen("ENC1 k2", "ENC1 k1", 1);
+        assert!(keyring.open(&relabeled).unwrap_err().contains("authentication failed"));
+
+        let mut parts: Vec<String> = segment.split(' ').map(String::from).collect();
+        let mut ciphertext = STANDARD.decode(&parts[3]).unwrap();
+        ciphertext[0] ^= 1;
+        parts[3] = STANDARD.encode(ciphertext);
+        assert!(keyring.open(&parts.join(" ")).is_err());
+        assert!(keyring.open("ENC1 k2 only-three").is_err());
+    }
+
+    #[test]
+    fn rotated_keyring_still_reads_old_segments() {
+        let path = temp_dir("rotate").join("keys.json");
+        rotate_key(&path, "2025-01").unwrap();
+        let old = write_segments(&Keyring::load(&path).unwrap(), &["1"]);
+
+        rotate_key(&path, "2025-02").unwrap();
+        assert!(rotate_key(&path, "2025-02").is_err());
+        let keyring = Keyring::load(&path).unwrap();
+        assert_eq!(keyring.active_key_id(), "2025-02");
+        let new = write_segments(&keyring, &["2"]);
+        assert!(String::from_utf8_lossy(&new).starts_with("ENC1 2025-02 "));
+
+        let log = String::from_utf8([old, new].concat()).unwrap();
+        let opened: Vec<_> = log.lines().map(|segment| keyring.open(segment).unwrap()).collect();
+        assert_eq!(opened, [b"1\n".to_vec(), b"2\n".to_vec()]);
+    }
+
+    #[test]
+    fn log_reader_decrypts_mixed_plaintext_and_segments() {
+        const EVENT: &str = r#"{"event_type":"click","timestamp":1,"x":1,"y":2,"session_id":"s1","event_id":"e1"}"#;
+        let path = temp_dir("mixed").join("keys.json");
+        rotate_key(&path, "k1").unwrap();
+        let keyring = Keyring::load(&path).unwrap();
+
+        let mut log = format!("{EVENT}\n").into_bytes();
+        log.extend(write_segments(&keyring, &[EVENT, EVENT]));
+
+        let records: Vec<_> = LogReader::new(&log[..]).with_keyring(Some(&keyring)).collect();
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.as_ref().is_ok_and(|r| r.raw == EVENT.as_bytes())));
+        // 同一段中的记录共享段所在的行号
+        assert_eq!(records[1].as_ref().unwrap().line, 2);
+        assert_eq!(records[2].as_ref().unwrap().line, 2);
+
+        let without_key: Vec<_> = LogReader::new(&log[..]).collect();
+        assert_eq!(without_key.len(), 2);
+        assert_eq!(without_key[1].as_ref().unwrap_err().kind, ErrorKind::Decrypt);
+    }
+
+    #[test]
+    fn segments_hold_at_most_segment_lines() {
+        let path = temp_dir("segment-size").join("keys.json");
+        rotate_key(&path, "k1").unwrap();
+        let keyring = Keyring::load(&path).unwrap();
+        let lines = vec!["x"; SEGMENT_LINES + 1];
+        let out = write_segments(&keyring, &lines);
+        assert_eq!(out.split(|b| *b == b'\n').filter(|l| !l.is_empty()).count(), 2);
+    }
+
+    #[test]
+    fn load_rejects_bad_key_files() {
+        let dir = temp_dir("bad-keys");
+        let short = dir.join("short.json");
+        std::fs::write(&short, format!(r#"{{"active":"k","keys":{{"k":"{}"}}}}"#, STANDARD.encode([0u8; 16]))).unwrap();
+        assert!(Keyring::load(&short).is_err());
+
+        let missing_active = dir.join("missing.json");
+        std::fs::write(&missing_active, format!(r#"{{"active":"k2","keys":{{"k":"{}"}}}}"#, STANDARD.encode([0u8; 32]))).unwrap();
+        assert!(Keyring::load(&missing_active).is_err());
+        assert!(rotate_key(&dir.join("keys.json"), "has space").is_err());
+    }
+
+    #[test]
+    fn key_files_are_replaced_atomically() {
+        let dir = temp_dir("key-file-write");
+        let path = dir.join("keys.json");
+        rotate_key(&path, "k1").unwrap();
+        // 上次写入中途崩溃留下的临时文件不影响轮换
+        std::fs::write(dir.join(".keys.json.tmp"), "partial").unwrap();
+        rotate_key(&path, "k2").unwrap();
+        assert!(Keyring::load(&path).unwrap().keys.contains_key("k1"));
+        assert!(!dir.join(".keys.json.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let secret = dir.join("secret.key");
+        let key = write_secret_key(&secret, false).unwrap();
+        assert!(write_secret_key(&secret, false).is_err());
+        assert_eq!(load_secret_key(&secret).unwrap(), key);
+        assert_ne!(write_secret_key(&secret, true).unwrap(), key);
+        let names: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names.len(), 2, "{names:?}");
+    }
+}
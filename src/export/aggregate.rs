@@ -0,0 +1,562 @@
+//! 差分隐私聚合导出
+//!
+//! 公开发布时不提供原始轨迹，只发布加噪的聚合结果（JSON）：
+//!
+//! - `sessions`：会话数
+//! - `heatmap`：按视口归一化坐标分格的事件计数，每个会话最多计入前 `max_events_per_session` 个事件
+//! - `event_rate`：会话事件速率（事件/秒）的直方图，每个会话计入一次
+//!
+//! 隐私单位是会话：增删一个会话时上述三项的敏感度分别为 1、`max_events_per_session` 和 1。
+//! 总预算 ε（高斯机制还有 δ）平均分给三项，按基本组合定理相加。
+//! 加噪后的计数取整并截断为非负数，这属于后处理，不消耗预算。
+//!
+//! 已花费的预算记录在预算账本（JSONL，只追加）中，超出配置的上限时拒绝导出。
+//! 账本按输入数据记账而不是按数据集名称：同一路径的日志（包括之后追加了事件的）和内容相同的副本
+//! 共享一份预算，换个名称或复制文件都不能重新获得预算。数据集名称只用于标注发布结果。
+//! 检查和记账期间持有账本文件的排他锁，并发的导出不会都通过检查。
+//! 预算在加噪之前扣除，导出失败时也不退回。
+
+use super::read_events;
+use crate::encryption::Keyring;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// 默认的事件速率分箱边界（事件/秒），最后一箱没有上界
+pub const DEFAULT_RATE_EDGES: &[f64] = &[0.0, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// 聚合结果的项数，预算在各项之间平均分配
+const RELEASES: f64 = 3.0;
+
+/// 噪声机制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mechanism {
+    /// ε-差分隐私，按 L1 敏感度加拉普拉斯噪声
+    Laplace,
+    /// (ε, δ)-差分隐私，按 L2 敏感度加高斯噪声（要求每项的 ε < 1）
+    Gaussian,
+}
+
+impl std::str::FromStr for Mechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "laplace" => Ok(Self::Laplace),
+            "gaussian" => Ok(Self::Gaussian),
+            other => Err(format!("unknown mechanism: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregateExportOptions {
+    /// 数据集名称，只用于标注发布结果，预算按输入数据记账
+    pub dataset: String,
+    pub mechanism: Mechanism,
+    /// 本次导出花费的 ε
+    pub epsilon: f64,
+    /// 本次导出花费的 δ（仅高斯机制）
+    pub delta: f64,
+    /// 热力图每边的格数
+    pub grid: usize,
+    /// 每个会话计入热力图的最大事件数
+    pub max_events_per_session: u64,
+    /// 事件速率直方图的分箱边界，升序
+    pub rate_edges: Vec<f64>,
+    /// 解密输入中加密段的密钥
+    pub keyring: Option<Keyring>,
+}
+
+impl Default for AggregateExportOptions {
+    fn default() -> Self {
+        Self {
+            dataset: String::new(),
+            mechanism: Mechanism::Laplace,
+            epsilon: 1.0,
+            delta: 0.0,
+            grid: 32,
+            max_events_per_session: 1000,
+            rate_edges: DEFAULT_RATE_EDGES.to_vec(),
+            keyring: None,
+        }
+    }
+}
+
+/// 导出的聚合结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateRelease {
+    pub dataset: String,
+    pub mechanism: Mechanism,
+    pub epsilon: f64,
+    pub delta: f64,
+    pub generated_at: u64,
+    pub sessions: u64,
+    pub heatmap: Heatmap,
+    pub event_rate: RateHistogram,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Heatmap {
+    pub grid: usize,
+    pub max_events_per_session: u64,
+    /// `counts[行][列]`，行对应 y，列对应 x，原点在视口左上角
+    pub counts: Vec<Vec<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateHistogram {
+    /// 分箱边界（事件/秒），第 i 箱为 `[edges[i], edges[i+1])`，最后一箱没有上界
+    pub edges: Vec<f64>,
+    pub counts: Vec<u64>,
+}
+
+/// 每个会话的累计量
+struct SessionStats {
+    first: u64,
+    last: u64,
+    events: u64,
+}
+
+/// (0, 1) 上的均匀随机数
+fn uniform() -> f64 {
+    loop {
+        let u = (OsRng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        if u > 0.0 {
+            return u;
+        }
+    }
+}
+
+fn laplace(scale: f64) -> f64 {
+    let u = uniform() - 0.5;
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+/// 标准正态分布（Box-Muller）
+fn standard_normal() -> f64 {
+    (-2.0 * uniform().ln()).sqrt() * (2.0 * std::f64::consts::PI * uniform()).cos()
+}
+
+impl AggregateExportOptions {
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !(self.epsilon > 0.0 && self.epsilon.is_finite()) {
+            return Err("epsilon must be positive".into());
+        }
+        if self.mechanism == Mechanism::Gaussian {
+            if !(self.delta > 0.0 && self.delta < 1.0) {
+                return Err("gaussian mechanism needs 0 < delta < 1".into());
+            }
+            if self.epsilon / RELEASES >= 1.0 {
+                return Err(format!("gaussian mechanism needs epsilon < {}", RELEASES).into());
+            }
+        }
+        if self.grid == 0 || self.max_events_per_session == 0 {
+            return Err("grid and max_events_per_session must be positive".into());
+        }
+        if self.rate_edges.is_empty() || self.rate_edges.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("rate edges must be non-empty and increasing".into());
+        }
+        Ok(())
+    }
+
+    /// 给一个敏感度为 `sensitivity` 的计数加噪声（每项分到 ε/3、δ/3）
+    fn noisy(&self, count: u64, sensitivity: f64) -> u64 {
+        let epsilon = self.epsilon / RELEASES;
+        let noise = match self.mechanism {
+            Mechanism::Laplace => laplace(sensitivity / epsilon),
+            Mechanism::Gaussian => {
+                let delta = self.delta / RELEASES;
+                let sigma = sensitivity * (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+                sigma * standard_normal()
+            }
+        };
+        (count as f64 + noise).round().max(0.0) as u64
+    }
+}
+
+/// 计算加噪的聚合结果并写入 `output`（JSON）
+///
+/// 先在预算账本中扣除本次的 ε、δ，剩余预算不足时返回错误，不输出任何结果。
+pub fn export_aggregates(
+    input: &Path,
+    output: &Path,
+    options: &AggregateExportOptions,
+    ledger: &BudgetLedger,
+    budget: &PrivacyBudget,
+) -> Result<AggregateRelease, Box<dyn std::error::Error>> {
+    options.validate()?;
+
+    let grid = options.grid;
+    let mut cells = vec![vec![0u64; grid]; grid];
+    let mut sessions: HashMap<String, SessionStats> = HashMap::new();
+
+    for event in read_events(input, options.keyring.as_ref())? {
+        let event = event?;
+        let stats = sessions.entry(event.session_id.clone()).or_insert_with(|| SessionStats {
+            first: event.timestamp,
+            last: event.timestamp,
+            events: 0,
+        });
+        stats.first = stats.first.min(event.timestamp);
+        stats.last = stats.last.max(event.timestamp);
+        stats.events += 1;
+
+        if stats.events > options.max_events_per_session {
+            continue;
+        }
+        let (Some(width), Some(height)) = (event.viewport_width, event.viewport_height) else {
+            continue;
+        };
+        if width == 0 || height == 0 || event.x < 0 || event.y < 0 {
+            continue;
+        }
+        let (col, row) = (
+            event.x as usize * grid / width as usize,
+            event.y as usize * grid / height as usize,
+        );
+        if col < grid && row < grid {
+            cells[row][col] += 1;
+        }
+    }
+
+    let mut rates = vec![0u64; options.rate_edges.len()];
+    for stats in sessions.values() {
+        // 不足一秒的会话按一秒计
+        let seconds = ((stats.last - stats.first) as f64 / 1000.0).max(1.0);
+        let rate = stats.events as f64 / seconds;
+        if let Some(bin) = options.rate_edges.iter().rposition(|&edge| rate >= edge) {
+            rates[bin] += 1;
+        }
+    }
+
+    let generated_at = crate::mouse_handler::now_ms();
+    ledger.charge(
+        &BudgetEntry {
+            dataset: options.dataset.clone(),
+            source: std::fs::canonicalize(input)?.display().to_string(),
+            content_hash: content_hash(input)?,
+            at: generated_at,
+            mechanism: options.mechanism,
+            epsilon: options.epsilon,
+            delta: options.delta,
+            output: output.display().to_string(),
+        },
+        budget,
+    )?;
+
+    // 一个会话的事件全部落在同一格时 L1、L2 敏感度都取到上限
+    let heatmap_sensitivity = options.max_events_per_session as f64;
+    let release = AggregateRelease {
+        dataset: options.dataset.clone(),
+        mechanism: options.mechanism,
+        epsilon: options.epsilon,
+        delta: options.delta,
+        generated_at,
+        sessions: options.noisy(sessions.len() as u64, 1.0),
+        heatmap: Heatmap {
+            grid,
+            max_events_per_session: options.max_events_per_session,
+            counts: cells
+                .iter()
+                .map(|row| row.iter().map(|&c| options.noisy(c, heatmap_sensitivity)).collect())
+                .collect(),
+        },
+        event_rate: RateHistogram {
+            edges: options.rate_edges.clone(),
+            counts: rates.iter().map(|&c| options.noisy(c, 1.0)).collect(),
+        },
+    };
+
+    let mut out = File::create(output)?;
+    serde_json::to_writer_pretty(&mut out, &release)?;
+    writeln!(out)?;
+    Ok(release)
+}
+
+/// 文件内容的 SHA-256（十六进制）
+fn content_hash(path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// 每份输入数据的预算上限
+#[derive(Debug, Clone, Copy)]
+pub struct PrivacyBudget {
+    pub epsilon: f64,
+    pub delta: f64,
+}
+
+/// 预算账本中的一条记录
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BudgetEntry {
+    pub dataset: String,
+    /// 输入文件的规范路径
+    #[serde(default)]
+    pub source: String,
+    /// 输入文件内容的 SHA-256
+    #[serde(default)]
+    pub content_hash: String,
+    pub at: u64,
+    pub mechanism: Mechanism,
+    pub epsilon: f64,
+    pub delta: f64,
+    /// 导出文件
+    pub output: String,
+}
+
+impl BudgetEntry {
+    /// 两次导出是否使用同一份数据：路径相同或内容相同
+    ///
+    /// 早期的记录没有路径和哈希，按数据集名称比较。
+    fn same_data(&self, other: &BudgetEntry) -> bool {
+        if self.source.is_empty() || other.source.is_empty() {
+            return self.dataset == other.dataset;
+        }
+        self.source == other.source || self.content_hash == other.content_hash
+    }
+
+    /// 汇总时的分组键
+    fn source_key(&self) -> &str {
+        match self.source.is_empty() {
+            true => &self.dataset,
+            false => &self.source,
+        }
+    }
+}
+
+/// 一份输入数据已花费的预算
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BudgetSpent {
+    /// 最近一次导出使用的数据集名称
+    pub dataset: String,
+    /// 输入文件的规范路径（早期记录为空）
+    pub source: String,
+    pub releases: usize,
+    pub epsilon: f64,
+    pub delta: f64,
+}
+
+/// 隐私预算账本
+pub struct BudgetLedger {
+    path: PathBuf,
+}
+
+impl BudgetLedger {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// 每份输入数据已花费的预算，按路径排序
+    pub fn spent(&self) -> Result<Vec<BudgetSpent>, Box<dyn std::error::Error>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("{}: {}", self.path.display(), e).into()),
+        };
+
+        let mut spent: HashMap<String, BudgetSpent> = HashMap::new();
+        for entry in self.entries(file)? {
+            let total = spent.entry(entry.source_key().to_string()).or_default();
+            total.dataset = entry.dataset.clone();
+            total.source = entry.source.clone();
+            total.releases += 1;
+            total.epsilon += entry.epsilon;
+            total.delta += entry.delta;
+        }
+
+        let mut spent: Vec<BudgetSpent> = spent.into_values().collect();
+        spent.sort_by(|a, b| (&a.source, &a.dataset).cmp(&(&b.source, &b.dataset)));
+        Ok(spent)
+    }
+
+    /// 读取账本中的全部记录
+    fn entries(&self, file: impl Read) -> Result<Vec<BudgetEntry>, Box<dyn std::error::Error>> {
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: BudgetEntry = serde_json::from_str(&line)
+                .map_err(|e| format!("{}: line {}: {}", self.path.display(), i + 1, e))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// 剩余预算足够时追加一条记录，否则返回错误
+    ///
+    /// 同一份数据（见 [`BudgetEntry`] 的路径和内容哈希）之前的所有导出都计入已花费的预算。
+    pub fn charge(&self, entry: &BudgetEntry, budget: &PrivacyBudget) -> Result<(), Box<dyn std::error::Error>> {
+        let mut file = OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+        // 排他锁在文件关闭时释放，检查和追加之间其他导出只能等待
+        file.lock()?;
+
+        file.seek(SeekFrom::Start(0))?;
+        let (epsilon, delta) = self
+            .entries(&file)?
+            .iter()
+            .filter(|e| e.same_data(entry))
+            .fold((0.0, 0.0), |(epsilon, delta), e| (epsilon + e.epsilon, delta + e.delta));
+        // 浮点累加的误差不应导致恰好用完预算的导出被拒绝
+        const SLACK: f64 = 1e-9;
+        if epsilon + entry.epsilon > budget.epsilon + SLACK || delta + entry.delta > budget.delta + SLACK {
+            return Err(format!(
+                "privacy budget exceeded for {}: spent epsilon={} delta={}, requested epsilon={} delta={}, budget epsilon={} delta={}",
+                entry.source_key(), epsilon, delta, entry.epsilon, entry.delta, budget.epsilon, budget.delta
+            )
+            .into());
+        }
+
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{event, temp_dir};
+
+    /// 数据集 `dataset` 对应文件 `/data/<dataset>.jsonl`
+    fn entry(dataset: &str, epsilon: f64, delta: f64) -> BudgetEntry {
+        BudgetEntry {
+            dataset: dataset.to_string(),
+            source: format!("/data/{}.jsonl", dataset),
+            content_hash: format!("hash-{}", dataset),
+            at: 1,
+            mechanism: Mechanism::Laplace,
+            epsilon,
+            delta,
+            output: "release.json".to_string(),
+        }
+    }
+
+    #[test]
+    fn ledger_sums_spending_per_source_and_rejects_overruns() {
+        let ledger = BudgetLedger::new(temp_dir("budget-ledger").join("budget.jsonl"));
+        let budget = PrivacyBudget { epsilon: 1.0, delta: 1e-5 };
+        assert!(ledger.spent().unwrap().is_empty());
+
+        // 0.1 累加十次不精确等于 1.0，仍应允许用满预算
+        for _ in 0..10 {
+            ledger.charge(&entry("a", 0.1, 0.0), &budget).unwrap();
+        }
+        let err = ledger.charge(&entry("a", 0.01, 0.0), &budget).unwrap_err();
+        assert!(err.to_string().contains("privacy budget exceeded"));
+        ledger.charge(&entry("b", 0.5, 1e-5), &budget).unwrap();
+        assert!(ledger.charge(&entry("b", 0.1, 1e-6), &budget).is_err());
+
+        let spent = ledger.spent().unwrap();
+        assert_eq!(spent.iter().map(|s| (s.dataset.as_str(), s.releases)).collect::<Vec<_>>(), [("a", 10), ("b", 1)]);
+        assert!((spent[0].epsilon - 1.0).abs() < 1e-9);
+        assert_eq!(spent[1].delta, 1e-5);
+    }
+
+    #[test]
+    fn renamed_or_copied_data_shares_the_budget() {
+        let ledger = BudgetLedger::new(temp_dir("budget-same-data").join("budget.jsonl"));
+        let budget = PrivacyBudget { epsilon: 1.0, delta: 0.0 };
+        ledger.charge(&entry("a", 0.8, 0.0), &budget).unwrap();
+
+        // 同一文件换个名称
+        let renamed = BudgetEntry { dataset: "fresh-name".to_string(), ..entry("a", 0.5, 0.0) };
+        assert!(ledger.charge(&renamed, &budget).is_err());
+        // 同一内容复制到别处
+        let copied = BudgetEntry { source: "/tmp/copy.jsonl".to_string(), ..entry("a", 0.5, 0.0) };
+        assert!(ledger.charge(&copied, &budget).is_err());
+        // 之后追加了事件的同一文件
+        let appended = BudgetEntry { content_hash: "hash-a-appended".to_string(), ..entry("a", 0.5, 0.0) };
+        assert!(ledger.charge(&appended, &budget).is_err());
+        ledger.charge(&entry("b", 0.5, 0.0), &budget).unwrap();
+
+        // 早期没有路径和哈希的记录按名称记账
+        let legacy = BudgetEntry { source: String::new(), content_hash: String::new(), ..entry("c", 0.9, 0.0) };
+        ledger.charge(&legacy, &budget).unwrap();
+        let legacy_again = BudgetEntry { source: String::new(), content_hash: String::new(), ..entry("c", 0.2, 0.0) };
+        assert!(ledger.charge(&legacy_again, &budget).is_err());
+        assert!(ledger.charge(&entry("c", 0.2, 0.0), &budget).is_err());
+    }
+
+    #[test]
+    fn concurrent_charges_cannot_overspend() {
+        let ledger = std::sync::Arc::new(BudgetLedger::new(temp_dir("budget-concurrent").join("budget.jsonl")));
+        let budget = PrivacyBudget { epsilon: 1.0, delta: 0.0 };
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let ledger = ledger.clone();
+                std::thread::spawn(move || ledger.charge(&entry("a", 0.3, 0.0), &budget).is_ok())
+            })
+            .collect();
+        let granted = threads.into_iter().map(|t| t.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(granted, 3);
+        assert_eq!(ledger.spent().unwrap()[0].releases, 3);
+    }
+
+    fn write_events(path: &Path) {
+        let lines: Vec<String> = (0..4)
+            .map(|i| {
+                let mut event = event(&format!("s{}", i % 2), &format!("e{i}"), i * 1000);
+                event.viewport_width = Some(100);
+                event.viewport_height = Some(100);
+                serde_json::to_string(&event).unwrap() + "\n"
+            })
+            .collect();
+        std::fs::write(path, lines.concat()).unwrap();
+    }
+
+    #[test]
+    fn export_charges_the_ledger_before_releasing() {
+        let dir = temp_dir("budget-export");
+        let input = dir.join("events.jsonl");
+        write_events(&input);
+
+        let ledger = BudgetLedger::new(dir.join("budget.jsonl"));
+        let budget = PrivacyBudget { epsilon: 1.5, delta: 0.0 };
+        let options = AggregateExportOptions { dataset: "study".to_string(), grid: 4, ..Default::default() };
+
+        let release = export_aggregates(&input, &dir.join("r1.json"), &options, &ledger, &budget).unwrap();
+        assert_eq!((release.heatmap.counts.len(), release.event_rate.counts.len()), (4, DEFAULT_RATE_EDGES.len()));
+        assert!(dir.join("r1.json").exists());
+
+        // 剩余 0.5 不够第二次导出，不写出结果，也不记账
+        let err = export_aggregates(&input, &dir.join("r2.json"), &options, &ledger, &budget).unwrap_err();
+        assert!(err.to_string().contains("privacy budget exceeded"));
+        assert!(!dir.join("r2.json").exists());
+        let spent = ledger.spent().unwrap();
+        assert_eq!(spent[0].releases, 1);
+        assert_eq!(spent[0].source, std::fs::canonicalize(&input).unwrap().display().to_string());
+
+        // 复制到别处、换个名称也不能重新获得预算
+        let copy = dir.join("copy.jsonl");
+        std::fs::copy(&input, &copy).unwrap();
+        let renamed = AggregateExportOptions { dataset: "other".to_string(), ..options };
+        assert!(export_aggregates(&copy, &dir.join("r3.json"), &renamed, &ledger, &budget).is_err());
+    }
+
+    #[test]
+    fn invalid_options_are_rejected_without_charging() {
+        let dir = temp_dir("budget-invalid");
+        let ledger = BudgetLedger::new(dir.join("budget.jsonl"));
+        let budget = PrivacyBudget { epsilon: 10.0, delta: 1.0 };
+        let invalid = [
+            AggregateExportOptions { epsilon: 0.0, ..Default::default() },
+            AggregateExportOptions { mechanism: Mechanism::Gaussian, delta: 0.0, ..Default::default() },
+            AggregateExportOptions { mechanism: Mechanism::Gaussian, epsilon: 3.0, delta: 1e-6, ..Default::default() },
+            AggregateExportOptions { rate_edges: vec![1.0, 1.0], ..Default::default() },
+        ];
+        for options in &invalid {
+            assert!(export_aggregates(&dir.join("missing.jsonl"), &dir.join("out.json"), options, &ledger, &budget).is_err());
+        }
+        assert!(ledger.spent().unwrap().is_empty());
+    }
+}
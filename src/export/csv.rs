@@ -0,0 +1,238 @@
+//! 扁平 CSV 导出
+//!
+//! 表头顺序固定：先是所选的基础列（顺序同 `--columns`，缺省时同 [`super::COLUMNS`]），
+//! 再依次是启用的派生列。取值约定：
+//!
+//! - 缺失值（`None`）输出为空字段，不加引号
+//! - 空字符串输出为 `""`，以便与缺失值区分
+//! - 布尔值输出为 `true` / `false`，独热列输出为 `1` / `0`
+//! - 含逗号、引号或换行的字段按 RFC 4180 加引号转义
+
+use super::{read_events, select_columns, Column, Getter};
+use crate::encryption::Keyring;
+use crate::types::{MouseEvent, EVENT_TYPES};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default)]
+pub struct CsvExportOptions {
+    /// 要导出的基础列，为空时导出全部列
+    pub columns: Vec<String>,
+    /// `session_time_ms`：相对会话内最早事件的毫秒数
+    pub session_time: bool,
+    /// `x_norm` / `y_norm`：按视口尺寸归一化到 [0, 1] 的坐标
+    pub normalized: bool,
+    /// `is_<event_type>`：事件类型独热编码
+    pub one_hot: bool,
+    /// 解密输入中加密段的密钥
+    pub keyring: Option<Keyring>,
+}
+
+/// 写入一个字段，按需加引号
+fn write_field<W: Write>(out: &mut W, value: Option<&str>) -> std::io::Result<()> {
+    match value {
+        None => Ok(()),
+        Some("") => write!(out, "\"\""),
+        Some(v) if v.contains([',', '"', '\n', '\r']) => write!(out, "\"{}\"", v.replace('"', "\"\"")),
+        Some(v) => write!(out, "{}", v),
+    }
+}
+
+/// 将基础列的值格式化为字符串
+fn format_column(column: &Column, event: &MouseEvent) -> Option<String> {
+    match column.get {
+        Getter::Utf8(get) => get(event).map(str::to_string),
+        Getter::Int32(get) => get(event).map(|v| v.to_string()),
+        Getter::UInt16(get) => get(event).map(|v| v.to_string()),
+        Getter::UInt32(get) => get(event).map(|v| v.to_string()),
+        Getter::UInt64(get) => get(event).map(|v| v.to_string()),
+        Getter::Float64(get) => get(event).map(|v| v.to_string()),
+        Getter::Boolean(get) => get(event).map(|v| v.to_string()),
+    }
+}
+
+/// 按视口尺寸归一化坐标
+fn normalize(value: i32, extent: Option<u32>) -> Option<String> {
+    extent
+        .filter(|&e| e > 0)
+        .map(|e| (value as f64 / e as f64).to_string())
+}
+
+/// 导出的表头
+pub fn csv_header(columns: &[Column], options: &CsvExportOptions) -> Vec<String> {
+    let mut header: Vec<String> = columns.iter().map(|c| c.name.to_string()).collect();
+    if options.session_time {
+        header.push("session_time_ms".to_string());
+    }
+    if options.normalized {
+        header.push("x_norm".to_string());
+        header.push("y_norm".to_string());
+    }
+    if options.one_hot {
+        header.extend(EVENT_TYPES.iter().map(|t| format!("is_{}", t)));
+    }
+    header
+}
+
+/// 将 JSONL 事件日志导出为 CSV，返回导出的事件数
+///
+/// 启用 `session_time` 时会先扫描一遍输入以确定每个会话的起始时间。
+pub fn export_csv(
+    input: &Path,
+    output: &Path,
+    options: &CsvExportOptions,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let columns = select_columns(&options.columns)?;
+
+    let mut session_start: HashMap<String, u64> = HashMap::new();
+    if options.session_time {
+        for event in read_events(input, options.keyring.as_ref())? {
+            let event = event?;
+            session_start
+                .entry(event.session_id)
+                .and_modify(|t| *t = (*t).min(event.timestamp))
+                .or_insert(event.timestamp);
+        }
+    }
+
+    let mut out = BufWriter::new(File::create(output)?);
+    for (i, name) in csv_header(&columns, options).iter().enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write_field(&mut out, Some(name))?;
+    }
+    writeln!(out)?;
+
+    let mut count = 0;
+    for event in read_events(input, options.keyring.as_ref())? {
+        let event = event?;
+        let mut fields: Vec<Option<String>> = columns.iter().map(|c| format_column(c, &event)).collect();
+
+        if options.session_time {
+            let start = session_start.get(&event.session_id).copied().unwrap_or(event.timestamp);
+            fields.push(Some((event.timestamp - start).to_string()));
+        }
+        if options.normalized {
+            fields.push(normalize(event.x, event.viewport_width));
+            fields.push(normalize(event.y, event.viewport_height));
+        }
+        if options.one_hot {
+            fields.extend(
+                EVENT_TYPES
+                    .iter()
+                    .map(|t| Some(if *t == event.event_type { "1" } else { "0" }.to_string())),
+            );
+        }
+
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write_field(&mut out, field.as_deref())?;
+        }
+        writeln!(out)?;
+        count += 1;
+    }
+
+    out.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{event, temp_dir};
+
+    fn export(events: &[MouseEvent], options: &CsvExportOptions) -> Vec<String> {
+        let dir = temp_dir("csv");
+        let input = dir.join("events.jsonl");
+        let lines: Vec<String> = events.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        std::fs::write(&input, lines.concat()).unwrap();
+
+        let output = dir.join("events.csv");
+        assert_eq!(export_csv(&input, &output, options).unwrap(), events.len());
+        std::fs::read_to_string(output).unwrap().lines().map(String::from).collect()
+    }
+
+    fn columns(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn selected_columns_keep_their_order_and_quote_fields() {
+        let mut quoted = event("s1", "e1", 5);
+        quoted.target_text = Some("say \"hi\", then\nleave".to_string());
+        quoted.target_id = Some(String::new());
+        quoted.ctrl_key = Some(true);
+        let plain = event("s1", "e2", 6);
+
+        let options = CsvExportOptions { columns: columns(&["event_id", "target_text", "target_id", "ctrl_key", "x"]), ..Default::default() };
+        let lines = export(&[quoted, plain], &options);
+        assert_eq!(lines[0], "event_id,target_text,target_id,ctrl_key,x");
+        // 字段内的换行使一条记录跨两行
+        assert_eq!(lines[1], "e1,\"say \"\"hi\"\", then");
+        assert_eq!(lines[2], "leave\",\"\",true,10");
+        // 缺失值为空字段，空字符串为 ""
+        assert_eq!(lines[3], "e2,,,,10");
+    }
+
+    #[test]
+    fn default_columns_follow_the_shared_schema() {
+        let lines = export(&[event("s1", "e1", 5)], &CsvExportOptions::default());
+        let header: Vec<&str> = lines[0].split(',').collect();
+        assert_eq!(header, super::super::COLUMNS.iter().map(|c| c.name).collect::<Vec<_>>());
+        assert_eq!(lines[1].split(',').count(), header.len());
+    }
+
+    #[test]
+    fn derived_columns_are_appended_after_the_base_columns() {
+        let mut first = event("s1", "e1", 1000);
+        first.viewport_width = Some(40);
+        first.viewport_height = Some(80);
+        let mut click = event("s1", "e2", 1250);
+        click.event_type = "click".to_string();
+        click.viewport_width = Some(0);
+        // 会话内最早的事件在后面出现
+        let earlier = event("s1", "e0", 900);
+        let other = event("s2", "e3", 5000);
+
+        let options = CsvExportOptions {
+            columns: columns(&["event_id"]),
+            session_time: true,
+            normalized: true,
+            one_hot: true,
+            ..Default::default()
+        };
+        let lines = export(&[first, click, earlier, other], &options);
+        let header: Vec<&str> = lines[0].split(',').collect();
+        assert_eq!(header[..4], ["event_id", "session_time_ms", "x_norm", "y_norm"]);
+        assert_eq!(header[4..], EVENT_TYPES.iter().map(|t| format!("is_{}", t)).collect::<Vec<_>>());
+
+        let row = |i: usize| lines[i].split(',').map(String::from).collect::<Vec<_>>();
+        let hot = |row: &[String], event_type: &str| {
+            let i = header.iter().position(|h| *h == format!("is_{}", event_type)).unwrap();
+            assert_eq!(row[4..].iter().filter(|v| *v == "1").count(), 1);
+            row[i].clone()
+        };
+        assert_eq!(row(1)[..4], ["e1", "100", "0.25", "0.25"]);
+        assert_eq!(hot(&row(1), "mousemove"), "1");
+        // 视口宽度为 0 或缺失时不归一化
+        assert_eq!(row(2)[..4], ["e2", "350", "", ""]);
+        assert_eq!(hot(&row(2), "click"), "1");
+        assert_eq!(row(3)[1], "0");
+        assert_eq!(row(4)[1], "0");
+    }
+
+    #[test]
+    fn unknown_columns_are_rejected() {
+        let dir = temp_dir("csv-unknown");
+        let input = dir.join("events.jsonl");
+        std::fs::write(&input, "").unwrap();
+        let options = CsvExportOptions { columns: columns(&["nope"]), ..Default::default() };
+        let err = export_csv(&input, &dir.join("out.csv"), &options).unwrap_err();
+        assert!(err.to_string().contains("unknown column: nope"));
+    }
+}
@@ -0,0 +1,188 @@
+//! 事件日志导出
+//!
+//! 所有导出器共享同一张列定义表 [`COLUMNS`]，保证不同格式之间的列名、类型和含义一致。
+
+pub mod aggregate;
+pub mod csv;
+pub mod parquet;
+
+use crate::encryption::Keyring;
+use crate::log_reader::LogReader;
+use crate::types::MouseEvent;
+use std::path::Path;
+
+/// 列的取值函数，变体决定了导出时的列类型
+#[derive(Clone, Copy)]
+pub enum Getter {
+    Utf8(for<'a> fn(&'a MouseEvent) -> Option<&'a str>),
+    Int32(fn(&MouseEvent) -> Option<i32>),
+    UInt16(fn(&MouseEvent) -> Option<u16>),
+    UInt32(fn(&MouseEvent) -> Option<u32>),
+    UInt64(fn(&MouseEvent) -> Option<u64>),
+    Float64(fn(&MouseEvent) -> Option<f64>),
+    Boolean(fn(&MouseEvent) -> Option<bool>),
+}
+
+/// 一列的定义
+#[derive(Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub nullable: bool,
+    pub doc: &'static str,
+    pub get: Getter,
+}
+
+impl Column {
+    /// 列类型的名称（用于文档输出）
+    pub fn type_name(&self) -> &'static str {
+        match self.get {
+            Getter::Utf8(_) => "utf8",
+            Getter::Int32(_) => "int32",
+            Getter::UInt16(_) => "uint16",
+            Getter::UInt32(_) => "uint32",
+            Getter::UInt64(_) => "uint64",
+            Getter::Float64(_) => "float64",
+            Getter::Boolean(_) => "boolean",
+        }
+    }
+}
+
+/// 由 `MouseEvent` 派生的列定义，顺序即默认导出顺序
+pub const COLUMNS: &[Column] = &[
+    Column { name: "event_type", nullable: false, doc: "事件类型，如 mousemove、keydown", get: Getter::Utf8(|e| Some(e.event_type.as_str())) },
+    Column { name: "timestamp", nullable: false, doc: "事件时间（Unix 毫秒）", get: Getter::UInt64(|e| Some(e.timestamp)) },
+    Column { name: "event_timestamp", nullable: true, doc: "单调时钟时间（相对 time_origin 的毫秒，带小数）", get: Getter::Float64(|e| e.event_timestamp) },
+    Column { name: "time_origin", nullable: true, doc: "页面的 performance.timeOrigin（Unix 毫秒）", get: Getter::Float64(|e| e.time_origin) },
+    Column { name: "x", nullable: false, doc: "客户端 X 坐标（像素）", get: Getter::Int32(|e| Some(e.x)) },
+    Column { name: "y", nullable: false, doc: "客户端 Y 坐标（像素）", get: Getter::Int32(|e| Some(e.y)) },
+    Column { name: "screen_x", nullable: true, doc: "屏幕 X 坐标", get: Getter::Int32(|e| e.screen_x) },
+    Column { name: "screen_y", nullable: true, doc: "屏幕 Y 坐标", get: Getter::Int32(|e| e.screen_y) },
+    Column { name: "page_x", nullable: true, doc: "页面 X 坐标", get: Getter::Int32(|e| e.page_x) },
+    Column { name: "page_y", nullable: true, doc: "页面 Y 坐标", get: Getter::Int32(|e| e.page_y) },
+    Column { name: "offset_x", nullable: true, doc: "相对目标元素的 X 坐标", get: Getter::Int32(|e| e.offset_x) },
+    Column { name: "offset_y", nullable: true, doc: "相对目标元素的 Y 坐标", get: Getter::Int32(|e| e.offset_y) },
+    Column { name: "movement_x", nullable: true, doc: "相对上一个事件的 X 移动量", get: Getter::Int32(|e| e.movement_x) },
+    Column { name: "movement_y", nullable: true, doc: "相对上一个事件的 Y 移动量", get: Getter::Int32(|e| e.movement_y) },
+    Column { name: "button", nullable: true, doc: "按下的按钮：left、middle、right", get: Getter::Utf8(|e| e.button.as_deref()) },
+    Column { name: "buttons", nullable: true, doc: "按钮状态位掩码：1=左键, 2=右键, 4=中键", get: Getter::UInt16(|e| e.buttons) },
+    Column { name: "detail", nullable: true, doc: "点击类事件的连续点击次数", get: Getter::Int32(|e| e.detail) },
+    Column { name: "pointer_type", nullable: true, doc: "指针类型：mouse、pen、touch", get: Getter::Utf8(|e| e.pointer_type.as_deref()) },
+    Column { name: "pointer_id", nullable: true, doc: "指针 ID，同一次触摸或笔接触期间不变", get: Getter::Int32(|e| e.pointer_id) },
+    Column { name: "pressure", nullable: true, doc: "压力 0..1", get: Getter::Float64(|e| e.pressure) },
+    Column { name: "tangential_pressure", nullable: true, doc: "切向压力 -1..1", get: Getter::Float64(|e| e.tangential_pressure) },
+    Column { name: "tilt_x", nullable: true, doc: "笔的 X 方向倾斜角度（度）", get: Getter::Int32(|e| e.tilt_x) },
+    Column { name: "tilt_y", nullable: true, doc: "笔的 Y 方向倾斜角度（度）", get: Getter::Int32(|e| e.tilt_y) },
+    Column { name: "twist", nullable: true, doc: "笔绕自身轴的旋转（度）", get: Getter::Int32(|e| e.twist) },
+    Column { name: "width", nullable: true, doc: "接触区域宽度（CSS 像素）", get: Getter::Float64(|e| e.width) },
+    Column { name: "height", nullable: true, doc: "接触区域高度（CSS 像素）", get: Getter::Float64(|e| e.height) },
+    Column { name: "is_primary", nullable: true, doc: "是否为主指针", get: Getter::Boolean(|e| e.is_primary) },
+    Column { name: "scroll_y", nullable: true, doc: "滚轮垂直滚动量", get: Getter::Float64(|e| e.scroll_y) },
+    Column { name: "scroll_x", nullable: true, doc: "滚轮水平滚动量", get: Getter::Float64(|e| e.scroll_x) },
+    Column { name: "target", nullable: true, doc: "目标元素描述", get: Getter::Utf8(|e| e.target.as_deref()) },
+    Column { name: "target_tag", nullable: true, doc: "目标元素标签名", get: Getter::Utf8(|e| e.target_tag.as_deref()) },
+    Column { name: "target_id", nullable: true, doc: "目标元素 ID", get: Getter::Utf8(|e| e.target_id.as_deref()) },
+    Column { name: "target_class", nullable: true, doc: "目标元素类名", get: Getter::Utf8(|e| e.target_class.as_deref()) },
+    Column { name: "target_text", nullable: true, doc: "目标元素文本（截断）", get: Getter::Utf8(|e| e.target_text.as_deref()) },
+    Column { name: "session_id", nullable: false, doc: "会话 ID", get: Getter::Utf8(|e| Some(e.session_id.as_str())) },
+    Column { name: "event_id", nullable: false, doc: "事件唯一 ID", get: Getter::Utf8(|e| Some(e.event_id.as_str())) },
+    Column { name: "parent_event_id", nullable: true, doc: "父事件 ID（拖拽等序列事件）", get: Getter::Utf8(|e| e.parent_event_id.as_deref()) },
+    Column { name: "participant_id", nullable: true, doc: "参与者假名，跨会话稳定", get: Getter::Utf8(|e| e.participant_id.as_deref()) },
+    Column { name: "origin", nullable: true, doc: "提交页面的来源", get: Getter::Utf8(|e| e.origin.as_deref()) },
+    Column { name: "velocity_x", nullable: true, doc: "X 轴速度（像素/毫秒）", get: Getter::Float64(|e| e.velocity_x) },
+    Column { name: "velocity_y", nullable: true, doc: "Y 轴速度（像素/毫秒）", get: Getter::Float64(|e| e.velocity_y) },
+    Column { name: "distance", nullable: true, doc: "距上一个事件点的距离（像素）", get: Getter::Float64(|e| e.distance) },
+    Column { name: "key", nullable: true, doc: "按键名称", get: Getter::Utf8(|e| e.key.as_deref()) },
+    Column { name: "code", nullable: true, doc: "按键代码", get: Getter::Utf8(|e| e.code.as_deref()) },
+    Column { name: "ctrl_key", nullable: true, doc: "Ctrl 是否按下", get: Getter::Boolean(|e| e.ctrl_key) },
+    Column { name: "shift_key", nullable: true, doc: "Shift 是否按下", get: Getter::Boolean(|e| e.shift_key) },
+    Column { name: "alt_key", nullable: true, doc: "Alt 是否按下", get: Getter::Boolean(|e| e.alt_key) },
+    Column { name: "meta_key", nullable: true, doc: "Meta 是否按下", get: Getter::Boolean(|e| e.meta_key) },
+    Column { name: "key_class", nullable: true, doc: "按键类别：letter、digit、navigation、modifier 等", get: Getter::Utf8(|e| e.key_class.as_deref()) },
+    Column { name: "touch_count", nullable: true, doc: "触摸事件中的触点数（触点详情只保存在 JSONL 中）", get: Getter::UInt32(|e| e.touches.as_ref().map(|t| t.len() as u32)) },
+    Column { name: "gesture_scale", nullable: true, doc: "pinch 手势的缩放比例", get: Getter::Float64(|e| e.gesture_scale) },
+    Column { name: "gesture_rotation", nullable: true, doc: "rotate 手势的旋转角度（度）", get: Getter::Float64(|e| e.gesture_rotation) },
+    Column { name: "swipe_direction", nullable: true, doc: "swipe 手势方向：left、right、up、down", get: Getter::Utf8(|e| e.swipe_direction.as_deref()) },
+    Column { name: "coalesced", nullable: true, doc: "是否为同一帧内被合并的指针采样", get: Getter::Boolean(|e| e.coalesced) },
+    Column { name: "predicted", nullable: true, doc: "是否为浏览器预测的指针位置", get: Getter::Boolean(|e| e.predicted) },
+    Column { name: "visibility_state", nullable: true, doc: "visibilitychange 之后的页面可见性：visible、hidden", get: Getter::Utf8(|e| e.visibility_state.as_deref()) },
+    Column { name: "pointer_locked", nullable: true, doc: "pointerlockchange 之后指针是否锁定", get: Getter::Boolean(|e| e.pointer_locked) },
+    Column { name: "coordinate_mode", nullable: true, doc: "坐标含义：absolute 或 virtual（指针锁定期间的虚拟光标）", get: Getter::Utf8(|e| e.coordinate_mode.as_deref()) },
+    Column { name: "in_tracking_area", nullable: true, doc: "事件是否发生在追踪区域内", get: Getter::Boolean(|e| e.in_tracking_area) },
+    Column { name: "viewport_width", nullable: true, doc: "视口宽度（像素）", get: Getter::UInt32(|e| e.viewport_width) },
+    Column { name: "viewport_height", nullable: true, doc: "视口高度（像素）", get: Getter::UInt32(|e| e.viewport_height) },
+    Column { name: "device_pixel_ratio", nullable: true, doc: "设备像素比", get: Getter::Float64(|e| e.device_pixel_ratio) },
+    Column { name: "scroll_offset_x", nullable: true, doc: "页面水平滚动位置（像素）", get: Getter::Float64(|e| e.scroll_offset_x) },
+    Column { name: "scroll_offset_y", nullable: true, doc: "页面垂直滚动位置（像素）", get: Getter::Float64(|e| e.scroll_offset_y) },
+    Column { name: "metadata", nullable: true, doc: "额外元数据（JSON 字符串）", get: Getter::Utf8(|e| e.metadata.as_deref()) },
+];
+
+/// 按名称选择列；`names` 为空时返回全部列
+pub fn select_columns(names: &[String]) -> Result<Vec<Column>, Box<dyn std::error::Error>> {
+    if names.is_empty() {
+        return Ok(COLUMNS.to_vec());
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            COLUMNS
+                .iter()
+                .find(|c| c.name == name)
+                .copied()
+                .ok_or_else(|| format!("unknown column: {}", name).into())
+        })
+        .collect()
+}
+
+/// 逐行读取 JSONL 事件日志
+///
+/// 遇到坏行时返回带行号和字节偏移的错误；需要跳过坏行时先用 `mouse-tracker repair` 修复，
+/// 或直接使用 [`LogReader`]。给定密钥时加密段被透明解密。
+pub fn read_events(
+    path: &Path,
+    keyring: Option<&Keyring>,
+) -> Result<impl Iterator<Item = Result<MouseEvent, Box<dyn std::error::Error>>>, Box<dyn std::error::Error>> {
+    let path = path.to_path_buf();
+
+    Ok(LogReader::open(&path)?.with_keyring(keyring).map(move |item| {
+        item.map(|record| record.event).map_err(|diagnostic| {
+            format!("{}: {} (run `mouse-tracker repair` to quarantine bad lines)", path.display(), diagnostic).into()
+        })
+    }))
+}
+
+/// 将 UTC 日期（YYYY-MM-DD）转换为当天零点的 Unix 毫秒时间戳
+pub fn parse_utc_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Howard Hinnant 的 days_from_civil 算法
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    u64::try_from(days).ok().map(|d| d * 86_400_000)
+}
+
+/// 将 Unix 毫秒时间戳转换为 UTC 日期（YYYY-MM-DD）
+pub fn utc_date(timestamp_ms: u64) -> String {
+    // Howard Hinnant 的 civil_from_days 算法
+    let days = (timestamp_ms / 86_400_000) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,424 @@
+//! Parquet 列式导出
+//!
+//! 列定义来自 [`super::COLUMNS`]，每列的说明写入 Arrow 字段元数据的 `description` 键，
+//! 可直接用 `polars.read_parquet` / `pyarrow.parquet.read_schema` 查看。
+
+use super::{read_events, select_columns, utc_date, Column, Getter};
+use crate::encryption::Keyring;
+use crate::types::MouseEvent;
+use arrow::array::{
+    ArrayRef, BooleanBuilder, Float64Builder, Int32Builder, StringBuilder, UInt16Builder,
+    UInt32Builder, UInt64Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::record_batch::RecordBatch;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 输出分区方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionBy {
+    /// 输出单个文件
+    None,
+    /// 按会话输出到 `session_id=<id>/part-0.parquet`
+    Session,
+    /// 按 UTC 日期输出到 `date=YYYY-MM-DD/part-0.parquet`
+    Date,
+}
+
+impl std::str::FromStr for PartitionBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "session" => Ok(Self::Session),
+            "date" => Ok(Self::Date),
+            other => Err(format!("unknown partition mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParquetExportOptions {
+    /// 要导出的列名，为空时导出全部列
+    pub columns: Vec<String>,
+    /// 每个 row group 的最大行数
+    pub row_group_size: usize,
+    pub partition_by: PartitionBy,
+    /// 同时打开的分区文件上限，超过时关闭最久未写入的分区，该分区再出现时写入新的 `part-<n>.parquet`
+    pub max_open_partitions: usize,
+    /// 解密输入中加密段的密钥
+    pub keyring: Option<Keyring>,
+}
+
+impl Default for ParquetExportOptions {
+    fn default() -> Self {
+        Self {
+            columns: Vec::new(),
+            row_group_size: 64 * 1024,
+            partition_by: PartitionBy::None,
+            max_open_partitions: 64,
+            keyring: None,
+        }
+    }
+}
+
+/// 导出结果统计
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub events: usize,
+    pub files: Vec<PathBuf>,
+}
+
+/// 由列定义生成 Arrow schema
+pub fn arrow_schema(columns: &[Column]) -> SchemaRef {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|c| {
+            let data_type = match c.get {
+                Getter::Utf8(_) => DataType::Utf8,
+                Getter::Int32(_) => DataType::Int32,
+                Getter::UInt16(_) => DataType::UInt16,
+                Getter::UInt32(_) => DataType::UInt32,
+                Getter::UInt64(_) => DataType::UInt64,
+                Getter::Float64(_) => DataType::Float64,
+                Getter::Boolean(_) => DataType::Boolean,
+            };
+            Field::new(c.name, data_type, c.nullable).with_metadata(HashMap::from([(
+                "description".to_string(),
+                c.doc.to_string(),
+            )]))
+        })
+        .collect();
+
+    Arc::new(Schema::new(fields))
+}
+
+/// 将一批事件转换为 RecordBatch
+fn to_record_batch(
+    schema: &SchemaRef,
+    columns: &[Column],
+    events: &[MouseEvent],
+) -> Result<RecordBatch, Box<dyn std::error::Error>> {
+    let arrays: Vec<ArrayRef> = columns
+        .iter()
+        .map(|c| -> ArrayRef {
+            match c.get {
+                Getter::Utf8(get) => {
+                    let mut b = StringBuilder::new();
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::Int32(get) => {
+                    let mut b = Int32Builder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::UInt16(get) => {
+                    let mut b = UInt16Builder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::UInt32(get) => {
+                    let mut b = UInt32Builder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::UInt64(get) => {
+                    let mut b = UInt64Builder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::Float64(get) => {
+                    let mut b = Float64Builder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+                Getter::Boolean(get) => {
+                    let mut b = BooleanBuilder::with_capacity(events.len());
+                    events.iter().for_each(|e| b.append_option(get(e)));
+                    Arc::new(b.finish())
+                }
+            }
+        })
+        .collect();
+
+    Ok(RecordBatch::try_new(schema.clone(), arrays)?)
+}
+
+/// 将分区键转换为安全的目录名
+fn partition_dir(prefix: &str, value: &str) -> String {
+    let safe: String = value
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+    format!("{}={}", prefix, safe)
+}
+
+/// 单个分区的写入状态
+struct PartitionWriter {
+    writer: ArrowWriter<File>,
+    pending: Vec<MouseEvent>,
+    /// 最后写入的事件序号，用于选择要关闭的分区
+    last_used: usize,
+}
+
+impl PartitionWriter {
+    /// 写出缓冲中的事件并关闭文件
+    fn close(mut self, schema: &SchemaRef, columns: &[Column]) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.pending.is_empty() {
+            let batch = to_record_batch(schema, columns, &self.pending)?;
+            self.writer.write(&batch)?;
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// 将 JSONL 事件日志导出为 Parquet
+///
+/// 不分区时 `output` 为目标文件路径，分区时为输出目录。
+/// 事件按分区缓冲，满一个 row group 即写出；同时打开的分区不超过 `max_open_partitions`，
+/// 内存占用和文件描述符数与该上限成正比。
+pub fn export_parquet(
+    input: &Path,
+    output: &Path,
+    options: &ParquetExportOptions,
+) -> Result<ExportSummary, Box<dyn std::error::Error>> {
+    let columns = select_columns(&options.columns)?;
+    let schema = arrow_schema(&columns);
+    let row_group_size = options.row_group_size.max(1);
+    let props = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .set_compression(Compression::SNAPPY)
+        .build();
+
+    let max_open = options.max_open_partitions.max(1);
+    let mut writers: HashMap<String, PartitionWriter> = HashMap::new();
+    // 每个分区已创建的文件数
+    let mut parts: HashMap<String, usize> = HashMap::new();
+    let mut summary = ExportSummary::default();
+
+    for (index, event) in read_events(input, options.keyring.as_ref())?.enumerate() {
+        let event = event?;
+        let key = match options.partition_by {
+            PartitionBy::None => String::new(),
+            PartitionBy::Session => partition_dir("session_id", &event.session_id),
+            PartitionBy::Date => partition_dir("date", &utc_date(event.timestamp)),
+        };
+
+        if !writers.contains_key(&key) {
+            if writers.len() >= max_open {
+                let oldest = writers
+                    .iter()
+                    .min_by_key(|(_, w)| w.last_used)
+                    .map(|(k, _)| k.clone())
+                    .expect("writers is not empty");
+                writers.remove(&oldest).expect("oldest writer exists").close(&schema, &columns)?;
+            }
+
+            let part = parts.entry(key.clone()).or_insert(0);
+            let path = if options.partition_by == PartitionBy::None {
+                output.to_path_buf()
+            } else {
+                output.join(&key).join(format!("part-{}.parquet", part))
+            };
+            *part += 1;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let writer = ArrowWriter::try_new(File::create(&path)?, schema.clone(), Some(props.clone()))?;
+            summary.files.push(path);
+            writers.insert(key.clone(), PartitionWriter { writer, pending: Vec::new(), last_used: index });
+        }
+
+        let partition = writers.get_mut(&key).expect("partition writer just inserted");
+        partition.last_used = index;
+        partition.pending.push(event);
+        summary.events += 1;
+
+        if partition.pending.len() >= row_group_size {
+            let batch = to_record_batch(&schema, &columns, &partition.pending)?;
+            partition.writer.write(&batch)?;
+            partition.pending.clear();
+        }
+    }
+
+    // 没有事件时仍输出一个只含 schema 的文件，方便下游按固定路径读取
+    if writers.is_empty() && options.partition_by == PartitionBy::None {
+        ArrowWriter::try_new(File::create(output)?, schema.clone(), Some(props))?.close()?;
+        summary.files.push(output.to_path_buf());
+    }
+
+    for (_, partition) in writers {
+        partition.close(&schema, &columns)?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{event, temp_dir};
+    use arrow::array::{Array, StringArray, UInt32Array};
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const DAY_MS: u64 = 86_400_000;
+
+    fn write_input(dir: &Path, events: &[MouseEvent]) -> PathBuf {
+        let input = dir.join("events.jsonl");
+        let lines: Vec<String> = events.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        fs::write(&input, lines.concat()).unwrap();
+        input
+    }
+
+    /// 读回一个文件的全部行，同时返回 row group 数
+    fn read_back(path: &Path) -> (SchemaRef, Vec<RecordBatch>, usize) {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let schema = builder.schema().clone();
+        let row_groups = builder.metadata().num_row_groups();
+        let batches = builder.build().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        (schema, batches, row_groups)
+    }
+
+    fn event_ids(batches: &[RecordBatch]) -> Vec<String> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let ids = b.column_by_name("event_id").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+                (0..ids.len()).map(|i| ids.value(i).to_string()).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn relative(dir: &Path, files: &[PathBuf]) -> Vec<String> {
+        let mut names: Vec<String> = files.iter().map(|f| f.strip_prefix(dir).unwrap().display().to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn single_file_round_trips_with_field_descriptions() {
+        let dir = temp_dir("parquet");
+        let mut first = event("s1", "e1", 5);
+        first.viewport_width = Some(1280);
+        let events = [first, event("s1", "e2", 6), event("s1", "e3", 7)];
+        let input = write_input(&dir, &events);
+        let output = dir.join("events.parquet");
+
+        let options = ParquetExportOptions { row_group_size: 2, ..Default::default() };
+        let summary = export_parquet(&input, &output, &options).unwrap();
+        assert_eq!(summary.events, 3);
+        assert_eq!(summary.files, vec![output.clone()]);
+
+        let (schema, batches, row_groups) = read_back(&output);
+        let columns = select_columns(&[]).unwrap();
+        assert_eq!(schema.fields().len(), columns.len());
+        for (field, column) in schema.fields().iter().zip(&columns) {
+            assert_eq!(field.name(), column.name);
+            assert_eq!(field.is_nullable(), column.nullable);
+            assert_eq!(field.metadata().get("description").map(String::as_str), Some(column.doc));
+        }
+        assert_eq!(row_groups, 2);
+        assert_eq!(event_ids(&batches), ["e1", "e2", "e3"]);
+
+        let widths = batches[0].column_by_name("viewport_width").unwrap().as_any().downcast_ref::<UInt32Array>().unwrap();
+        assert_eq!(widths.value(0), 1280);
+        assert!(widths.is_null(1));
+    }
+
+    #[test]
+    fn selected_columns_keep_their_order() {
+        let dir = temp_dir("parquet-columns");
+        let input = write_input(&dir, &[event("s1", "e1", 5)]);
+        let output = dir.join("events.parquet");
+        let columns = vec!["x".to_string(), "event_id".to_string()];
+        export_parquet(&input, &output, &ParquetExportOptions { columns, ..Default::default() }).unwrap();
+
+        let (schema, batches, _) = read_back(&output);
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        assert_eq!(names, ["x", "event_id"]);
+        assert_eq!(event_ids(&batches), ["e1"]);
+
+        let columns = vec!["nope".to_string()];
+        assert!(export_parquet(&input, &output, &ParquetExportOptions { columns, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn empty_input_still_writes_a_schema_only_file() {
+        let dir = temp_dir("parquet-empty");
+        let input = write_input(&dir, &[]);
+        let output = dir.join("events.parquet");
+        let summary = export_parquet(&input, &output, &ParquetExportOptions::default()).unwrap();
+        assert_eq!(summary.events, 0);
+
+        let (schema, batches, _) = read_back(&output);
+        assert_eq!(schema.fields().len(), select_columns(&[]).unwrap().len());
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+    }
+
+    #[test]
+    fn partitions_by_session_and_by_date() {
+        let dir = temp_dir("parquet-partitions");
+        let events = [
+            event("s1", "e1", 5),
+            event("s/2", "e2", DAY_MS + 5),
+            event("s1", "e3", DAY_MS + 6),
+        ];
+        let input = write_input(&dir, &events);
+
+        let out = dir.join("by-session");
+        let options = ParquetExportOptions { partition_by: PartitionBy::Session, ..Default::default() };
+        let summary = export_parquet(&input, &out, &options).unwrap();
+        // 会话 id 中的路径分隔符被替换
+        assert_eq!(relative(&out, &summary.files), ["session_id=s1/part-0.parquet", "session_id=s_2/part-0.parquet"]);
+        assert_eq!(event_ids(&read_back(&out.join("session_id=s1/part-0.parquet")).1), ["e1", "e3"]);
+
+        let out = dir.join("by-date");
+        let options = ParquetExportOptions { partition_by: PartitionBy::Date, ..Default::default() };
+        let summary = export_parquet(&input, &out, &options).unwrap();
+        assert_eq!(relative(&out, &summary.files), ["date=1970-01-01/part-0.parquet", "date=1970-01-02/part-0.parquet"]);
+        assert_eq!(event_ids(&read_back(&out.join("date=1970-01-02/part-0.parquet")).1), ["e2", "e3"]);
+    }
+
+    #[test]
+    fn evicted_partitions_continue_in_a_new_part_file() {
+        let dir = temp_dir("parquet-evict");
+        let events = [
+            event("a", "e1", 1),
+            event("b", "e2", 2),
+            event("a", "e3", 3),
+            event("c", "e4", 4),
+            event("b", "e5", 5),
+        ];
+        let input = write_input(&dir, &events);
+        let out = dir.join("out");
+        let options = ParquetExportOptions { partition_by: PartitionBy::Session, max_open_partitions: 2, ..Default::default() };
+        let summary = export_parquet(&input, &out, &options).unwrap();
+        assert_eq!(summary.events, 5);
+
+        // c 出现时关闭最久未写入的 b，b 再出现时写入 part-1
+        assert_eq!(
+            relative(&out, &summary.files),
+            ["session_id=a/part-0.parquet", "session_id=b/part-0.parquet", "session_id=b/part-1.parquet", "session_id=c/part-0.parquet"]
+        );
+        assert_eq!(event_ids(&read_back(&out.join("session_id=a/part-0.parquet")).1), ["e1", "e3"]);
+        assert_eq!(event_ids(&read_back(&out.join("session_id=b/part-0.parquet")).1), ["e2"]);
+        assert_eq!(event_ids(&read_back(&out.join("session_id=b/part-1.parquet")).1), ["e5"]);
+    }
+
+    #[test]
+    fn partition_modes_parse() {
+        assert_eq!("session".parse::<PartitionBy>(), Ok(PartitionBy::Session));
+        assert_eq!("date".parse::<PartitionBy>(), Ok(PartitionBy::Date));
+        assert_eq!("none".parse::<PartitionBy>(), Ok(PartitionBy::None));
+        assert!("hour".parse::<PartitionBy>().is_err());
+    }
+}
@@ -1,9 +1,55 @@
 pub mod app;
+pub mod tracker;
 pub mod types;
 
+#[cfg(feature = "ssr")]
+pub mod config;
+
+#[cfg(feature = "ssr")]
+pub mod audit;
+
+#[cfg(feature = "ssr")]
+pub mod consent;
+
+#[cfg(feature = "ssr")]
+pub mod pseudonym;
+
 #[cfg(feature = "ssr")]
 pub mod mouse_handler;
 
+#[cfg(feature = "ssr")]
+pub mod cors;
+
+#[cfg(feature = "ssr")]
+pub mod projects;
+
+#[cfg(feature = "ssr")]
+pub mod redaction;
+
+#[cfg(feature = "ssr")]
+pub mod retention;
+
+#[cfg(feature = "ssr")]
+pub mod encryption;
+
+#[cfg(feature = "ssr")]
+pub mod chain;
+
+#[cfg(feature = "ssr")]
+pub mod log_reader;
+
+#[cfg(feature = "ssr")]
+pub mod export;
+
+#[cfg(feature = "ssr")]
+pub mod merge;
+
+#[cfg(feature = "ssr")]
+pub mod cli;
+
+#[cfg(all(test, feature = "ssr"))]
+mod test_util;
+
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
 pub fn hydrate() {
@@ -0,0 +1,320 @@
+//! 容错的事件日志读取
+//!
+//! [`LogReader`] 逐行解析 JSONL，好的行产出 [`LogRecord`]，坏的行产出带行号、
+//! 字节偏移和错误类别的 [`LineDiagnostic`]，单个坏行不会中断后续读取。
+//! [`repair`] 基于它把好记录复制到新文件、坏记录写入隔离文件。
+//!
+//! 给定密钥时加密段被透明解密，段中的每条记录共享段所在行的行号和字节偏移。
+
+use crate::chain::is_chain_line;
+use crate::encryption::{is_segment, Keyring, SegmentWriter};
+use crate::types::MouseEvent;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// 坏行的错误类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// 不是合法的 UTF-8
+    InvalidUtf8,
+    /// 不是合法的 JSON
+    Syntax,
+    /// JSON 在行内提前结束（通常是写入被截断）
+    Truncated,
+    /// JSON 合法但不符合 `MouseEvent` 的结构
+    Schema,
+    /// 读取文件时发生 I/O 错误，之后的内容不再读取
+    Io,
+    /// 加密段无法解密（没有密钥、密钥 ID 未知或认证失败）
+    Decrypt,
+}
+
+/// 一条解析成功的记录
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// 行号（从 1 开始）
+    pub line: usize,
+    /// 该行起始的字节偏移
+    pub byte_offset: u64,
+    pub event: MouseEvent,
+    /// 原始行内容（不含换行符；加密段中的记录为解密后的行）
+    pub raw: Vec<u8>,
+}
+
+/// 一个坏行的诊断信息
+#[derive(Debug, Clone, Serialize)]
+pub struct LineDiagnostic {
+    pub line: usize,
+    pub byte_offset: u64,
+    pub kind: ErrorKind,
+    pub message: String,
+    /// 原始行内容（非 UTF-8 字节以替换字符显示）
+    pub raw: String,
+    /// 原始行的字节（不含换行符），需要原样写回时使用
+    #[serde(skip)]
+    pub bytes: Vec<u8>,
+}
+
+impl std::fmt::Display for LineDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} (byte {}): {:?}: {}",
+            self.line, self.byte_offset, self.kind, self.message
+        )
+    }
+}
+
+impl std::error::Error for LineDiagnostic {}
+
+/// 逐行读取事件日志，跳过空行和哈希链记录
+pub struct LogReader<R> {
+    inner: R,
+    line: usize,
+    offset: u64,
+    failed: bool,
+    keyring: Option<Keyring>,
+    /// 当前加密段中尚未产出的记录
+    pending: VecDeque<Result<LogRecord, LineDiagnostic>>,
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> LogReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: 0,
+            offset: 0,
+            failed: false,
+            keyring: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// 使用密钥解密加密段；没有密钥时加密段报告为 [`ErrorKind::Decrypt`]
+    pub fn with_keyring(mut self, keyring: Option<&Keyring>) -> Self {
+        self.keyring = keyring.cloned();
+        self
+    }
+
+    /// 解密一个加密段，把其中每一行的解析结果放入 `pending`
+    fn open_segment(&mut self, raw: Vec<u8>) {
+        let opened = match &self.keyring {
+            Some(keyring) => std::str::from_utf8(&raw)
+                .map_err(|e| e.to_string())
+                .and_then(|text| keyring.open(text)),
+            None => Err("encrypted segment but no key file given".to_string()),
+        };
+
+        match opened {
+            Ok(plaintext) => {
+                let items: Vec<_> = plaintext
+                    .split(|b| *b == b'\n')
+                    .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+                    .map(|line| self.parse(line.to_vec()))
+                    .collect();
+                self.pending.extend(items);
+            }
+            Err(message) => self.pending.push_back(Err(LineDiagnostic {
+                line: self.line,
+                byte_offset: self.offset,
+                kind: ErrorKind::Decrypt,
+                message,
+                raw: String::from_utf8_lossy(&raw).into_owned(),
+                bytes: raw,
+            })),
+        }
+    }
+
+    fn parse(&self, raw: Vec<u8>) -> Result<LogRecord, LineDiagnostic> {
+        let diagnostic = |kind, message: String, raw: &[u8]| LineDiagnostic {
+            line: self.line,
+            byte_offset: self.offset,
+            kind,
+            message,
+            raw: String::from_utf8_lossy(raw).into_owned(),
+            bytes: raw.to_vec(),
+        };
+
+        let text = std::str::from_utf8(&raw)
+            .map_err(|e| diagnostic(ErrorKind::InvalidUtf8, e.to_string(), &raw))?;
+
+        match serde_json::from_str::<MouseEvent>(text) {
+            Ok(event) => Ok(LogRecord {
+                line: self.line,
+                byte_offset: self.offset,
+                event,
+                raw,
+            }),
+            Err(e) => {
+                let kind = match e.classify() {
+                    serde_json::error::Category::Syntax => ErrorKind::Syntax,
+                    serde_json::error::Category::Eof => ErrorKind::Truncated,
+                    serde_json::error::Category::Data => ErrorKind::Schema,
+                    serde_json::error::Category::Io => ErrorKind::Io,
+                };
+                Err(diagnostic(kind, e.to_string(), &raw))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogRecord, LineDiagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.pending.pop_front() {
+            return Some(item);
+        }
+        if self.failed {
+            return None;
+        }
+
+        loop {
+            let mut buf = Vec::new();
+            let read = match self.inner.read_until(b'\n', &mut buf) {
+                Ok(0) => return None,
+                Ok(n) => n,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(LineDiagnostic {
+                        line: self.line + 1,
+                        byte_offset: self.offset,
+                        kind: ErrorKind::Io,
+                        message: e.to_string(),
+                        raw: String::new(),
+                        bytes: Vec::new(),
+                    }));
+                }
+            };
+
+            self.line += 1;
+            while matches!(buf.last(), Some(b'\n' | b'\r')) {
+                buf.pop();
+            }
+
+            let item = if buf.iter().all(u8::is_ascii_whitespace) || is_chain_line(&buf) {
+                None
+            } else if is_segment(&buf) {
+                self.open_segment(buf);
+                self.pending.pop_front()
+            } else {
+                Some(self.parse(buf))
+            };
+            self.offset += read as u64;
+
+            if let Some(item) = item {
+                return Some(item);
+            }
+        }
+    }
+}
+
+/// 修复结果统计
+#[derive(Debug, Default)]
+pub struct RepairSummary {
+    pub good: usize,
+    pub bad: usize,
+    pub by_kind: BTreeMap<ErrorKind, usize>,
+}
+
+/// 将 `input` 中的好记录原样复制到 `output`，坏记录连同诊断信息写入 `quarantine`（JSONL）
+///
+/// 给定密钥时输入中的加密段被解密，`output` 用当前密钥重新加密。
+pub fn repair(
+    input: &Path,
+    output: &Path,
+    quarantine: &Path,
+    keyring: Option<&Keyring>,
+) -> Result<RepairSummary, Box<dyn std::error::Error>> {
+    let mut good_out = SegmentWriter::new(BufWriter::new(File::create(output)?), keyring);
+    let mut bad_out = BufWriter::new(File::create(quarantine)?);
+    let mut summary = RepairSummary::default();
+
+    for item in LogReader::open(input)?.with_keyring(keyring) {
+        match item {
+            Ok(record) => {
+                good_out.write_line(&record.raw)?;
+                summary.good += 1;
+            }
+            Err(diagnostic) => {
+                writeln!(bad_out, "{}", serde_json::to_string(&diagnostic)?)?;
+                *summary.by_kind.entry(diagnostic.kind).or_default() += 1;
+                summary.bad += 1;
+            }
+        }
+    }
+
+    good_out.finish()?;
+    bad_out.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    const GOOD: &str = r#"{"event_type":"mousemove","timestamp":1,"x":1,"y":2,"session_id":"s1","event_id":"e1"}"#;
+
+    fn read(text: &str) -> Vec<Result<LogRecord, LineDiagnostic>> {
+        LogReader::new(text.as_bytes()).collect()
+    }
+
+    #[test]
+    fn bad_lines_are_classified_without_stopping_the_read() {
+        let text = format!("{GOOD}\n\n{{\"event_type\":\n not json\n{{\"x\":1}}\n{GOOD}\r\n");
+        let items = read(&text);
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[0].as_ref().unwrap().line, 1);
+        let kinds: Vec<_> = items[1..4].iter().map(|i| i.as_ref().unwrap_err().kind).collect();
+        assert_eq!(kinds, [ErrorKind::Truncated, ErrorKind::Syntax, ErrorKind::Schema]);
+        // 空行也计入行号
+        assert_eq!(items[1].as_ref().unwrap_err().line, 3);
+        let last = items[4].as_ref().unwrap();
+        assert_eq!((last.line, last.byte_offset), (6, text.rfind(GOOD).unwrap() as u64));
+        assert_eq!(last.raw, GOOD.as_bytes());
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_with_replacement_characters() {
+        let mut bytes = b"{\"x\":\xff}\n".to_vec();
+        bytes.extend_from_slice(GOOD.as_bytes());
+        let items: Vec<_> = LogReader::new(&bytes[..]).collect();
+        let diagnostic = items[0].as_ref().unwrap_err();
+        assert_eq!(diagnostic.kind, ErrorKind::InvalidUtf8);
+        assert!(diagnostic.raw.contains('\u{fffd}'));
+        assert!(items[1].is_ok());
+    }
+
+    #[test]
+    fn repair_splits_good_and_bad_lines() {
+        let dir = temp_dir("repair");
+        let input = dir.join("events.jsonl");
+        std::fs::write(&input, format!("{GOOD}\ngarbage\n{GOOD}\n{{\"x\":1}}\n")).unwrap();
+
+        let summary = repair(&input, &dir.join("good.jsonl"), &dir.join("bad.jsonl"), None).unwrap();
+        assert_eq!((summary.good, summary.bad), (2, 2));
+        assert_eq!(summary.by_kind[&ErrorKind::Syntax], 1);
+        assert_eq!(summary.by_kind[&ErrorKind::Schema], 1);
+        assert_eq!(std::fs::read_to_string(dir.join("good.jsonl")).unwrap(), format!("{GOOD}\n{GOOD}\n"));
+
+        let quarantined: Vec<serde_json::Value> = std::fs::read_to_string(dir.join("bad.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(quarantined[0]["line"], 2);
+        assert_eq!(quarantined[0]["kind"], "syntax");
+        assert_eq!(quarantined[1]["raw"], "{\"x\":1}");
+    }
+}
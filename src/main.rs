@@ -6,24 +6,44 @@ async fn main() {
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use mouse_tracker::app::*;
-    use mouse_tracker::mouse_handler::{AppState, MouseLogger};
-    use std::path::PathBuf;
+    use mouse_tracker::config::ServerConfig;
+    use mouse_tracker::mouse_handler::{AppState, ServerState};
     use std::sync::Arc;
 
+    // 带子命令时作为离线工具运行
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = mouse_tracker::cli::run(&args).await {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let conf = get_configuration(None).unwrap();
     let addr = conf.leptos_options.site_addr;
     let leptos_options = conf.leptos_options;
     // Generate the list of routes in your Leptos App
     let routes = generate_route_list(App);
 
-    // Create mouse logger state
-    let log_path = PathBuf::from("mouse_events.jsonl");
-    let mouse_logger: AppState = Arc::new(MouseLogger::new(log_path));
+    // Create server state
+    let config = ServerConfig::load().unwrap();
+    let state: AppState = Arc::new(ServerState::new(config.clone()).unwrap());
+    // 其他项目各自有独立的存储和状态
+    let projects = mouse_tracker::projects::load(&config).unwrap();
+
+    for (name, project) in std::iter::once(("", &state)).chain(projects.iter().map(|(name, s)| (name.as_str(), s))) {
+        project.audit_config_load("system", name, &ServerConfig::path());
+        if let Some(retention) = project.config.retention.clone() {
+            mouse_tracker::retention::spawn_retention_task(project.clone(), retention);
+        }
+    }
+
+    // 关闭时需要为每个项目的日志补写检查点
+    let states: Vec<AppState> = std::iter::once(state.clone()).chain(projects.iter().map(|(_, s)| s.clone())).collect();
 
     // API routes with their own state
-    let api_routes = Router::new()
-        .route("/mouse", axum::routing::post(mouse_tracker::mouse_handler::handle_mouse_event))
-        .with_state(mouse_logger);
+    let api_routes = mouse_tracker::projects::routes(state, &projects);
 
     // Leptos routes
     let app = Router::new()
@@ -39,9 +59,42 @@ async fn main() {
     // `axum::Server` is a re-export of `hyper::Server`
     log!("listening on http://{}", &addr);
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
+    // 审计日志需要请求方地址
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    // 最后一个检查点之后的批没有签名保护，关闭前补写检查点
+    for state in &states {
+        if let Err(e) = state.logger.checkpoint().await {
+            eprintln!("failed to write checkpoint: {}", e);
+        }
+    }
+}
+
+/// 等待 Ctrl-C 或 SIGTERM
+#[cfg(feature = "ssr")]
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
 #[cfg(not(feature = "ssr"))]
@@ -0,0 +1,279 @@
+//! 多文件合并
+//!
+//! 对已按 `(session_id, timestamp, event_id)` 排序的多个事件日志做 k 路归并，
+//! 写出一个有序的日志文件。每个输入只保留一行在内存中；去重用的 `event_id` 集合
+//! 只覆盖当前会话，因此内存占用与单个会话的大小成正比，而不是与文件大小成正比。
+//!
+//! 去重按会话进行：`(session_id, event_id)` 相同的记录才视为重复。客户端生成的
+//! `event_id` 带有会话 ID，不同会话不会重复；不同会话中出现相同 `event_id` 时两条都会写出。
+
+use crate::encryption::{Keyring, SegmentWriter};
+use crate::export::read_events;
+use crate::types::MouseEvent;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+type EventIter = Box<dyn Iterator<Item = Result<MouseEvent, Box<dyn std::error::Error>>>>;
+
+/// 排序键
+fn sort_key(event: &MouseEvent) -> (&str, u64, &str) {
+    (&event.session_id, event.timestamp, &event.event_id)
+}
+
+/// 堆中的一项：某个输入的当前事件
+struct HeapEntry {
+    event: MouseEvent,
+    input: usize,
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // 键相同时按输入顺序，保证结果确定
+        sort_key(&self.event)
+            .cmp(&sort_key(&other.event))
+            .then(self.input.cmp(&other.input))
+    }
+}
+
+/// 同一 `event_id` 对应不同内容的记录
+#[derive(Debug, Serialize)]
+pub struct MergeConflict {
+    pub event_id: String,
+    /// 被保留的记录来源
+    pub kept_from: PathBuf,
+    /// 被丢弃的记录来源
+    pub dropped_from: PathBuf,
+    pub kept: MouseEvent,
+    pub dropped: MouseEvent,
+}
+
+/// 合并结果统计
+#[derive(Debug, Default)]
+pub struct MergeSummary {
+    pub read: usize,
+    pub written: usize,
+    /// 内容完全相同的重复记录数
+    pub duplicates: usize,
+    /// 内容冲突的记录数（已写入冲突报告）
+    pub conflicts: usize,
+}
+
+/// 单个输入：记录上一条事件的键以检查输入是否有序
+struct Input {
+    path: PathBuf,
+    events: EventIter,
+    last_key: Option<(String, u64, String)>,
+}
+
+impl Input {
+    fn next(&mut self) -> Result<Option<MouseEvent>, Box<dyn std::error::Error>> {
+        let Some(event) = self.events.next().transpose()? else {
+            return Ok(None);
+        };
+
+        let key = (event.session_id.clone(), event.timestamp, event.event_id.clone());
+        if let Some(last) = &self.last_key {
+            if key < *last {
+                return Err(format!(
+                    "{} is not sorted by (session_id, timestamp, event_id): {:?} follows {:?}",
+                    self.path.display(),
+                    key,
+                    last
+                )
+                .into());
+            }
+        }
+        self.last_key = Some(key);
+
+        Ok(Some(event))
+    }
+}
+
+/// 合并多个有序事件日志，并在每个会话内按 `event_id` 去重
+///
+/// 结果先写入临时文件，成功后再重命名为 `output`，失败时删除临时文件；
+/// 冲突记录逐条写入 `conflicts`（JSONL），没有冲突时它是空文件，不会留下上次合并的内容。
+/// 给定密钥时解密输入中的加密段，`output` 用当前密钥加密。
+pub fn merge_logs(
+    inputs: &[PathBuf],
+    output: &Path,
+    conflicts: &Path,
+    keyring: Option<&Keyring>,
+) -> Result<MergeSummary, Box<dyn std::error::Error>> {
+    let tmp_path = output.with_extension("merging");
+    let result = merge_into(inputs, &tmp_path, conflicts, keyring);
+    match result {
+        Ok(_) => fs::rename(&tmp_path, output)?,
+        Err(_) => {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+    result
+}
+
+fn merge_into(
+    inputs: &[PathBuf],
+    tmp_path: &Path,
+    conflicts: &Path,
+    keyring: Option<&Keyring>,
+) -> Result<MergeSummary, Box<dyn std::error::Error>> {
+    let mut conflict_out = BufWriter::new(File::create(conflicts)?);
+    let mut sources = Vec::with_capacity(inputs.len());
+    for path in inputs {
+        sources.push(Input {
+            path: path.clone(),
+            events: Box::new(read_events(path, keyring)?),
+            last_key: None,
+        });
+    }
+
+    let mut heap = BinaryHeap::new();
+    for (input, source) in sources.iter_mut().enumerate() {
+        if let Some(event) = source.next()? {
+            heap.push(Reverse(HeapEntry { event, input }));
+        }
+    }
+
+    let mut out = SegmentWriter::new(BufWriter::new(File::create(tmp_path)?), keyring);
+    let mut summary = MergeSummary::default();
+
+    // 当前会话内已写出的事件：event_id -> (序列化内容, 来源)
+    let mut current_session = String::new();
+    let mut seen: HashMap<String, (String, usize)> = HashMap::new();
+
+    while let Some(Reverse(HeapEntry { event, input })) = heap.pop() {
+        summary.read += 1;
+
+        if let Some(next) = sources[input].next()? {
+            heap.push(Reverse(HeapEntry { event: next, input }));
+        }
+
+        if event.session_id != current_session {
+            current_session = event.session_id.clone();
+            seen.clear();
+        }
+
+        let line = serde_json::to_string(&event)?;
+        match seen.get(&event.event_id) {
+            Some((kept, _)) if *kept == line => summary.duplicates += 1,
+            Some((kept, kept_input)) => {
+                summary.conflicts += 1;
+                let conflict = MergeConflict {
+                    event_id: event.event_id.clone(),
+                    kept_from: inputs[*kept_input].clone(),
+                    dropped_from: inputs[input].clone(),
+                    kept: serde_json::from_str(kept)?,
+                    dropped: event,
+                };
+                writeln!(conflict_out, "{}", serde_json::to_string(&conflict)?)?;
+            }
+            None => {
+                out.write_line(line.as_bytes())?;
+                summary.written += 1;
+                seen.insert(event.event_id, (line, input));
+            }
+        }
+    }
+
+    out.finish()?;
+    conflict_out.flush()?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{event, temp_dir};
+
+    fn write_log(path: &Path, events: &[MouseEvent]) {
+        let lines: Vec<String> = events.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+        fs::write(path, lines.concat()).unwrap();
+    }
+
+    fn ids(path: &Path) -> Vec<String> {
+        read_events(path, None).unwrap().map(|e| e.unwrap().event_id).collect()
+    }
+
+    #[test]
+    fn interleaves_inputs_by_session_timestamp_and_event_id() {
+        let dir = temp_dir("merge-order");
+        let (a, b) = (dir.join("a.jsonl"), dir.join("b.jsonl"));
+        write_log(&a, &[event("s1", "e1", 10), event("s1", "e4", 30), event("s2", "e6", 5)]);
+        write_log(&b, &[event("s1", "e3", 20), event("s1", "e2", 30), event("s2", "e5", 5)]);
+
+        let output = dir.join("merged.jsonl");
+        let summary = merge_logs(&[a, b], &output, &dir.join("conflicts.jsonl"), None).unwrap();
+        assert_eq!((summary.read, summary.written), (6, 6));
+        // 时间戳相同时按 event_id 排序
+        assert_eq!(ids(&output), ["e1", "e3", "e2", "e4", "e5", "e6"]);
+        assert!(!output.with_extension("merging").exists());
+    }
+
+    #[test]
+    fn identical_duplicates_are_dropped_and_conflicts_reported() {
+        let dir = temp_dir("merge-dedup");
+        let (a, b) = (dir.join("a.jsonl"), dir.join("b.jsonl"));
+        let mut changed = event("s1", "e2", 20);
+        changed.x = 99;
+        write_log(&a, &[event("s1", "e1", 10), event("s1", "e2", 20)]);
+        write_log(&b, &[event("s1", "e1", 10), changed, event("s2", "e1", 10)]);
+
+        let output = dir.join("merged.jsonl");
+        let conflicts = dir.join("conflicts.jsonl");
+        let summary = merge_logs(&[a.clone(), b.clone()], &output, &conflicts, None).unwrap();
+        assert_eq!((summary.read, summary.written, summary.duplicates, summary.conflicts), (5, 3, 1, 1));
+        // 去重只在会话内进行，s2 的 e1 保留
+        assert_eq!(ids(&output), ["e1", "e2", "e1"]);
+
+        let conflict: serde_json::Value = serde_json::from_str(fs::read_to_string(&conflicts).unwrap().trim()).unwrap();
+        assert_eq!(conflict["event_id"], "e2");
+        assert_eq!(conflict["kept_from"], a.to_str().unwrap());
+        assert_eq!(conflict["dropped_from"], b.to_str().unwrap());
+        assert_eq!(conflict["dropped"]["x"], 99);
+    }
+
+    #[test]
+    fn unsorted_input_is_rejected() {
+        let dir = temp_dir("merge-unsorted");
+        let input = dir.join("a.jsonl");
+        write_log(&input, &[event("s1", "e2", 20), event("s1", "e1", 10)]);
+
+        let output = dir.join("merged.jsonl");
+        let err = merge_logs(&[input], &output, &dir.join("conflicts.jsonl"), None).unwrap_err();
+        assert!(err.to_string().contains("is not sorted"));
+        assert!(!output.exists());
+        assert!(!output.with_extension("merging").exists());
+    }
+
+    #[test]
+    fn conflicts_file_is_truncated_when_there_are_none() {
+        let dir = temp_dir("merge-stale-conflicts");
+        let input = dir.join("a.jsonl");
+        write_log(&input, &[event("s1", "e1", 10)]);
+        let conflicts = dir.join("conflicts.jsonl");
+        fs::write(&conflicts, "{\"event_id\":\"stale\"}\n").unwrap();
+
+        let summary = merge_logs(&[input], &dir.join("merged.jsonl"), &conflicts, None).unwrap();
+        assert_eq!(summary.conflicts, 0);
+        assert_eq!(fs::read_to_string(&conflicts).unwrap(), "");
+    }
+}
@@ -1,6 +1,18 @@
-use crate::types::MouseEvent;
-use std::fs::OpenOptions;
-use std::io::Write;
+use crate::audit::{AuditEntry, AuditLog};
+use crate::chain::{ChainHead, ChainOptions, ChainWriter, CheckpointKey};
+use crate::config::ServerConfig;
+use crate::consent::{ConsentStore, InvalidWithdrawToken};
+use crate::cors::RequestOrigin;
+use crate::encryption::{Keyring, SegmentWriter, SEGMENT_LINES};
+use crate::log_reader::{ErrorKind, LogReader};
+use crate::projects::Reader;
+use crate::pseudonym::Pseudonymizer;
+use crate::redaction::{RedactionReport, Redactor};
+use crate::retention::{delete_session, unlink_participant, DeletionReceipt};
+use crate::types::{ClientConfig, ConsentDecision, ConsentResponse, MouseEvent, PARTICIPANT_HEADER};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -9,6 +21,12 @@ pub struct MouseLogger {
     file_path: PathBuf,
     // 使用 Mutex 保护并发写入
     _guard: Arc<Mutex<()>>,
+    /// 设置后每批事件写为一个加密段
+    keyring: Option<Keyring>,
+    /// 设置后每批事件之后追加哈希链记录
+    chain: Option<ChainOptions>,
+    /// 链头，第一次写入时从文件中读取
+    chain_head: std::sync::Mutex<Option<ChainHead>>,
 }
 
 impl MouseLogger {
@@ -16,11 +34,46 @@ impl MouseLogger {
         Self {
             file_path,
             _guard: Arc::new(Mutex::new(())),
+            keyring: None,
+            chain: None,
+            chain_head: std::sync::Mutex::new(None),
         }
     }
 
+    pub fn with_keyring(mut self, keyring: Option<Keyring>) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
+    pub fn with_chain(mut self, chain: Option<ChainOptions>) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// 当前链头，未开启哈希链时为 None
+    fn chain_head(&self) -> Result<Option<ChainHead>, Box<dyn std::error::Error>> {
+        if self.chain.is_none() {
+            return Ok(None);
+        }
+        let mut head = self.chain_head.lock().map_err(|_| "chain head poisoned")?;
+        if head.is_none() {
+            *head = Some(ChainHead::scan(&self.file_path)?);
+        }
+        Ok(head.clone())
+    }
+
+    fn set_chain_head(&self, head: Option<ChainHead>) {
+        if let Ok(mut current) = self.chain_head.lock() {
+            *current = head;
+        }
+    }
+
+    pub fn file_path(&self) -> &PathBuf {
+        &self.file_path
+    }
+
     pub async fn log_event(&self, event: &MouseEvent) -> Result<(), Box<dyn std::error::Error>> {
-        self.log_events(&[event.clone()]).await
+        self.log_events(std::slice::from_ref(event)).await
     }
 
     pub async fn log_events(&self, events: &[MouseEvent]) -> Result<(), Box<dyn std::error::Error>> {
@@ -31,31 +84,682 @@ impl MouseLogger {
         // 获取锁以确保同一时间只有一个写入操作
         let _guard = self._guard.lock().await;
 
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.file_path)?;
 
+        let file = ChainWriter::new(file, self.chain_head()?);
+        let mut writer = SegmentWriter::new(file, self.keyring.as_ref());
         for event in events {
             let json_line = serde_json::to_string(event)?;
-            writeln!(file, "{}", json_line)?;
+            writer.write_line(json_line.as_bytes())?;
         }
-        file.flush()?;
+
+        let mut chained = writer.finish()?;
+        if let Some(chain) = &self.chain {
+            chained.end_batch(chain, now_ms())?;
+        }
+        let (_, head) = chained.into_inner();
+        self.set_chain_head(head);
 
         Ok(())
     }
+
+    /// 为当前链头追加一个签名检查点（服务器关闭时调用）
+    ///
+    /// 未开启哈希链、还没有任何批或链头已有检查点时什么也不做。
+    pub async fn checkpoint(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let _guard = self._guard.lock().await;
+        let (Some(chain), Some(head)) = (&self.chain, self.chain_head()?) else {
+            return Ok(());
+        };
+        if head.seq == 0 || (chain.checkpoint_every > 0 && head.seq % chain.checkpoint_every == 0) {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new().append(true).open(&self.file_path)?;
+        let mut writer = ChainWriter::new(file, Some(head));
+        writer.checkpoint(chain, now_ms())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// 重写日志文件，只保留 `keep` 返回 true 的事件；`keep` 可以就地修改事件
+    ///
+    /// 先写入临时文件再重命名替换；未修改的事件原样保留。
+    /// 无法解析的行也原样保留，它们是否属于要删除的数据无从判断，计入 `unchecked`。
+    /// 遇到无法解密的段（密钥缺失或已轮换掉）或读取错误时报错并保持原文件不变，以免删除或清理静默地漏掉其中的事件。
+    /// 设置了密钥时保留的事件用当前密钥重新加密；开启哈希链时从头重建链并在末尾写检查点。
+    pub async fn rewrite<F>(&self, mut keep: F) -> Result<RewriteSummary, Box<dyn std::error::Error>>
+    where
+        F: FnMut(&mut MouseEvent) -> bool,
+    {
+        let _guard = self._guard.lock().await;
+        let mut summary = RewriteSummary::default();
+
+        let reader = match LogReader::open(&self.file_path) {
+            Ok(reader) => reader.with_keyring(self.keyring.as_ref()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(summary),
+            Err(e) => return Err(e.into()),
+        };
+
+        let tmp_path = self.file_path.with_extension("rewrite");
+        let now = now_ms();
+        let file = ChainWriter::new(
+            BufWriter::new(File::create(&tmp_path)?),
+            self.chain.as_ref().map(|_| ChainHead::default()),
+        );
+        let mut out = SegmentWriter::new(file, self.keyring.as_ref());
+        let mut batch_lines = 0;
+        for item in reader {
+            match item {
+                Ok(mut record) => {
+                    let original = record.event.clone();
+                    if !keep(&mut record.event) {
+                        *summary.removed.entry(record.event.session_id).or_insert(0) += 1;
+                        continue;
+                    }
+                    if record.event == original {
+                        out.write_line(&record.raw)?;
+                    } else {
+                        out.write_line(serde_json::to_string(&record.event)?.as_bytes())?;
+                        *summary.modified.entry(record.event.session_id).or_insert(0) += 1;
+                    }
+                }
+                // 读取中断时之后的内容都读不到，替换原文件会丢掉它们
+                Err(diagnostic) if matches!(diagnostic.kind, ErrorKind::Decrypt | ErrorKind::Io) => {
+                    drop(out);
+                    fs::remove_file(&tmp_path)?;
+                    return Err(format!("{}: cannot rewrite log: {}", self.file_path.display(), diagnostic).into());
+                }
+                // 其余坏行按原始字节与事件一样按当前设置写出
+                Err(diagnostic) => {
+                    summary.unchecked += 1;
+                    out.write_line(&diagnostic.bytes)?
+                }
+            }
+
+            batch_lines += 1;
+            if batch_lines >= SEGMENT_LINES {
+                out.seal_pending()?;
+                if let Some(chain) = &self.chain {
+                    out.get_mut().end_batch(chain, now)?;
+                }
+                batch_lines = 0;
+            }
+        }
+
+        let mut chained = out.finish()?;
+        if let Some(chain) = &self.chain {
+            chained.end_batch(chain, now)?;
+            chained.checkpoint(chain, now)?;
+        }
+        let (mut file, head) = chained.into_inner();
+        file.flush()?;
+        drop(file);
+
+        if summary.removed.is_empty() && summary.modified.is_empty() {
+            fs::remove_file(&tmp_path)?;
+        } else {
+            fs::rename(&tmp_path, &self.file_path)?;
+            self.set_chain_head(head);
+        }
+
+        Ok(summary)
+    }
+}
+
+/// 重写结果：每个会话被移除和被修改的事件数
+#[derive(Debug, Default)]
+pub struct RewriteSummary {
+    pub removed: BTreeMap<String, usize>,
+    pub modified: BTreeMap<String, usize>,
+    /// 原样保留、未经 `keep` 检查的坏行数
+    pub unchecked: usize,
+}
+
+/// 服务器共享状态
+pub struct ServerState {
+    pub logger: MouseLogger,
+    pub redactor: Redactor,
+    /// 服务器启动以来的累计脱敏统计
+    pub redaction_report: std::sync::Mutex<RedactionReport>,
+    pub consent: ConsentStore,
+    pub pseudonymizer: Option<Pseudonymizer>,
+    pub audit: AuditLog,
+    pub config: ServerConfig,
+}
+
+impl ServerState {
+    pub fn new(config: ServerConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let keyring = match &config.encryption {
+            Some(encryption) => Some(Keyring::load(&encryption.key_file)?),
+            None => None,
+        };
+
+        let chain = match &config.integrity {
+            Some(integrity) => Some(ChainOptions {
+                key: CheckpointKey::load(&integrity.key_file)?,
+                checkpoint_every: integrity.checkpoint_every,
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            logger: MouseLogger::new(config.log_path.clone())
+                .with_keyring(keyring)
+                .with_chain(chain),
+            redactor: Redactor::new(&config.redaction)?,
+            redaction_report: std::sync::Mutex::new(RedactionReport::default()),
+            consent: ConsentStore::open(&config.consent.path)?,
+            pseudonymizer: match &config.pseudonym {
+                Some(pseudonym) => Some(Pseudonymizer::load(&pseudonym.key_file)?),
+                None => None,
+            },
+            audit: AuditLog::new(config.audit_path.clone()),
+            config,
+        })
+    }
+
+    /// 记录一次配置加载
+    ///
+    /// 脱敏规则只记录指纹（含盐，不落明文），CORS 来源原样记录；
+    /// 与该项目上一次加载相比有变化的部分列在 `changed` 参数中。
+    pub fn audit_config_load(&self, actor: &str, project: &str, path: &std::path::Path) {
+        let redaction = fingerprint(&self.config.redaction);
+        let cors_origins = self.config.cors.allowed_origins.join(" ");
+        let cors_max_age = self.config.cors.max_age_secs.to_string();
+
+        let query = crate::audit::AuditQuery { actions: vec!["config_load".to_string()], ..Default::default() };
+        let previous = crate::audit::query(&self.config.audit_path, &query)
+            .unwrap_or_default()
+            .into_iter()
+            .rfind(|e| e.params.get("project").map(String::as_str) == Some(project));
+
+        let mut entry = AuditEntry::new(now_ms(), actor, "config_load")
+            .param("path", path.display())
+            .param("project", project)
+            .param("redaction", &redaction)
+            .param("cors_origins", &cors_origins)
+            .param("cors_max_age_secs", &cors_max_age)
+            .count("redaction_rules", self.config.redaction.rules.len() as u64);
+        if let Some(previous) = previous {
+            let differs = |name: &str, value: &str| previous.params.get(name).map(String::as_str) != Some(value);
+            let mut changed = Vec::new();
+            if differs("redaction", &redaction) {
+                changed.push("redaction");
+            }
+            if differs("cors_origins", &cors_origins) || differs("cors_max_age_secs", &cors_max_age) {
+                changed.push("cors");
+            }
+            entry = entry.param("changed", changed.join(","));
+        }
+        self.audit.record(&entry);
+    }
+}
+
+/// 配置片段的指纹：JSON 序列化后 SHA-256 的前 8 字节（十六进制）
+fn fingerprint(value: &impl serde::Serialize) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(serde_json::to_vec(value).unwrap_or_default());
+    digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-pub type AppState = Arc<MouseLogger>;
+pub type AppState = Arc<ServerState>;
+
+/// 当前 Unix 毫秒时间
+pub fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// HTTP 请求的操作者（审计用）
+fn http_actor(addr: &std::net::SocketAddr) -> String {
+    format!("http:{}", addr.ip())
+}
+
+/// 根据请求头中的参与者令牌计算假名；未配置假名或没有有效令牌时为 None
+fn participant_id(state: &ServerState, headers: &axum::http::HeaderMap) -> Option<String> {
+    let token = headers.get(PARTICIPANT_HEADER)?.to_str().ok()?;
+    state.pseudonymizer.as_ref()?.pseudonym(token)
+}
 
 pub async fn handle_mouse_event(
-    axum::extract::State(mouse_logger): axum::extract::State<AppState>,
-    axum::Json(events): axum::Json<Vec<MouseEvent>>,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: axum::http::HeaderMap,
+    origin: Option<axum::Extension<RequestOrigin>>,
+    axum::Json(mut events): axum::Json<Vec<MouseEvent>>,
 ) -> Result<String, axum::http::StatusCode> {
-    mouse_logger
+    if state.config.consent.required
+        && !events
+            .iter()
+            .all(|e| state.consent.has_valid_consent(&e.session_id, &state.config.consent.version))
+    {
+        return Err(axum::http::StatusCode::FORBIDDEN);
+    }
+
+    // participant_id 和 origin 只由服务器填写，忽略客户端提交的值
+    let participant_id = participant_id(&state, &headers);
+    let origin = origin.map(|axum::Extension(RequestOrigin(origin))| origin);
+    for event in &mut events {
+        event.participant_id = participant_id.clone();
+        event.origin = origin.clone();
+    }
+
+    // 脱敏在写入存储之前进行，原始值不会落盘
+    if !state.redactor.is_empty() {
+        let report = state.redactor.apply_all(&mut events);
+        if let Ok(mut total) = state.redaction_report.lock() {
+            total.merge(&report);
+        }
+    }
+
+    state
+        .logger
         .log_events(&events)
         .await
         .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
     Ok(format!("{} events logged", events.len()))
 }
+
+/// `POST /api/consent`：记录同意或撤回；撤回时删除该会话已存储的数据
+///
+/// 会话同意过之后，决定必须附上同意时签发的撤回密钥，否则返回 403。
+pub async fn handle_consent(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    axum::Json(decision): axum::Json<ConsentDecision>,
+) -> Result<axum::Json<ConsentResponse>, axum::http::StatusCode> {
+    // 不记录撤回密钥
+    let entry = AuditEntry::new(now_ms(), &http_actor(&addr), "consent")
+        .param("session_id", &decision.session_id)
+        .param("version", &decision.version)
+        .param("granted", decision.granted);
+
+    let result = async {
+        let (record, withdraw_token) = state.consent.record(&decision, now_ms())?;
+        let receipt = match decision.granted {
+            true => None,
+            false => Some(delete_session(&state, &decision.session_id, "consent withdrawn").await?),
+        };
+        Ok::<_, Box<dyn std::error::Error>>((record, withdraw_token, receipt))
+    }
+    .await;
+
+    state.audit.record_result(entry, &result, |entry, (_, _, receipt)| match receipt {
+        Some(receipt) => receipt.audit_counts(entry),
+        None => entry,
+    });
+    result
+        .map(|(record, withdraw_token, _)| {
+            axum::Json(ConsentResponse {
+                session_id: record.session_id,
+                version: record.version,
+                granted: record.granted,
+                withdraw_token,
+            })
+        })
+        .map_err(|e| match e.is::<InvalidWithdrawToken>() {
+            true => axum::http::StatusCode::FORBIDDEN,
+            false => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        })
+}
+
+/// `POST /api/participant/unlink`：清除请求头中令牌对应的假名，之后浏览器应换用新令牌
+pub async fn handle_unlink_participant(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::Json<DeletionReceipt>, axum::http::StatusCode> {
+    if state.pseudonymizer.is_none() {
+        return Err(axum::http::StatusCode::NOT_FOUND);
+    }
+    let participant_id = participant_id(&state, &headers).ok_or(axum::http::StatusCode::BAD_REQUEST)?;
+
+    // 不记录假名本身，与删除回执一致
+    let entry = AuditEntry::new(now_ms(), &http_actor(&addr), "unlink_participant");
+    let result = unlink_participant(&state, &participant_id, "participant unlinked").await;
+    state
+        .audit
+        .record_result(entry, &result, |entry, receipt| {
+            entry.count("unlinked", receipt.unlinked as u64).count("unchecked", receipt.unchecked as u64)
+        });
+
+    result
+        .map(axum::Json)
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// `GET /api/client-config`：采集页面的设置
+pub async fn handle_client_config(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+) -> axum::Json<ClientConfig> {
+    let config = ClientConfig {
+        consent_version: state.config.consent.version.clone(),
+        consent_required: state.config.consent.required,
+        keystroke_class_only: state.config.client.keystroke_class_only,
+    };
+
+    state.audit.record(
+        &AuditEntry::new(now_ms(), &http_actor(&addr), "read_client_config")
+            .param("consent_version", &config.consent_version)
+            .param("consent_required", config.consent_required)
+            .param("keystroke_class_only", config.keystroke_class_only),
+    );
+    axum::Json(config)
+}
+
+/// 审计记录的操作者，带读取令牌时附上令牌名称
+fn reader_actor(addr: &std::net::SocketAddr, reader: Option<&axum::Extension<Reader>>) -> String {
+    match reader {
+        Some(axum::Extension(Reader(name))) => format!("{} token:{}", http_actor(addr), name),
+        None => http_actor(addr),
+    }
+}
+
+/// `GET /api/redaction/report`：返回累计脱敏统计
+pub async fn handle_redaction_report(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    reader: Option<axum::Extension<Reader>>,
+) -> axum::Json<RedactionReport> {
+    let report = state
+        .redaction_report
+        .lock()
+        .map(|r| r.clone())
+        .unwrap_or_default();
+
+    state.audit.record(
+        &AuditEntry::new(now_ms(), &reader_actor(&addr, reader.as_ref()), "read_redaction_report").count("events", report.events),
+    );
+    axum::Json(report)
+}
+
+/// `DELETE /api/sessions/{id}`：删除会话的全部数据并返回删除回执
+pub async fn handle_delete_session(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::ConnectInfo(addr): axum::extract::ConnectInfo<std::net::SocketAddr>,
+    reader: Option<axum::Extension<Reader>>,
+    axum::extract::Path(session_id): axum::extract::Path<String>,
+) -> Result<axum::Json<DeletionReceipt>, axum::http::StatusCode> {
+    let entry = AuditEntry::new(now_ms(), &reader_actor(&addr, reader.as_ref()), "delete_session").param("session_id", &session_id);
+    let result = delete_session(&state, &session_id, "api request").await;
+    state
+        .audit
+        .record_result(entry, &result, |entry, receipt| receipt.audit_counts(entry));
+
+    result
+        .map(axum::Json)
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{config_in, event, run, temp_dir};
+    use crate::types::CONSENT_VERSION;
+    use axum::extract::{ConnectInfo, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::Json;
+
+    fn addr() -> ConnectInfo<std::net::SocketAddr> {
+        ConnectInfo(([127, 0, 0, 1], 3000).into())
+    }
+
+    fn decision(session_id: &str, version: &str, granted: bool, withdraw_token: Option<String>) -> Json<ConsentDecision> {
+        Json(ConsentDecision {
+            session_id: session_id.to_string(),
+            version: version.to_string(),
+            granted,
+            withdraw_token,
+        })
+    }
+
+    #[test]
+    fn custom_consent_version_from_client_config_is_accepted() {
+        let mut config = config_in(&temp_dir("consent-version"));
+        config.consent.required = true;
+        config.consent.version = "a-2025-03".to_string();
+        let state: AppState = Arc::new(ServerState::new(config).unwrap());
+
+        run(async {
+            let Json(client) = handle_client_config(State(state.clone()), addr()).await;
+            assert_eq!(client.consent_version, "a-2025-03");
+
+            let Json(granted) = handle_consent(State(state.clone()), addr(), decision("s1", &client.consent_version, true, None))
+                .await
+                .unwrap();
+            assert!(granted.granted);
+            let logged = handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(vec![event("s1", "e1", 1)])).await;
+            assert!(logged.is_ok());
+
+            // 内置版本的同意对该项目无效
+            let Json(granted) = handle_consent(State(state.clone()), addr(), decision("s2", CONSENT_VERSION, true, None))
+                .await
+                .unwrap();
+            assert!(granted.granted);
+            let rejected = handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(vec![event("s2", "e2", 2)])).await;
+            assert_eq!(rejected.unwrap_err(), StatusCode::FORBIDDEN);
+        });
+    }
+
+    #[test]
+    fn rewrite_fails_without_the_key_of_an_old_segment() {
+        let dir = temp_dir("rewrite-missing-key");
+        let log_path = dir.join("events.jsonl");
+        crate::encryption::rotate_key(&dir.join("old.json"), "old").unwrap();
+        crate::encryption::rotate_key(&dir.join("new.json"), "new").unwrap();
+        let old_key = Keyring::load(&dir.join("old.json")).unwrap();
+        let new_key = Keyring::load(&dir.join("new.json")).unwrap();
+
+        run(async {
+            let logger = MouseLogger::new(log_path.clone()).with_keyring(Some(old_key));
+            logger.log_events(&[event("s1", "e1", 1), event("s2", "e2", 2)]).await.unwrap();
+            let before = fs::read(&log_path).unwrap();
+
+            // 轮换时丢掉了旧密钥：不能把旧段当作不相关数据跳过
+            let logger = MouseLogger::new(log_path.clone()).with_keyring(Some(new_key));
+            assert!(logger.rewrite(|e| e.session_id != "s1").await.is_err());
+            assert_eq!(fs::read(&log_path).unwrap(), before);
+            assert!(!log_path.with_extension("rewrite").exists());
+        });
+    }
+
+    #[test]
+    fn rewrite_reencrypts_kept_events_with_the_active_key() {
+        let dir = temp_dir("rewrite-reencrypt");
+        let log_path = dir.join("events.jsonl");
+        let key_path = dir.join("keys.json");
+        crate::encryption::rotate_key(&key_path, "k1").unwrap();
+
+        run(async {
+            let logger = MouseLogger::new(log_path.clone()).with_keyring(Some(Keyring::load(&key_path).unwrap()));
+            logger.log_events(&[event("s1", "e1", 1), event("s2", "e2", 2)]).await.unwrap();
+
+            crate::encryption::rotate_key(&key_path, "k2").unwrap();
+            let keyring = Keyring::load(&key_path).unwrap();
+            let logger = MouseLogger::new(log_path.clone()).with_keyring(Some(keyring.clone()));
+            let summary = logger.rewrite(|e| e.session_id != "s1").await.unwrap();
+            assert_eq!(summary.removed["s1"], 1);
+
+            let text = fs::read_to_string(&log_path).unwrap();
+            assert!(text.lines().all(|line| line.starts_with("ENC1 k2 ")));
+            let kept: Vec<_> = LogReader::open(&log_path)
+                .unwrap()
+                .with_keyring(Some(&keyring))
+                .map(|r| r.unwrap().event.event_id)
+                .collect();
+            assert_eq!(kept, ["e2"]);
+        });
+    }
+
+    #[test]
+    fn withdrawing_consent_needs_the_token_and_deletes_the_session() {
+        let mut config = config_in(&temp_dir("consent-withdraw"));
+        config.consent.required = true;
+        let log_path = config.log_path.clone();
+        let state: AppState = Arc::new(ServerState::new(config).unwrap());
+
+        run(async {
+            let Json(granted) = handle_consent(State(state.clone()), addr(), decision("s1", CONSENT_VERSION, true, None))
+                .await
+                .unwrap();
+            let Json(other) = handle_consent(State(state.clone()), addr(), decision("s2", CONSENT_VERSION, true, None))
+                .await
+                .unwrap();
+            assert_ne!(other.withdraw_token, granted.withdraw_token);
+            let events = vec![event("s1", "e1", 1), event("s1", "e2", 2)];
+            handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(events)).await.unwrap();
+            handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(vec![event("s2", "e3", 3)])).await.unwrap();
+
+            // 只知道会话 ID 不能撤回别人的同意
+            for token in [None, Some("guess".to_string())] {
+                let rejected = handle_consent(State(state.clone()), addr(), decision("s1", CONSENT_VERSION, false, token)).await;
+                assert_eq!(rejected.unwrap_err(), StatusCode::FORBIDDEN);
+            }
+            assert_eq!(crate::export::read_events(&log_path, None).unwrap().count(), 3);
+
+            let Json(withdrawn) =
+                handle_consent(State(state.clone()), addr(), decision("s1", CONSENT_VERSION, false, granted.withdraw_token))
+                    .await
+                    .unwrap();
+            assert!(!withdrawn.granted && withdrawn.withdraw_token.is_none());
+            let remaining: Vec<_> = crate::export::read_events(&log_path, None)
+                .unwrap()
+                .map(|e| e.unwrap().event_id)
+                .collect();
+            assert_eq!(remaining, ["e3"]);
+
+            // 撤回后该会话的新事件被拒绝
+            let rejected = handle_mouse_event(State(state.clone()), HeaderMap::new(), None, Json(vec![event("s1", "e4", 4)])).await;
+            assert_eq!(rejected.unwrap_err(), StatusCode::FORBIDDEN);
+        });
+
+        let audit = fs::read_to_string(&state.config.audit_path).unwrap();
+        assert!(!audit.contains("guess"));
+    }
+
+    #[test]
+    fn unlinking_clears_only_the_callers_pseudonym() {
+        let dir = temp_dir("unlink");
+        let mut config = config_in(&dir);
+        let key_file = dir.join("pseudonym.key");
+        crate::encryption::write_secret_key(&key_file, false).unwrap();
+        config.pseudonym = Some(crate::config::PseudonymConfig { key_file });
+        let log_path = config.log_path.clone();
+        let state: AppState = Arc::new(ServerState::new(config).unwrap());
+
+        let token = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(PARTICIPANT_HEADER, value.parse().unwrap());
+            headers
+        };
+        let participants = || -> Vec<Option<String>> {
+            crate::export::read_events(&log_path, None).unwrap().map(|e| e.unwrap().participant_id).collect()
+        };
+
+        let alice = run(async {
+            // 同一令牌在不同会话中得到同一假名，客户端提交的假名被忽略
+            let mut forged = event("s1", "e1", 1);
+            forged.participant_id = Some("p_forged".to_string());
+            handle_mouse_event(State(state.clone()), token("alice"), None, Json(vec![forged])).await.unwrap();
+            handle_mouse_event(State(state.clone()), token("alice"), None, Json(vec![event("s2", "e2", 2)])).await.unwrap();
+            handle_mouse_event(State(state.clone()), token("bob"), None, Json(vec![event("s3", "e3", 3)])).await.unwrap();
+            let before = participants();
+            assert!(before.iter().all(Option::is_some));
+            assert_eq!(before[0], before[1]);
+            assert_ne!(before[0], before[2]);
+            assert_ne!(before[0].as_deref(), Some("p_forged"));
+
+            let rejected = handle_unlink_participant(State(state.clone()), addr(), HeaderMap::new()).await;
+            assert_eq!(rejected.unwrap_err(), StatusCode::BAD_REQUEST);
+
+            let Json(receipt) = handle_unlink_participant(State(state.clone()), addr(), token("alice")).await.unwrap();
+            assert_eq!(receipt.unlinked, 2);
+            assert!(receipt.removed.is_empty());
+            assert_eq!(participants(), [None, None, before[2].clone()]);
+            before[0].clone().unwrap()
+        });
+
+        // 审计记录和回执都不含假名或令牌
+        let audit = fs::read_to_string(&state.config.audit_path).unwrap();
+        let receipts = fs::read_to_string(&state.config.receipts_path).unwrap();
+        assert!(audit.contains("unlink_participant"));
+        for text in [audit, receipts] {
+            assert!(!text.contains(&alice) && !text.contains("alice"));
+        }
+    }
+
+    #[test]
+    fn config_loads_record_redaction_and_cors_changes() {
+        let dir = temp_dir("config-load");
+        let config_path = dir.join("mouse_tracker.json");
+        let load = |config: ServerConfig| {
+            let state = ServerState::new(config).unwrap();
+            state.audit_config_load("system", "", &config_path);
+            let query = crate::audit::AuditQuery { actions: vec!["config_load".to_string()], ..Default::default() };
+            crate::audit::query(&state.config.audit_path, &query).unwrap().pop().unwrap()
+        };
+
+        let mut config = config_in(&dir);
+        let first = load(config.clone());
+        assert!(!first.params.contains_key("changed"));
+        assert_eq!(first.params["cors_origins"], "");
+
+        let unchanged = load(config.clone());
+        assert_eq!(unchanged.params["changed"], "");
+
+        config.redaction = serde_json::from_value(serde_json::json!({
+            "salt": "secret-salt",
+            "rules": [{"name": "text", "actions": ["drop_target_text"]}],
+        }))
+        .unwrap();
+        let redaction = load(config.clone());
+        assert_eq!(redaction.params["changed"], "redaction");
+        assert_eq!(redaction.counts["redaction_rules"], 1);
+        assert_ne!(redaction.params["redaction"], first.params["redaction"]);
+
+        config.cors.allowed_origins = vec!["https://example.com".to_string()];
+        let cors = load(config.clone());
+        assert_eq!(cors.params["changed"], "cors");
+        assert_eq!(cors.params["cors_origins"], "https://example.com");
+
+        // 盐只参与指纹，不写入审计日志
+        let audit = fs::read_to_string(dir.join("audit.jsonl")).unwrap();
+        assert!(!audit.contains("secret-salt"));
+    }
+
+    #[test]
+    fn rewrite_keeps_invalid_utf8_lines_byte_for_byte() {
+        let log_path = temp_dir("rewrite-utf8").join("events.jsonl");
+        let mut log = Vec::new();
+        for e in [event("s1", "e1", 1), event("s2", "e2", 2)] {
+            log.extend(serde_json::to_vec(&e).unwrap());
+            log.push(b'\n');
+        }
+        log.extend(b"{\"x\":\"\xff\xfe\"}\n");
+        fs::write(&log_path, &log).unwrap();
+
+        let summary = run(MouseLogger::new(log_path.clone()).rewrite(|e| e.session_id != "s1")).unwrap();
+        assert_eq!(summary.unchecked, 1);
+        let text = fs::read(&log_path).unwrap();
+        assert!(text.ends_with(b"{\"x\":\"\xff\xfe\"}\n"));
+    }
+
+    #[test]
+    fn rewrite_fails_on_a_read_error_instead_of_truncating() {
+        // 把目录当作日志：打开成功，读取时出错
+        let log_path = temp_dir("rewrite-io").join("events.jsonl");
+        fs::create_dir(&log_path).unwrap();
+
+        let err = run(MouseLogger::new(log_path.clone()).rewrite(|_| false)).unwrap_err();
+        assert!(err.to_string().contains("cannot rewrite log"), "{err}");
+        assert!(log_path.is_dir());
+        assert!(!log_path.with_extension("rewrite").exists());
+    }
+}
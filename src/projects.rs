@@ -0,0 +1,157 @@
+//! 多项目
+//!
+//! 顶层配置对应默认项目（`/api/...`），`projects` 中的每个项目挂在 `/api/projects/<名称>/...`，
+//! 有自己的存储根目录、密钥、隐私策略和采集页面设置，各自使用独立的 [`ServerState`]，
+//! 一个项目的路由无法访问其他项目的数据。
+//!
+//! 读取接口（脱敏统计、删除会话）需要该项目 `read_tokens` 中的令牌；
+//! 项目（包括默认项目）没有配置令牌时不提供读取接口。
+
+use crate::config::ServerConfig;
+use crate::mouse_handler::{self, AppState, ServerState};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::Router;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// 通过校验的读取令牌名称
+#[derive(Debug, Clone)]
+pub struct Reader(pub String);
+
+/// 令牌的 SHA-256（十六进制），配置文件中只保存这个值
+pub fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 生成随机的访问令牌（32 字节，十六进制）
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 项目名称只能包含字母、数字、`-` 和 `_`
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// 为每个项目创建存储根目录和服务器状态
+pub fn load(config: &ServerConfig) -> Result<Vec<(String, AppState)>, Box<dyn std::error::Error>> {
+    let mut projects = Vec::new();
+    for (name, project) in &config.projects {
+        if !valid_name(name) {
+            return Err(format!("invalid project name {:?}", name).into());
+        }
+        std::fs::create_dir_all(&project.root).map_err(|e| format!("{}: {}", project.root.display(), e))?;
+        let state = ServerState::new(config.project(name)?).map_err(|e| format!("project {:?}: {}", name, e))?;
+        projects.push((name.clone(), Arc::new(state)));
+    }
+    Ok(projects)
+}
+
+/// 默认项目和全部项目的 API 路由
+pub fn routes(default: AppState, projects: &[(String, AppState)]) -> Router {
+    let mut router = project_routes(default);
+    for (name, state) in projects {
+        router = router.nest(&format!("/projects/{}", name), project_routes(state.clone()));
+    }
+    router
+}
+
+/// 一个项目的路由；没有配置令牌时只有采集接口
+fn project_routes(state: AppState) -> Router {
+    // 采集接口，按配置允许跨域提交
+    let collect = Router::new()
+        .route("/mouse", post(mouse_handler::handle_mouse_event))
+        .route("/consent", post(mouse_handler::handle_consent))
+        .route("/participant/unlink", post(mouse_handler::handle_unlink_participant))
+        .route("/client-config", get(mouse_handler::handle_client_config))
+        .route_layer(from_fn_with_state(state.clone(), crate::cors::enforce));
+
+    let read = Router::new()
+        .route("/redaction/report", get(mouse_handler::handle_redaction_report))
+        .route("/sessions/{id}", delete(mouse_handler::handle_delete_session));
+
+    let router = match state.config.read_tokens.is_empty() {
+        false => collect.merge(read.route_layer(from_fn_with_state(state.clone(), require_reader))),
+        true => collect,
+    };
+    router.with_state(state)
+}
+
+/// 校验 `Authorization: Bearer <令牌>`，通过后把令牌名称放入请求扩展
+async fn require_reader(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let hash = token_hash(token.trim());
+    let reader = state
+        .config
+        .read_tokens
+        .iter()
+        .find(|(_, expected)| expected.eq_ignore_ascii_case(&hash));
+    match reader {
+        Some((name, _)) => {
+            request.extensions_mut().insert(Reader(name.clone()));
+            next.run(request).await
+        }
+        None => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{config_in, run, temp_dir};
+    use axum::body::Body;
+    use axum::extract::ConnectInfo;
+    use axum::http::Method;
+    use tower::ServiceExt;
+
+    fn delete_request(token: Option<&str>) -> Request {
+        let mut builder = Request::builder().method(Method::DELETE).uri("/sessions/s1");
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let mut request = builder.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(std::net::SocketAddr::from(([127, 0, 0, 1], 3000))));
+        request
+    }
+
+    #[test]
+    fn reads_need_a_token_even_in_the_default_project() {
+        let open = routes(Arc::new(ServerState::new(config_in(&temp_dir("routes-open"))).unwrap()), &[]);
+        let token = generate_token();
+        let mut config = config_in(&temp_dir("routes-token"));
+        config.read_tokens.insert("alice".to_string(), token_hash(&token));
+        let guarded = routes(Arc::new(ServerState::new(config).unwrap()), &[]);
+
+        run(async {
+            // 没有配置令牌时不提供删除接口
+            let response = open.oneshot(delete_request(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            let response = guarded.clone().oneshot(delete_request(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let response = guarded.clone().oneshot(delete_request(Some("wrong"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            let response = guarded.oneshot(delete_request(Some(&token))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        });
+    }
+}
@@ -0,0 +1,78 @@
+//! 参与者假名
+//!
+//! 浏览器在 localStorage 中保存一个随机的参与者令牌，随请求通过 `X-Participant-Token` 请求头提交。
+//! 服务器用密钥对令牌做 HMAC，得到写入事件的 `participant_id`；令牌本身不落盘。
+//! 更换密钥后新旧假名无法对应；参与者解除关联时，已存储事件中的假名被清除，浏览器换用新令牌。
+
+use crate::encryption::load_secret_key;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::path::Path;
+
+/// 令牌最大长度
+const MAX_TOKEN_LEN: usize = 128;
+
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    /// 读取假名密钥文件
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            key: load_secret_key(path)?,
+        })
+    }
+
+    /// 计算令牌对应的假名；令牌为空、过长或含非法字符时返回 None
+    pub fn pseudonym(&self, token: &str) -> Option<String> {
+        let valid = !token.is_empty()
+            && token.len() <= MAX_TOKEN_LEN
+            && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return None;
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(token.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        Some(format!("p_{}", digest[..8].iter().map(|b| format!("{:02x}", b)).collect::<String>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::write_secret_key;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn same_token_and_key_give_the_same_pseudonym() {
+        let dir = temp_dir("pseudonym");
+        write_secret_key(&dir.join("a.key"), false).unwrap();
+        write_secret_key(&dir.join("b.key"), false).unwrap();
+
+        let first = Pseudonymizer::load(&dir.join("a.key")).unwrap().pseudonym("token-1").unwrap();
+        // 重新读取同一密钥（例如服务器重启）后假名不变
+        let again = Pseudonymizer::load(&dir.join("a.key")).unwrap();
+        assert_eq!(again.pseudonym("token-1").unwrap(), first);
+        assert_ne!(again.pseudonym("token-2").unwrap(), first);
+        assert!(first.starts_with("p_") && first.len() == 18);
+        assert!(!first.contains("token"));
+
+        let other_key = Pseudonymizer::load(&dir.join("b.key")).unwrap();
+        assert_ne!(other_key.pseudonym("token-1").unwrap(), first);
+    }
+
+    #[test]
+    fn invalid_tokens_get_no_pseudonym() {
+        let dir = temp_dir("pseudonym-invalid");
+        write_secret_key(&dir.join("a.key"), false).unwrap();
+        let pseudonymizer = Pseudonymizer::load(&dir.join("a.key")).unwrap();
+
+        assert!(pseudonymizer.pseudonym("").is_none());
+        assert!(pseudonymizer.pseudonym("a b").is_none());
+        assert!(pseudonymizer.pseudonym(&"a".repeat(MAX_TOKEN_LEN + 1)).is_none());
+        assert!(pseudonymizer.pseudonym(&"a".repeat(MAX_TOKEN_LEN)).is_some());
+    }
+}
@@ -0,0 +1,384 @@
+//! 服务器端脱敏
+//!
+//! 事件写入存储前按声明式规则处理：丢弃或哈希目标元素文本、哈希元素 ID / 类名、
+//! 把按键映射为类别。规则可按事件类型和元素选择器限定范围，并统计每条规则修改了多少字段。
+//!
+//! ```json
+//! {
+//!   "salt": "study-2025",
+//!   "rules": [
+//!     { "name": "no-text", "actions": ["drop_target_text"] },
+//!     { "name": "keys", "event_types": ["keydown", "keyup"], "actions": ["key_category"] },
+//!     { "name": "form-ids", "selector": "input, textarea, div.field", "actions": ["hash_target_id", "hash_target_class"] }
+//!   ]
+//! }
+//! ```
+
+use crate::types::{KeyClass, MouseEvent};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RedactionPolicy {
+    /// 哈希前拼接的盐，防止通过字典反查
+    #[serde(default)]
+    pub salt: String,
+    #[serde(default)]
+    pub rules: Vec<RedactionRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionRule {
+    pub name: String,
+    /// 适用的事件类型，为空时适用于全部事件
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    /// 元素选择器，逗号分隔；每项形如 `tag#id.class1.class2`，`*` 匹配任意元素
+    ///
+    /// 服务器端只有目标元素自身的标签、ID 和类名，属性、伪类和组合符（包括后代选择器）
+    /// 在加载配置时报错，而不是静默地从不匹配。
+    #[serde(default)]
+    pub selector: Option<String>,
+    pub actions: Vec<RedactionAction>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionAction {
+    DropTargetText,
+    HashTargetText,
+    HashTargetId,
+    HashTargetClass,
+    /// 删除 `key` 和 `code`，只保留 `key_class`
+    KeyCategory,
+}
+
+/// 选择器中的一项：标签、ID、类名均为可选，全部满足才算匹配
+#[derive(Debug, Clone, Default)]
+struct SimpleSelector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl SimpleSelector {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let unsupported = |what: &str| Err(format!("selector {:?}: {} not supported", text, what));
+        if text.contains(char::is_whitespace) || text.contains(['>', '+', '~']) {
+            return unsupported("combinators");
+        }
+        if text.contains(['[', ']', ':', '(', ')']) {
+            return unsupported("attributes and pseudo-classes");
+        }
+
+        let mut selector = Self::default();
+        let boundary = text.find(['#', '.']).unwrap_or(text.len());
+        let (tag, mut rest) = text.split_at(boundary);
+        if !tag.is_empty() && tag != "*" {
+            if !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(format!("selector {:?}: invalid tag name {:?}", text, tag));
+            }
+            selector.tag = Some(tag.to_lowercase());
+        }
+
+        while let Some(prefix) = rest.chars().next() {
+            let body = &rest[1..];
+            let end = body.find(['#', '.']).unwrap_or(body.len());
+            let name = body[..end].to_string();
+            if name.is_empty() || name.contains('*') {
+                return Err(format!("selector {:?}: empty or invalid name after {:?}", text, prefix));
+            }
+            match prefix {
+                '#' if selector.id.is_some() => return unsupported("multiple ids"),
+                '#' => selector.id = Some(name),
+                _ => selector.classes.push(name),
+            }
+            rest = &body[end..];
+        }
+
+        Ok(selector)
+    }
+
+    fn matches(&self, event: &MouseEvent) -> bool {
+        if let Some(tag) = &self.tag {
+            if event.target_tag.as_deref() != Some(tag.as_str()) {
+                return false;
+            }
+        }
+        if let Some(id) = &self.id {
+            if event.target_id.as_deref() != Some(id.as_str()) {
+                return false;
+            }
+        }
+        let classes: Vec<&str> = event
+            .target_class
+            .as_deref()
+            .map(|c| c.split_whitespace().collect())
+            .unwrap_or_default();
+        self.classes.iter().all(|c| classes.contains(&c.as_str()))
+    }
+}
+
+/// 预先解析好选择器的规则
+struct CompiledRule {
+    rule: RedactionRule,
+    selectors: Vec<SimpleSelector>,
+}
+
+impl CompiledRule {
+    fn applies_to(&self, event: &MouseEvent) -> bool {
+        let type_ok = self.rule.event_types.is_empty()
+            || self.rule.event_types.contains(&event.event_type);
+        let selector_ok = self.selectors.is_empty() || self.selectors.iter().any(|s| s.matches(event));
+        type_ok && selector_ok
+    }
+}
+
+/// 每条规则修改的字段数：规则名 -> 字段名 -> 次数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RedactionReport {
+    pub events: u64,
+    pub rules: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl RedactionReport {
+    fn count(&mut self, rule: &str, field: &str) {
+        *self
+            .rules
+            .entry(rule.to_string())
+            .or_default()
+            .entry(field.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: &RedactionReport) {
+        self.events += other.events;
+        for (rule, fields) in &other.rules {
+            for (field, count) in fields {
+                *self.rules.entry(rule.clone()).or_default().entry(field.clone()).or_insert(0) += count;
+            }
+        }
+    }
+}
+
+pub struct Redactor {
+    salt: String,
+    rules: Vec<CompiledRule>,
+}
+
+impl RedactionPolicy {
+    /// 检查所有规则的选择器都能解析
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Redactor::new(self).map(|_| ())
+    }
+}
+
+impl Redactor {
+    /// 编译规则；选择器不受支持时报错并指出规则名
+    pub fn new(policy: &RedactionPolicy) -> Result<Self, Box<dyn std::error::Error>> {
+        let rules = policy
+            .rules
+            .iter()
+            .map(|rule| {
+                let selectors = match rule.selector.as_deref() {
+                    Some(s) => s
+                        .split(',')
+                        .filter(|p| !p.trim().is_empty())
+                        .map(SimpleSelector::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("redaction rule {:?}: {}", rule.name, e))?,
+                    None => Vec::new(),
+                };
+                Ok(CompiledRule { selectors, rule: rule.clone() })
+            })
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+
+        Ok(Self {
+            salt: policy.salt.clone(),
+            rules,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.salt.as_bytes())
+            .chain_update(value.as_bytes())
+            .finalize();
+        format!("h_{}", &format!("{:x}", digest)[..16])
+    }
+
+    /// 对事件就地脱敏，并把修改计入 `report`
+    pub fn apply(&self, event: &mut MouseEvent, report: &mut RedactionReport) {
+        report.events += 1;
+
+        // 先按原始值确定适用的规则，避免前面的规则哈希了 ID 后选择器不再匹配
+        let applicable: Vec<&CompiledRule> = self.rules.iter().filter(|r| r.applies_to(event)).collect();
+
+        for compiled in applicable {
+            let name = compiled.rule.name.as_str();
+            for action in &compiled.rule.actions {
+                match action {
+                    RedactionAction::DropTargetText => {
+                        if event.target_text.take().is_some() {
+                            report.count(name, "target_text");
+                        }
+                    }
+                    RedactionAction::HashTargetText => {
+                        if let Some(text) = event.target_text.take() {
+                            event.target_text = Some(self.hash(&text));
+                            report.count(name, "target_text");
+                        }
+                    }
+                    RedactionAction::HashTargetId => {
+                        if let Some(id) = event.target_id.take() {
+                            event.target_id = Some(self.hash(&id));
+                            report.count(name, "target_id");
+                        }
+                    }
+                    RedactionAction::HashTargetClass => {
+                        if let Some(class) = event.target_class.take() {
+                            event.target_class = Some(self.hash(&class));
+                            report.count(name, "target_class");
+                        }
+                    }
+                    RedactionAction::KeyCategory => {
+                        if let Some(key) = event.key.take() {
+                            event.key_class = Some(KeyClass::of(&key).as_str().to_string());
+                            report.count(name, "key");
+                        }
+                        if event.code.take().is_some() {
+                            report.count(name, "code");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 对一批事件脱敏，返回本批的修改统计
+    pub fn apply_all(&self, events: &mut [MouseEvent]) -> RedactionReport {
+        let mut report = RedactionReport::default();
+        for event in events {
+            self.apply(event, &mut report);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::event;
+
+    fn target(tag: &str, id: Option<&str>, class: Option<&str>) -> MouseEvent {
+        let mut event = event("s1", "e1", 1);
+        event.target_tag = Some(tag.to_string());
+        event.target_id = id.map(String::from);
+        event.target_class = class.map(String::from);
+        event.target_text = Some("secret".to_string());
+        event
+    }
+
+    fn rule(name: &str, selector: &str, actions: &[RedactionAction]) -> RedactionRule {
+        RedactionRule {
+            name: name.to_string(),
+            event_types: Vec::new(),
+            selector: Some(selector.to_string()),
+            actions: actions.to_vec(),
+        }
+    }
+
+    fn redactor(selector: &str, actions: &[RedactionAction]) -> Result<Redactor, Box<dyn std::error::Error>> {
+        Redactor::new(&RedactionPolicy {
+            salt: "salt".to_string(),
+            rules: vec![rule("rule", selector, actions)],
+        })
+    }
+
+    fn matches(selector: &str, event: &MouseEvent) -> bool {
+        let mut event = event.clone();
+        redactor(selector, &[RedactionAction::DropTargetText]).unwrap().apply(&mut event, &mut RedactionReport::default());
+        event.target_text.is_none()
+    }
+
+    #[test]
+    fn compound_selectors_match_tag_id_and_classes() {
+        let input = target("input", Some("email"), Some("field  wide"));
+        for selector in ["input", "*", "#email", ".field", "input#email.wide.field", "div, input.wide", "INPUT"] {
+            assert!(matches(selector, &input), "{selector}");
+        }
+        for selector in ["div", "#name", ".narrow", "input.field.narrow", "textarea#email"] {
+            assert!(!matches(selector, &input), "{selector}");
+        }
+    }
+
+    #[test]
+    fn unsupported_selectors_are_rejected() {
+        for selector in ["form input", "form > input", "a + b", "a ~ b", "input[type=password]", "a:hover", ":not(a)", "#a#b", "a.", "#", "in*put"] {
+            let err = redactor(selector, &[]).err().unwrap_or_else(|| panic!("{selector} accepted"));
+            assert!(err.to_string().contains("redaction rule \"rule\""), "{err}");
+        }
+        // 逗号两侧的空白和空项不算组合符
+        assert!(redactor(" input , textarea ,", &[]).is_ok());
+    }
+
+    #[test]
+    fn actions_hash_with_salt_and_report_counts() {
+        let redactor = redactor("input", &[RedactionAction::HashTargetId, RedactionAction::HashTargetClass, RedactionAction::HashTargetText]).unwrap();
+        let mut events = vec![target("input", Some("email"), Some("field")), target("div", Some("email"), None)];
+        let report = redactor.apply_all(&mut events);
+
+        let hashed = events[0].target_id.clone().unwrap();
+        assert!(hashed.starts_with("h_") && hashed.len() == 18);
+        assert_eq!(events[1].target_id.as_deref(), Some("email"));
+        assert_eq!(report.events, 2);
+        assert_eq!(report.rules["rule"]["target_id"], 1);
+        assert_eq!(report.rules["rule"]["target_class"], 1);
+        assert_eq!(report.rules["rule"]["target_text"], 1);
+
+        // 规则按原始值选择：哈希 ID 后后续规则仍然适用
+        let policy = RedactionPolicy {
+            salt: "other".to_string(),
+            rules: vec![
+                rule("ids", "#email", &[RedactionAction::HashTargetId]),
+                rule("text", "#email", &[RedactionAction::DropTargetText]),
+            ],
+        };
+        let mut event = target("input", Some("email"), None);
+        Redactor::new(&policy).unwrap().apply(&mut event, &mut RedactionReport::default());
+        assert!(event.target_text.is_none());
+        assert_ne!(event.target_id.as_deref(), Some(hashed.as_str()), "different salt, different hash");
+    }
+
+    #[test]
+    fn key_category_replaces_key_and_code_for_listed_event_types() {
+        let redactor = Redactor::new(&RedactionPolicy {
+            salt: String::new(),
+            rules: vec![RedactionRule {
+                event_types: vec!["keydown".to_string()],
+                selector: None,
+                ..rule("keys", "*", &[RedactionAction::KeyCategory])
+            }],
+        })
+        .unwrap();
+        let mut keydown = event("s1", "e1", 1);
+        keydown.event_type = "keydown".to_string();
+        keydown.key = Some("a".to_string());
+        keydown.code = Some("KeyA".to_string());
+        let mut other = keydown.clone();
+        other.event_type = "keyup".to_string();
+
+        let mut events = vec![keydown, other];
+        let report = redactor.apply_all(&mut events);
+        assert_eq!((events[0].key.as_deref(), events[0].code.as_deref()), (None, None));
+        assert_eq!(events[0].key_class.as_deref(), Some("letter"));
+        assert_eq!(events[1].key.as_deref(), Some("a"));
+        assert_eq!((report.rules["keys"]["key"], report.rules["keys"]["code"]), (1, 1));
+    }
+}
@@ -0,0 +1,315 @@
+//! 数据删除与保留策略
+//!
+//! 删除会话（参与者撤回）和按时间清理（伦理审批的保留期限）都会重写事件日志，
+//! 并在回执文件中追加一条 [`DeletionReceipt`]，记录删除了什么、何时、为什么。
+//! 日志中有当前密钥文件无法解密的段时，删除和清理都会失败，而不是跳过该段。
+
+use crate::audit::AuditEntry;
+use crate::config::RetentionConfig;
+use crate::mouse_handler::{now_ms, AppState, RewriteSummary, ServerState};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+static RECEIPT_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 删除回执
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletionReceipt {
+    pub receipt_id: String,
+    /// 删除时间（Unix 毫秒）
+    pub deleted_at: u64,
+    pub reason: String,
+    /// 按会话删除时的目标会话
+    pub session_id: Option<String>,
+    /// 按时间清理时的截止时间，早于它的事件被删除
+    pub cutoff: Option<u64>,
+    /// 每个会话被删除的事件数
+    pub removed: BTreeMap<String, usize>,
+    /// 解除参与者关联的事件数；不记录涉及哪些会话，以免回执本身重新建立关联
+    #[serde(default)]
+    pub unlinked: usize,
+    /// 无法解析、因而未检查就原样保留的行数；不为 0 时其中可能仍有目标数据
+    #[serde(default)]
+    pub unchecked: usize,
+    /// 被重写的存储
+    pub stores: Vec<String>,
+}
+
+impl DeletionReceipt {
+    fn new(state: &ServerState, reason: &str, summary: RewriteSummary) -> Self {
+        let deleted_at = now_ms();
+        Self {
+            receipt_id: format!("deletion_{}_{}", deleted_at, RECEIPT_COUNTER.fetch_add(1, Ordering::Relaxed)),
+            deleted_at,
+            reason: reason.to_string(),
+            session_id: None,
+            cutoff: None,
+            removed: summary.removed,
+            unlinked: 0,
+            unchecked: summary.unchecked,
+            stores: vec![state.logger.file_path().display().to_string()],
+        }
+    }
+
+    pub fn total_removed(&self) -> usize {
+        self.removed.values().sum()
+    }
+
+    /// 在审计记录中写入删除数和未检查数
+    pub fn audit_counts(&self, entry: AuditEntry) -> AuditEntry {
+        entry
+            .count("removed", self.total_removed() as u64)
+            .count("unchecked", self.unchecked as u64)
+    }
+}
+
+/// 追加一条回执
+fn append_receipt(state: &ServerState, receipt: &DeletionReceipt) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&state.config.receipts_path)?;
+    writeln!(file, "{}", serde_json::to_string(receipt)?)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// 删除一个会话的全部事件
+///
+/// 即使会话没有任何事件也会写回执，作为删除请求已处理的记录。
+pub async fn delete_session(
+    state: &ServerState,
+    session_id: &str,
+    reason: &str,
+) -> Result<DeletionReceipt, Box<dyn std::error::Error>> {
+    let summary = state.logger.rewrite(|e| e.session_id != session_id).await?;
+
+    let mut receipt = DeletionReceipt::new(state, reason, summary);
+    receipt.session_id = Some(session_id.to_string());
+    append_receipt(state, &receipt)?;
+
+    Ok(receipt)
+}
+
+/// 清除已存储事件中的参与者假名，事件本身保留
+pub async fn unlink_participant(
+    state: &ServerState,
+    participant_id: &str,
+    reason: &str,
+) -> Result<DeletionReceipt, Box<dyn std::error::Error>> {
+    let summary = state
+        .logger
+        .rewrite(|e| {
+            if e.participant_id.as_deref() == Some(participant_id) {
+                e.participant_id = None;
+            }
+            true
+        })
+        .await?;
+
+    // 只清除字段、不删除事件，`removed` 恒为空
+    let unlinked = summary.modified.values().sum();
+    let mut receipt = DeletionReceipt::new(state, reason, summary);
+    receipt.unlinked = unlinked;
+    append_receipt(state, &receipt)?;
+
+    Ok(receipt)
+}
+
+/// 删除早于保留期限的事件，没有可删除的事件时返回 `None`
+pub async fn apply_retention(
+    state: &ServerState,
+    max_age_days: u64,
+) -> Result<Option<DeletionReceipt>, Box<dyn std::error::Error>> {
+    let cutoff = now_ms().saturating_sub(max_age_days * DAY_MS);
+    let summary = state.logger.rewrite(|e| e.timestamp >= cutoff).await?;
+    if summary.removed.is_empty() {
+        return Ok(None);
+    }
+
+    let mut receipt = DeletionReceipt::new(state, &format!("retention: older than {} days", max_age_days), summary);
+    receipt.cutoff = Some(cutoff);
+    append_receipt(state, &receipt)?;
+
+    Ok(Some(receipt))
+}
+
+/// 启动后台任务，按配置的间隔定期执行保留策略
+pub fn spawn_retention_task(state: AppState, config: RetentionConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let result = apply_retention(&state, config.max_age_days).await;
+            let entry = AuditEntry::new(now_ms(), "system:retention", "retention").param("max_age_days", config.max_age_days);
+            state.audit.record_result(entry, &result, |entry, receipt| match receipt {
+                Some(receipt) => receipt.audit_counts(entry),
+                None => entry.count("removed", 0),
+            });
+
+            match result {
+                Ok(Some(receipt)) => eprintln!(
+                    "retention: removed {} events, {} lines unchecked ({})",
+                    receipt.total_removed(),
+                    receipt.unchecked,
+                    receipt.receipt_id
+                ),
+                Ok(None) => {}
+                Err(e) => eprintln!("retention failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::{verify, CheckpointKey};
+    use crate::config::{EncryptionConfig, IntegrityConfig, ServerConfig};
+    use crate::encryption::{rotate_key, Keyring};
+    use crate::log_reader::LogReader;
+    use crate::test_util::{cli, config_in, event, run, temp_dir};
+    use std::path::Path;
+
+    /// 日志中剩下的 (会话, 事件) ID
+    fn stored(config: &ServerConfig) -> Vec<(String, String)> {
+        let keyring = config.encryption.as_ref().map(|e| Keyring::load(&e.key_file).unwrap());
+        LogReader::open(&config.log_path)
+            .unwrap()
+            .with_keyring(keyring.as_ref())
+            .map(|r| r.unwrap().event)
+            .map(|e| (e.session_id, e.event_id))
+            .collect()
+    }
+
+    fn receipts(path: &Path) -> Vec<DeletionReceipt> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    /// 默认、加密、哈希链、加密加哈希链四种存储设置
+    fn storage_configs(name: &str) -> Vec<ServerConfig> {
+        (0..4)
+            .map(|i| {
+                let dir = temp_dir(name);
+                let mut config = config_in(&dir);
+                if i & 1 != 0 {
+                    rotate_key(&dir.join("events.keys"), "k1").unwrap();
+                    config.encryption = Some(EncryptionConfig { key_file: dir.join("events.keys") });
+                }
+                if i & 2 != 0 {
+                    CheckpointKey::generate(&dir.join("checkpoint.key")).unwrap();
+                    config.integrity = Some(IntegrityConfig { key_file: dir.join("checkpoint.key"), checkpoint_every: 2 });
+                }
+                config
+            })
+            .collect()
+    }
+
+    #[test]
+    fn retention_removes_only_expired_events() {
+        let config = config_in(&temp_dir("retention-age"));
+        let state = ServerState::new(config.clone()).unwrap();
+        let now = now_ms();
+
+        run(async {
+            let events = [event("s1", "old", now - 200 * DAY_MS), event("s1", "new", now), event("s2", "edge", now - 179 * DAY_MS)];
+            state.logger.log_events(&events).await.unwrap();
+
+            let receipt = apply_retention(&state, 180).await.unwrap().unwrap();
+            assert_eq!(receipt.removed, BTreeMap::from([("s1".to_string(), 1)]));
+            let cutoff = receipt.cutoff.unwrap();
+            assert!(cutoff > now - 200 * DAY_MS && cutoff <= now - 179 * DAY_MS);
+            assert!(receipt.reason.contains("180 days"));
+
+            // 没有过期事件时不写回执
+            assert!(apply_retention(&state, 180).await.unwrap().is_none());
+        });
+
+        assert_eq!(stored(&config), [("s1".to_string(), "new".to_string()), ("s2".to_string(), "edge".to_string())]);
+        assert_eq!(receipts(&config.receipts_path).len(), 1);
+    }
+
+    #[test]
+    fn delete_session_works_for_every_storage_setting() {
+        for config in storage_configs("delete-storage") {
+            let state = ServerState::new(config.clone()).unwrap();
+            run(async {
+                for batch in [[event("s1", "e1", 1), event("s2", "e2", 2)], [event("s1", "e3", 3), event("s2", "e4", 4)]] {
+                    state.logger.log_events(&batch).await.unwrap();
+                }
+                let receipt = delete_session(&state, "s1", "withdrawal").await.unwrap();
+                assert_eq!((receipt.total_removed(), receipt.session_id.as_deref()), (2, Some("s1")));
+                assert_eq!(receipt.stores, [config.log_path.display().to_string()]);
+
+                // 重写后继续追加，链和加密段都接得上
+                state.logger.log_events(&[event("s3", "e5", 5)]).await.unwrap();
+            });
+
+            let ids: Vec<_> = stored(&config).into_iter().map(|(_, id)| id).collect();
+            assert_eq!(ids, ["e2", "e4", "e5"], "{:?}", config.encryption);
+            let text = std::fs::read_to_string(&config.log_path).unwrap();
+            assert_eq!(text.contains("\"e2\""), config.encryption.is_none());
+            if let Some(integrity) = &config.integrity {
+                let key = CheckpointKey::load(&integrity.key_file).unwrap();
+                let report = verify(&config.log_path, Some(&key)).unwrap();
+                assert!(report.problems.is_empty(), "{:?}", report.problems);
+                assert!(report.last_checkpoint.is_some());
+            }
+            assert_eq!(receipts(&config.receipts_path)[0].reason, "withdrawal");
+        }
+    }
+
+    #[test]
+    fn delete_session_over_http_writes_a_receipt_and_an_audit_record() {
+        use axum::extract::{ConnectInfo, Path, State};
+
+        let config = config_in(&temp_dir("delete-http"));
+        let state: AppState = std::sync::Arc::new(ServerState::new(config.clone()).unwrap());
+        run(async {
+            state.logger.log_events(&[event("s1", "e1", 1), event("s2", "e2", 2)]).await.unwrap();
+            let addr = ConnectInfo(std::net::SocketAddr::from(([127, 0, 0, 1], 3000)));
+            let axum::Json(receipt) = crate::mouse_handler::handle_delete_session(State(state.clone()), addr, None, Path("s1".to_string()))
+                .await
+                .unwrap();
+            assert_eq!(receipt.removed["s1"], 1);
+        });
+
+        assert_eq!(stored(&config), [("s2".to_string(), "e2".to_string())]);
+        assert_eq!(receipts(&config.receipts_path).len(), 1);
+        let audit = std::fs::read_to_string(&config.audit_path).unwrap();
+        assert!(audit.contains("delete_session") && audit.contains("\"s1\""));
+    }
+
+    #[test]
+    fn delete_session_and_retention_from_the_command_line() {
+        let config = config_in(&temp_dir("delete-cli"));
+        let old = now_ms() - 40 * DAY_MS;
+        run(ServerState::new(config.clone()).unwrap().logger.log_events(&[
+            event("s1", "e1", old),
+            event("s2", "e2", old),
+            event("s2", "e3", now_ms()),
+        ]))
+        .unwrap();
+
+        cli(&config, &["delete-session", "s1", "--reason", "withdrawal"]).unwrap();
+        assert_eq!(stored(&config).len(), 2);
+        assert!(cli(&config, &["retention"]).is_err(), "no --max-age-days and no retention configured");
+        cli(&config, &["retention", "--max-age-days", "30"]).unwrap();
+        assert_eq!(stored(&config), [("s2".to_string(), "e3".to_string())]);
+
+        let receipts = receipts(&config.receipts_path);
+        assert_eq!(receipts.len(), 2);
+        assert_eq!((receipts[0].reason.as_str(), receipts[0].total_removed()), ("withdrawal", 1));
+        assert_eq!(receipts[1].removed["s2"], 1);
+    }
+}
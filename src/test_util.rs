@@ -0,0 +1,63 @@
+//! 单元测试共用的辅助函数
+
+use crate::config::ServerConfig;
+use crate::types::MouseEvent;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// 为测试新建一个空的临时目录，同名的调用也各得到不同的目录
+pub fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "mouse-tracker-test-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// 所有文件都放在 `dir` 下的默认配置
+pub fn config_in(dir: &std::path::Path) -> ServerConfig {
+    ServerConfig::default().rooted(dir)
+}
+
+/// 只有必填字段的事件
+pub fn event(session_id: &str, event_id: &str, timestamp: u64) -> MouseEvent {
+    serde_json::from_value(serde_json::json!({
+        "event_type": "mousemove",
+        "timestamp": timestamp,
+        "x": 10,
+        "y": 20,
+        "session_id": session_id,
+        "event_id": event_id,
+    }))
+    .unwrap()
+}
+
+/// 在单线程运行时中执行异步代码
+pub fn run<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// 用 `config` 作为配置文件执行一个命令行子命令
+///
+/// 命令行从环境变量读取配置路径，这里串行执行以免并行的测试互相覆盖。
+pub fn cli(config: &ServerConfig, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    static ENV: Mutex<()> = Mutex::new(());
+    let _guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+
+    let path = temp_dir("config").join("mouse_tracker.json");
+    std::fs::write(&path, serde_json::to_string(config)?)?;
+    std::env::set_var(crate::config::CONFIG_ENV, &path);
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    run(crate::cli::run(&args))
+}
@@ -0,0 +1,1816 @@
+//! 可复用的交互追踪组件
+//!
+//! `<MouseTracker>` 包裹任意子元素，记录其中的鼠标、笔、触摸、滚轮、拖拽和键盘事件，
+//! 以及由触摸推导出的 pinch、rotate、swipe 手势，批量提交到服务器：
+//!
+//! ```ignore
+//! view! {
+//!     <MouseTracker endpoint="/api/projects/study-a" debounce_ms=250 attr:style="height: 400px">
+//!         <MyExperiment/>
+//!     </MouseTracker>
+//! }
+//! ```
+//!
+//! 浏览器把一帧内的指针移动合并为一个 `pointermove`，组件把它展开为逐个采样（`coalesced`），
+//! 需要时也记录浏览器预测的位置（`predicted_events`）。
+//!
+//! `pointer_lock` 开启时，在区域内按下指针会请求指针锁定；锁定期间 `x`、`y` 是由
+//! `movement_x/y` 累加得到的虚拟光标（`coordinate_mode` 为 `virtual`），不受屏幕边缘限制。
+//!
+//! 默认只记录组件区域内的事件；`capture=CaptureMode::Document` 时在 `window` 上以捕获阶段监听，
+//! 记录整个页面的事件，并用 `in_tracking_area` 标明事件是否发生在组件区域内。
+//!
+//! 子组件通过 [`use_tracker`] 取得 [`TrackerHandle`]，可以立即发送、暂停、恢复和读取统计；
+//! 组件之外需要控制时，先用 [`TrackerHandle::new`] 创建句柄再通过 `handle` 传入。
+
+use crate::types::{ClientConfig, EVENT_TYPES};
+use leptos::prelude::*;
+
+#[cfg(any(feature = "hydrate", test))]
+use crate::types::KeyClass;
+#[cfg(feature = "hydrate")]
+use crate::types::{ConsentDecision, ConsentResponse, MouseEvent, TouchPoint, PARTICIPANT_HEADER};
+#[cfg(feature = "hydrate")]
+use std::cell::RefCell;
+#[cfg(feature = "hydrate")]
+use std::collections::HashMap;
+#[cfg(feature = "hydrate")]
+use std::rc::Rc;
+#[cfg(feature = "hydrate")]
+use wasm_bindgen::prelude::*;
+#[cfg(feature = "hydrate")]
+use wasm_bindgen_futures::JsFuture;
+#[cfg(feature = "hydrate")]
+use web_sys::{Headers, KeyboardEvent, Request, RequestInit, RequestMode, Response};
+
+/// 最后一个事件之后等待多久发送（毫秒）
+pub const DEFAULT_DEBOUNCE_MS: i32 = 500;
+
+/// 缓冲达到该数量时立即发送
+pub const DEFAULT_MAX_BATCH: usize = 100;
+
+/// swipe 的最小移动距离（像素）
+#[cfg(any(feature = "hydrate", test))]
+const SWIPE_MIN_DISTANCE: f64 = 50.0;
+
+/// swipe 的最长持续时间（毫秒）
+#[cfg(any(feature = "hydrate", test))]
+const SWIPE_MAX_MS: f64 = 500.0;
+
+/// 两指手势结束时缩放比例变化超过该值记为 pinch
+#[cfg(any(feature = "hydrate", test))]
+const PINCH_MIN_SCALE_CHANGE: f64 = 0.1;
+
+/// 两指手势结束时旋转超过该角度（度）记为 rotate
+#[cfg(any(feature = "hydrate", test))]
+const ROTATE_MIN_DEGREES: f64 = 15.0;
+
+/// 总是不记录的元素：密码框，以及带 `data-no-track` 属性的元素及其后代
+pub const EXCLUDED_SELECTOR: &str = "input[type=password], [data-no-track]";
+
+/// 键盘事件的记录粒度
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeystrokeMode {
+    /// 记录 key 和 code
+    #[default]
+    Full,
+    /// 只记录按键类别（letter、digit、navigation、modifier 等）和时间，不记录具体字符
+    ClassOnly,
+}
+
+/// 事件监听范围
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// 只监听组件区域
+    #[default]
+    Area,
+    /// 在 `window` 上以捕获阶段监听整个页面，拖拽移出区域、在其他元素上的操作也会记录
+    Document,
+}
+
+/// 追踪统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackerStats {
+    /// 已记录的事件数
+    pub recorded: u64,
+    /// 服务器已接收的事件数
+    pub sent: u64,
+    /// 发送失败而丢弃的事件数
+    pub failed: u64,
+    /// 缓冲中等待发送的事件数
+    pub pending: usize,
+}
+
+/// 页面状态事件：滚动、窗口大小、标签页可见性和窗口焦点，无论监听范围如何都在 `window` / `document` 上监听
+pub const PAGE_EVENT_TYPES: &[&str] = &["scroll", "resize", "visibilitychange", "focus", "blur", "pointerlockchange"];
+
+/// 记录的事件类型对应的 DOM 事件
+///
+/// 按下、移动、释放通过 Pointer Events 监听，鼠标、笔和触摸都能收到，
+/// 仍记为 mousedown/mousemove/mouseup 以兼容已有的数据处理，设备由 `pointer_type` 区分。
+#[cfg(feature = "hydrate")]
+fn dom_event(event_type: &'static str) -> &'static str {
+    match event_type {
+        "mousemove" => "pointermove",
+        "mousedown" => "pointerdown",
+        "mouseup" => "pointerup",
+        other => other,
+    }
+}
+
+/// 128 位随机数的十六进制形式
+///
+/// 使用 `crypto.getRandomValues`：它在非安全上下文（非 localhost 的 HTTP 页面）中也可用，
+/// 而 `crypto.randomUUID` 只在安全上下文中存在。
+#[cfg(feature = "hydrate")]
+fn random_id() -> Option<String> {
+    let mut bytes = [0u8; 16];
+    web_sys::window()?.crypto().ok()?.get_random_values_with_u8_array(&mut bytes).ok()?;
+    Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// 追踪器的控制句柄，可复制
+#[derive(Clone, Copy)]
+pub struct TrackerHandle {
+    session_id: StoredValue<String>,
+    endpoint: StoredValue<String>,
+    stats: RwSignal<TrackerStats>,
+    paused: RwSignal<bool>,
+    /// 每次请求立即发送时加一，由组件响应
+    flush_requests: RwSignal<u64>,
+    /// 服务器下发的采集设置，取得前为 None
+    client_config: RwSignal<Option<ClientConfig>>,
+}
+
+impl TrackerHandle {
+    /// 创建句柄并生成新的随机会话 ID（服务器端渲染时为空）
+    ///
+    /// 会话 ID 不可猜测，其他人无法冒用它提交事件或决定。
+    pub fn new() -> Self {
+        #[cfg(feature = "hydrate")]
+        let session_id = format!(
+            "session_{}",
+            random_id().unwrap_or_else(|| {
+                leptos::logging::warn!("crypto.getRandomValues failed, session id falls back to Math.random");
+                (0..4).map(|_| format!("{:08x}", (js_sys::Math::random() * 4294967296.0) as u32)).collect()
+            })
+        );
+        #[cfg(not(feature = "hydrate"))]
+        let session_id = String::new();
+
+        Self {
+            session_id: StoredValue::new(session_id),
+            endpoint: StoredValue::new("/api".to_string()),
+            stats: RwSignal::new(TrackerStats::default()),
+            paused: RwSignal::new(false),
+            flush_requests: RwSignal::new(0),
+            client_config: RwSignal::new(None),
+        }
+    }
+
+    pub fn session_id(&self) -> String {
+        self.session_id.get_value()
+    }
+
+    /// 所属 `<MouseTracker>` 的 API 地址前缀
+    pub fn endpoint(&self) -> String {
+        self.endpoint.get_value()
+    }
+
+    /// 服务器下发的采集设置（`<endpoint>/client-config`，响应式），取得前为 None
+    ///
+    /// 同意横幅应使用其中的 `consent_version`；取得失败时为 [`ClientConfig::default`]。
+    pub fn client_config(&self) -> Option<ClientConfig> {
+        self.client_config.get()
+    }
+
+    /// 当前统计（响应式）
+    pub fn stats(&self) -> TrackerStats {
+        self.stats.get()
+    }
+
+    /// 是否已暂停（响应式）
+    pub fn is_paused(&self) -> bool {
+        self.paused.get()
+    }
+
+    /// 暂停记录，已缓冲的事件仍会发送
+    pub fn pause(&self) {
+        self.paused.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.set(false);
+    }
+
+    /// 立即发送缓冲中的事件
+    pub fn flush(&self) {
+        self.flush_requests.update(|n| *n += 1);
+    }
+}
+
+impl Default for TrackerHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 取得外层 `<MouseTracker>` 的句柄
+pub fn use_tracker() -> Option<TrackerHandle> {
+    use_context::<TrackerHandle>()
+}
+
+/// 组件属性整理后的设置
+#[derive(Clone)]
+#[cfg_attr(not(feature = "hydrate"), allow(dead_code))]
+struct TrackerOptions {
+    endpoint: String,
+    event_types: Vec<String>,
+    debounce_ms: i32,
+    max_batch: usize,
+    keystroke_mode: KeystrokeMode,
+    exclude: String,
+    participant_token: bool,
+    enabled: Signal<bool>,
+    area: NodeRef<leptos::html::Div>,
+    coalesced_events: bool,
+    predicted_events: bool,
+    pointer_lock: bool,
+}
+
+/// 记录子元素中的交互事件并批量提交到服务器
+#[component]
+pub fn MouseTracker(
+    /// API 地址前缀，事件提交到 `<endpoint>/mouse`；多项目时为 `/api/projects/<名称>`，跨域时写完整地址
+    #[prop(into, default = "/api".to_string())]
+    endpoint: String,
+    /// 记录的事件类型，默认为全部
+    #[prop(optional)]
+    event_types: Option<Vec<String>>,
+    /// 最后一个事件之后等待多久发送（毫秒）
+    #[prop(default = DEFAULT_DEBOUNCE_MS)]
+    debounce_ms: i32,
+    /// 缓冲达到该数量时立即发送
+    #[prop(default = DEFAULT_MAX_BATCH)]
+    max_batch: usize,
+    #[prop(optional)]
+    keystroke_mode: KeystrokeMode,
+    /// 监听组件区域还是整个页面
+    #[prop(optional)]
+    capture: CaptureMode,
+    /// 在组件区域内阻止浏览器的右键菜单（contextmenu 事件仍会记录）
+    #[prop(optional)]
+    suppress_context_menu: bool,
+    /// 在区域内按下指针时请求指针锁定，锁定期间记录相对移动和虚拟光标位置
+    #[prop(optional)]
+    pointer_lock: bool,
+    /// 把同一帧内合并的指针移动展开为逐个采样
+    #[prop(default = true)]
+    coalesced_events: bool,
+    /// 同时记录浏览器预测的指针位置
+    #[prop(optional)]
+    predicted_events: bool,
+    /// 额外不记录的元素（CSS 选择器）；密码框和 `data-no-track` 总是不记录
+    #[prop(optional, into)]
+    exclude: Option<String>,
+    /// 是否随事件提交参与者令牌
+    #[prop(default = true)]
+    participant_token: bool,
+    /// 为 false 时不记录，并丢弃尚未发送的事件（例如撤回同意）
+    #[prop(into, default = Signal::stored(true))]
+    enabled: Signal<bool>,
+    /// 外部创建的句柄；未提供时新建
+    #[prop(optional)]
+    handle: Option<TrackerHandle>,
+    children: Children,
+) -> impl IntoView {
+    let handle = handle.unwrap_or_default();
+    handle.endpoint.set_value(endpoint.clone());
+    provide_context(handle);
+
+    let area = NodeRef::<leptos::html::Div>::new();
+    let options = TrackerOptions {
+        endpoint,
+        event_types: event_types.unwrap_or_else(|| EVENT_TYPES.iter().map(|t| t.to_string()).collect()),
+        debounce_ms,
+        max_batch,
+        keystroke_mode,
+        exclude: match exclude {
+            Some(extra) => format!("{}, {}", EXCLUDED_SELECTOR, extra),
+            None => EXCLUDED_SELECTOR.to_string(),
+        },
+        participant_token,
+        enabled,
+        area,
+        coalesced_events,
+        predicted_events,
+        pointer_lock,
+    };
+
+    #[cfg(feature = "hydrate")]
+    {
+        let config_endpoint = options.endpoint.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let config = fetch_client_config(&config_endpoint).await.unwrap_or_else(|e| {
+                web_sys::console::log_1(&format!("Failed to fetch client config: {:?}", e).into());
+                ClientConfig::default()
+            });
+            handle.client_config.set(Some(config));
+        });
+    }
+
+    #[cfg(feature = "hydrate")]
+    let recorder = StoredValue::new_local(Rc::new(Recorder::new(options, handle)));
+    #[cfg(not(feature = "hydrate"))]
+    let _ = options;
+
+    #[cfg(feature = "hydrate")]
+    {
+        Effect::new(move |_| {
+            handle.flush_requests.track();
+            recorder.with_value(|r| r.flush());
+        });
+        Effect::new(move |_| {
+            if !enabled.get() {
+                recorder.with_value(|r| r.discard());
+            }
+        });
+
+        let listeners = StoredValue::new_local(PageListeners::install(recorder, capture));
+        on_cleanup(move || {
+            // 卸载时发送缓冲中的事件，之后不再有防抖定时器触发
+            let _ = recorder.try_with_value(|r| r.flush());
+            let _ = listeners.try_with_value(|l| l.remove());
+            if pointer_lock {
+                if let Some(document) = web_sys::window().and_then(|w| w.document()) {
+                    document.exit_pointer_lock();
+                }
+            }
+        });
+    }
+
+    // 整页监听时区域本身不再重复记录
+    let record = move |event_type: &'static str, event: &web_sys::Event| {
+        if capture == CaptureMode::Document {
+            return;
+        }
+        #[cfg(feature = "hydrate")]
+        recorder.with_value(|r| r.record(event_type, event));
+        #[cfg(not(feature = "hydrate"))]
+        let _ = (event_type, event);
+    };
+
+    view! {
+        <div
+            node_ref=area
+            tabindex="0"
+            on:pointermove=move |e| record("mousemove", &e)
+            on:pointerdown=move |e| {
+                // 请求指针锁定需要用户手势，因此在区域自身的监听器中进行
+                #[cfg(feature = "hydrate")]
+                if pointer_lock && enabled.get_untracked() {
+                    if let Some(area) = area.get_untracked() {
+                        let locked = area.owner_document().and_then(|d| d.pointer_lock_element()).is_some();
+                        if !locked {
+                            area.request_pointer_lock();
+                        }
+                    }
+                }
+                record("mousedown", &e)
+            }
+            on:pointerup=move |e| record("mouseup", &e)
+            on:pointercancel=move |e| record("pointercancel", &e)
+            on:touchstart=move |e| record("touchstart", &e)
+            on:touchmove=move |e| record("touchmove", &e)
+            on:touchend=move |e| record("touchend", &e)
+            on:touchcancel=move |e| record("touchcancel", &e)
+            on:wheel=move |e| record("wheel", &e)
+            on:dragstart=move |e| record("dragstart", &e)
+            on:drag=move |e| record("drag", &e)
+            on:dragend=move |e| record("dragend", &e)
+            on:keydown=move |e| record("keydown", &e)
+            on:keyup=move |e| record("keyup", &e)
+            on:click=move |e| record("click", &e)
+            on:dblclick=move |e| record("dblclick", &e)
+            on:auxclick=move |e| record("auxclick", &e)
+            on:contextmenu=move |e| {
+                // 整页监听器是 passive 的，阻止默认行为只能在区域自身的监听器中进行
+                if suppress_context_menu {
+                    e.prevent_default();
+                }
+                record("contextmenu", &e)
+            }
+        >
+            {children()}
+        </div>
+    }
+}
+
+// ============================================================================
+// IL 训练数据收集 - 会话和事件管理
+// ============================================================================
+
+#[cfg(feature = "hydrate")]
+#[derive(Clone)]
+pub struct TrackingState {
+    pub session_id: String,
+    pub event_counter: Rc<RefCell<u64>>,
+    pub last_event: Rc<RefCell<Option<(MouseEvent, f64)>>>, // (上一次事件, 上一次时间戳)
+    pub drag_state: Rc<RefCell<Option<String>>>, // 当前拖拽的事件 ID
+    virtual_cursor: Rc<RefCell<Option<(f64, f64)>>>, // 指针锁定期间的虚拟光标位置
+    viewport: Rc<RefCell<Option<(u32, u32)>>>,      // 视口大小，只在 resize 时重新读取（无论是否记录该事件）
+    pub presses: Rc<RefCell<HashMap<String, String>>>, // 各按钮最近一次 mousedown 的事件 ID
+    pub keystroke_mode: KeystrokeMode,
+    strokes: Rc<RefCell<HashMap<i32, Stroke>>>, // 按触点 ID 记录的进行中笔画
+    pinch: Rc<RefCell<Option<Pinch>>>,          // 进行中的两指手势
+}
+
+/// 一次触摸（从 touchstart 到 touchend）的起点
+#[cfg(feature = "hydrate")]
+#[derive(Clone)]
+struct Stroke {
+    event_id: String,
+    x: i32,
+    y: i32,
+    time: f64,
+    /// 期间有过两指手势，不再判断 swipe
+    multi: bool,
+}
+
+/// 两指手势开始时的距离和角度，以及当前的缩放和旋转
+#[cfg(any(feature = "hydrate", test))]
+#[derive(Clone)]
+struct Pinch {
+    parent: Option<String>,
+    distance: f64,
+    angle: f64,
+    scale: f64,
+    rotation: f64,
+}
+
+#[cfg(any(feature = "hydrate", test))]
+impl Pinch {
+    /// 两根手指按下时开始，`distance` 和 `angle` 见 [`span`]
+    fn start(parent: Option<String>, (distance, angle): (f64, f64)) -> Self {
+        Self { parent, distance, angle, scale: 1.0, rotation: 0.0 }
+    }
+
+    /// 用两指当前的距离和角度更新缩放和旋转
+    fn update(&mut self, (distance, angle): (f64, f64)) {
+        // 起始距离为 0（两指重合）时无法计算比例，保持上一次的值
+        if self.distance > 0.0 {
+            self.scale = distance / self.distance;
+        }
+        // 归一化到 -180..180
+        self.rotation = (angle - self.angle + 540.0).rem_euclid(360.0) - 180.0;
+    }
+
+    /// 手势结束时达到阈值的缩放比例和旋转角度，分别记为 pinch 和 rotate
+    fn finish(&self) -> (Option<f64>, Option<f64>) {
+        (
+            ((self.scale - 1.0).abs() >= PINCH_MIN_SCALE_CHANGE).then_some(self.scale),
+            (self.rotation.abs() >= ROTATE_MIN_DEGREES).then_some(self.rotation),
+        )
+    }
+}
+
+/// 单指抬起时的位移（像素）和用时（毫秒）是否构成 swipe，是则返回方向
+#[cfg(any(feature = "hydrate", test))]
+fn swipe_direction(dx: f64, dy: f64, dt: f64) -> Option<&'static str> {
+    if (dx * dx + dy * dy).sqrt() < SWIPE_MIN_DISTANCE || dt > SWIPE_MAX_MS {
+        return None;
+    }
+    Some(match (dx.abs() >= dy.abs(), dx >= 0.0, dy >= 0.0) {
+        (true, true, _) => "right",
+        (true, false, _) => "left",
+        (false, _, true) => "down",
+        (false, _, false) => "up",
+    })
+}
+
+#[cfg(feature = "hydrate")]
+impl TrackingState {
+    pub fn new(session_id: String) -> Self {
+        Self {
+            session_id,
+            event_counter: Rc::new(RefCell::new(0)),
+            last_event: Rc::new(RefCell::new(None)),
+            drag_state: Rc::new(RefCell::new(None)),
+            presses: Rc::new(RefCell::new(HashMap::new())),
+            viewport: Rc::new(RefCell::new(None)),
+            virtual_cursor: Rc::new(RefCell::new(None)),
+            keystroke_mode: KeystrokeMode::Full,
+            strokes: Rc::new(RefCell::new(HashMap::new())),
+            pinch: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    pub fn with_keystroke_mode(mut self, mode: KeystrokeMode) -> Self {
+        self.keystroke_mode = mode;
+        self
+    }
+
+    pub fn generate_event_id(&self) -> String {
+        let count = *self.event_counter.borrow();
+        *self.event_counter.borrow_mut() += 1;
+        format!("event_{}_{}", self.session_id, count)
+    }
+
+    /// 当前视口大小；`refresh` 为 true 或尚未读取时重新读取
+    fn viewport(&self, refresh: bool) -> Option<(u32, u32)> {
+        let mut viewport = self.viewport.borrow_mut();
+        if refresh || viewport.is_none() {
+            let window = web_sys::window()?;
+            let width = window.inner_width().ok()?.as_f64()? as u32;
+            let height = window.inner_height().ok()?.as_f64()? as u32;
+            *viewport = Some((width, height));
+        }
+        *viewport
+    }
+
+    /// 填写触点和所属笔画，返回本次事件结束的手势
+    ///
+    /// 与拖拽一样，touchstart 开始一条笔画，之后该触点的事件以它为父事件。
+    fn track_touches(&self, event: &mut MouseEvent, touch_evt: &web_sys::TouchEvent) -> Vec<MouseEvent> {
+        let now = event.event_timestamp.unwrap_or(event.timestamp as f64);
+        let changed = touch_list(&touch_evt.changed_touches());
+        let active = touch_list(&touch_evt.touches());
+        let mut strokes = self.strokes.borrow_mut();
+
+        if event.event_type == "touchstart" {
+            for touch in &changed {
+                strokes.insert(touch.identifier(), Stroke {
+                    event_id: event.event_id.clone(),
+                    x: touch.client_x(),
+                    y: touch.client_y(),
+                    time: now,
+                    multi: false,
+                });
+            }
+        } else if let Some(first) = changed.first() {
+            event.parent_event_id = strokes.get(&first.identifier()).map(|s| s.event_id.clone());
+        }
+
+        // 活动触点在前，本次抬起的触点（不在 touches 中）在后
+        let is_changed = |id: i32| changed.iter().any(|t| t.identifier() == id);
+        let mut points: Vec<TouchPoint> = active
+            .iter()
+            .map(|t| touch_point(t, is_changed(t.identifier()), &strokes))
+            .collect();
+        points.extend(
+            changed
+                .iter()
+                .filter(|t| !active.iter().any(|a| a.identifier() == t.identifier()))
+                .map(|t| touch_point(t, true, &strokes)),
+        );
+        event.touches = Some(points);
+
+        let mut gestures = Vec::new();
+        let mut pinch = self.pinch.borrow_mut();
+        let position = |t: &web_sys::Touch| (t.client_x(), t.client_y());
+        match (active.as_slice(), pinch.take()) {
+            ([a, b], None) => {
+                for stroke in strokes.values_mut() {
+                    stroke.multi = true;
+                }
+                let parent = strokes.get(&a.identifier()).map(|s| s.event_id.clone());
+                *pinch = Some(Pinch::start(parent, span(position(a), position(b))));
+            }
+            ([a, b], Some(mut p)) => {
+                p.update(span(position(a), position(b)));
+                *pinch = Some(p);
+            }
+            (_, Some(p)) => {
+                // 手指数不再是两根，两指手势结束
+                let (scale, rotation) = p.finish();
+                if let Some(scale) = scale {
+                    let mut g = self.gesture_event(event, "pinch", p.parent.clone());
+                    g.gesture_scale = Some(scale);
+                    gestures.push(g);
+                }
+                if let Some(rotation) = rotation {
+                    let mut g = self.gesture_event(event, "rotate", p.parent);
+                    g.gesture_rotation = Some(rotation);
+                    gestures.push(g);
+                }
+            }
+            (_, None) => {}
+        }
+
+        if matches!(event.event_type.as_str(), "touchend" | "touchcancel") {
+            for touch in &changed {
+                let Some(stroke) = strokes.remove(&touch.identifier()) else {
+                    continue;
+                };
+                if event.event_type == "touchcancel" || stroke.multi || !active.is_empty() {
+                    continue;
+                }
+                let dx = (touch.client_x() - stroke.x) as f64;
+                let dy = (touch.client_y() - stroke.y) as f64;
+                let dt = now - stroke.time;
+                if let Some(direction) = swipe_direction(dx, dy, dt) {
+                    let distance = (dx * dx + dy * dy).sqrt();
+                    let mut g = self.gesture_event(event, "swipe", Some(stroke.event_id));
+                    g.swipe_direction = Some(direction.to_string());
+                    g.distance = Some(distance);
+                    g.velocity_x = (dt > 0.0).then(|| dx / dt);
+                    g.velocity_y = (dt > 0.0).then(|| dy / dt);
+                    gestures.push(g);
+                }
+            }
+        }
+        gestures
+    }
+
+    /// 由触摸事件推导的手势事件，位置和上下文取自该触摸事件
+    fn gesture_event(&self, base: &MouseEvent, event_type: &str, parent: Option<String>) -> MouseEvent {
+        MouseEvent {
+            event_type: event_type.to_string(),
+            event_id: self.generate_event_id(),
+            parent_event_id: parent,
+            touches: None,
+            velocity_x: None,
+            velocity_y: None,
+            distance: None,
+            ..base.clone()
+        }
+    }
+}
+
+#[cfg(feature = "hydrate")]
+fn touch_list(list: &web_sys::TouchList) -> Vec<web_sys::Touch> {
+    (0..list.length()).filter_map(|i| list.get(i)).collect()
+}
+
+#[cfg(feature = "hydrate")]
+fn touch_point(touch: &web_sys::Touch, changed: bool, strokes: &HashMap<i32, Stroke>) -> TouchPoint {
+    TouchPoint {
+        identifier: touch.identifier(),
+        x: touch.client_x(),
+        y: touch.client_y(),
+        radius_x: Some(touch.radius_x() as f64),
+        radius_y: Some(touch.radius_y() as f64),
+        rotation_angle: Some(touch.rotation_angle() as f64),
+        force: Some(touch.force() as f64),
+        changed,
+        stroke_id: strokes.get(&touch.identifier()).map(|s| s.event_id.clone()),
+    }
+}
+
+/// 两个触点之间的距离和连线角度（度）
+#[cfg(any(feature = "hydrate", test))]
+fn span(a: (i32, i32), b: (i32, i32)) -> (f64, f64) {
+    let dx = (b.0 - a.0) as f64;
+    let dy = (b.1 - a.1) as f64;
+    ((dx * dx + dy * dy).sqrt(), dy.atan2(dx).to_degrees())
+}
+
+/// 按下开始一次点击：mousedown 记下该按钮的事件 ID，返回 mouseup 和之后的 click/dblclick/auxclick/contextmenu 的父事件
+#[cfg(any(feature = "hydrate", test))]
+fn link_press(
+    presses: &mut std::collections::HashMap<String, String>,
+    event_type: &str,
+    button: Option<&str>,
+    event_id: &str,
+) -> Option<String> {
+    let button = button?;
+    match event_type {
+        "mousedown" => {
+            presses.insert(button.to_string(), event_id.to_string());
+            None
+        }
+        "mouseup" | "click" | "dblclick" | "auxclick" | "contextmenu" => presses.get(button).cloned(),
+        _ => None,
+    }
+}
+
+/// 触摸产生的指针按下、移动、释放和取消：记录 touch* 事件时由它们记录，不再重复记为 mouse* 事件
+#[cfg(any(feature = "hydrate", test))]
+fn is_duplicate_touch(event_type: &str, pointer_type: Option<&str>, event_types: &[String]) -> bool {
+    matches!(event_type, "mousemove" | "mousedown" | "mouseup" | "pointercancel")
+        && pointer_type == Some("touch")
+        && event_types.iter().any(|t| t.starts_with("touch"))
+}
+
+/// 一个已注册的监听器：目标、记录的事件类型、是否捕获阶段、回调
+#[cfg(feature = "hydrate")]
+type Listener = (web_sys::EventTarget, &'static str, bool, Closure<dyn Fn(web_sys::Event)>);
+
+/// 注册在 `window` / `document` 上的监听器，组件卸载时移除
+///
+/// 页面事件总是注册；整页监听时另外以捕获阶段注册交互事件。
+#[cfg(feature = "hydrate")]
+struct PageListeners {
+    listeners: Vec<Listener>,
+}
+
+#[cfg(feature = "hydrate")]
+impl PageListeners {
+    fn install(recorder: StoredValue<Rc<Recorder>, LocalStorage>, capture: CaptureMode) -> Self {
+        let window = web_sys::window().expect("Window not available");
+        let document = window.document().expect("Document not available");
+        let mut this = Self { listeners: Vec::new() };
+
+        if capture == CaptureMode::Document {
+            // 手势由触摸事件推导，没有对应的 DOM 事件
+            let interaction = EVENT_TYPES
+                .iter()
+                .filter(|t| !matches!(**t, "pinch" | "rotate" | "swipe") && !PAGE_EVENT_TYPES.contains(*t));
+            for &event_type in interaction {
+                this.add(&window, event_type, true, recorder);
+            }
+        }
+        for &event_type in PAGE_EVENT_TYPES {
+            let target: &web_sys::EventTarget = match event_type {
+                "visibilitychange" | "pointerlockchange" => &document,
+                _ => &window,
+            };
+            // 不用捕获阶段：window 上只收到页面自身的滚动和窗口自身的焦点变化
+            this.add(target, event_type, false, recorder);
+        }
+
+        // 页面关闭或进入往返缓存前发送缓冲中的事件，普通请求可能随页面一起被取消
+        let callback = Closure::<dyn Fn(web_sys::Event)>::new(move |_: web_sys::Event| {
+            recorder.try_with_value(|r| r.flush_beacon());
+        });
+        this.listen(&window, "pagehide", false, callback);
+        this
+    }
+
+    fn add(
+        &mut self,
+        target: &web_sys::EventTarget,
+        event_type: &'static str,
+        capture: bool,
+        recorder: StoredValue<Rc<Recorder>, LocalStorage>,
+    ) {
+        let callback = Closure::<dyn Fn(web_sys::Event)>::new(move |event: web_sys::Event| {
+            recorder.try_with_value(|r| r.record(event_type, &event));
+        });
+        self.listen(target, event_type, capture, callback);
+    }
+
+    fn listen(
+        &mut self,
+        target: &web_sys::EventTarget,
+        event_type: &'static str,
+        capture: bool,
+        callback: Closure<dyn Fn(web_sys::Event)>,
+    ) {
+        let options = web_sys::AddEventListenerOptions::new();
+        options.set_capture(capture);
+        options.set_passive(true);
+        let _ = target.add_event_listener_with_callback_and_add_event_listener_options(
+            dom_event(event_type),
+            callback.as_ref().unchecked_ref(),
+            &options,
+        );
+        self.listeners.push((target.clone(), event_type, capture, callback));
+    }
+
+    fn remove(&self) {
+        for (target, event_type, capture, callback) in &self.listeners {
+            // 移除时的 capture 必须与注册时一致
+            let _ = target.remove_event_listener_with_callback_and_bool(
+                dom_event(event_type),
+                callback.as_ref().unchecked_ref(),
+                *capture,
+            );
+        }
+    }
+}
+
+/// 记录的采样来源
+#[cfg(feature = "hydrate")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Sample {
+    /// 浏览器派发的事件
+    Dispatched,
+    /// 同一帧内被合并、未单独派发的指针移动
+    Coalesced,
+    /// 浏览器预测的指针位置
+    Predicted,
+}
+
+/// 调用 PointerEvent 上返回事件数组的方法；浏览器不支持时返回空
+#[cfg(feature = "hydrate")]
+fn pointer_events(event: &web_sys::PointerEvent, method: &str) -> Vec<web_sys::Event> {
+    js_sys::Reflect::get(event, &JsValue::from_str(method))
+        .ok()
+        .and_then(|f| f.dyn_into::<js_sys::Function>().ok())
+        .and_then(|f| f.call0(event).ok())
+        .and_then(|a| a.dyn_into::<js_sys::Array>().ok())
+        .map(|a| a.iter().filter_map(|e| e.dyn_into::<web_sys::Event>().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// 事件的单调时钟时间和页面的 timeOrigin
+#[cfg(feature = "hydrate")]
+fn monotonic_time(event: &web_sys::Event) -> (f64, Option<f64>) {
+    let performance = web_sys::window().and_then(|w| w.performance());
+    let now = || performance.as_ref().map_or_else(js_sys::Date::now, |p| p.now());
+    (time_stamp_or_now(event.time_stamp(), now), performance.as_ref().map(|p| p.time_origin()))
+}
+
+/// 合成事件没有 timeStamp（为 0）时取当前时间
+#[cfg(any(feature = "hydrate", test))]
+fn time_stamp_or_now(time_stamp: f64, now: impl FnOnce() -> f64) -> f64 {
+    match time_stamp {
+        t if t > 0.0 => t,
+        _ => now(),
+    }
+}
+
+/// 事件发生的时间（Unix 毫秒，带小数）
+#[cfg(feature = "hydrate")]
+fn event_time(event: &web_sys::Event) -> f64 {
+    match web_sys::window().and_then(|w| w.performance()) {
+        Some(performance) => performance.time_origin() + event.time_stamp(),
+        None => js_sys::Date::now(),
+    }
+}
+
+/// 按一次派发的 pointermove 移动虚拟光标，返回每个记录的采样（较早的合并采样，最后是派发事件）的位置
+///
+/// `coalesced` 为各合并采样自身的移动量，最后一个就是派发事件。派发事件的 movement 是自上次派发以来
+/// 的总移动，等于合并采样之和，有合并采样时只按它们累加，否则同一段移动会算两次。
+#[cfg(any(feature = "hydrate", test))]
+fn move_cursor(pos: &mut (f64, f64), dispatched: (i32, i32), coalesced: &[(i32, i32)]) -> Vec<(f64, f64)> {
+    let movements = match coalesced.is_empty() {
+        true => std::slice::from_ref(&dispatched),
+        false => coalesced,
+    };
+    movements
+        .iter()
+        .map(|&(dx, dy)| {
+            *pos = (pos.0 + dx as f64, pos.1 + dy as f64);
+            *pos
+        })
+        .collect()
+}
+
+/// 事件缓冲和防抖发送
+#[cfg(feature = "hydrate")]
+struct Recorder {
+    options: TrackerOptions,
+    handle: TrackerHandle,
+    state: TrackingState,
+    buffer: RefCell<Vec<MouseEvent>>,
+    timeout: RefCell<Option<i32>>,
+}
+
+#[cfg(feature = "hydrate")]
+impl Recorder {
+    fn new(options: TrackerOptions, handle: TrackerHandle) -> Self {
+        // 无效的选择器会使所有带目标元素的事件都不被记录，创建时提示
+        let document = web_sys::window().and_then(|w| w.document());
+        if document.is_some_and(|d| d.query_selector(&options.exclude).is_err()) {
+            leptos::logging::warn!("MouseTracker: invalid exclude selector {:?}; events with a target element are not recorded", options.exclude);
+        }
+        let state = TrackingState::new(handle.session_id()).with_keystroke_mode(options.keystroke_mode);
+        Self {
+            options,
+            handle,
+            state,
+            buffer: RefCell::new(Vec::new()),
+            timeout: RefCell::new(None),
+        }
+    }
+
+    /// 记录一个事件：未启用、已暂停、类型未选择或目标不记录时忽略
+    fn record(self: &Rc<Self>, event_type: &'static str, event: &web_sys::Event) {
+        // 锁定状态和视口大小总是跟踪，即使暂停、未启用或对应事件不在记录的类型中
+        if event_type == "pointerlockchange" && self.options.pointer_lock {
+            self.update_pointer_lock();
+        }
+        if event_type == "resize" {
+            self.state.viewport(true);
+        }
+        if !self.options.enabled.get_untracked()
+            || self.handle.paused.get_untracked()
+            || !self.options.event_types.iter().any(|t| t == event_type)
+            || is_excluded(event, &self.options.exclude)
+        {
+            return;
+        }
+        if self.is_duplicate_touch(event_type, event) {
+            return;
+        }
+
+        let Some(pointer_evt) = event.dyn_ref::<web_sys::PointerEvent>().filter(|_| event_type == "mousemove") else {
+            self.record_sample(event_type, event, Sample::Dispatched, None);
+            return;
+        };
+        let coalesced = match self.options.coalesced_events {
+            true => pointer_events(pointer_evt, "getCoalescedEvents"),
+            false => Vec::new(),
+        };
+        // 指针锁定期间一次算好每个采样的虚拟光标位置，派发事件不再自行累加
+        let positions = self.state.virtual_cursor.borrow_mut().as_mut().filter(|_| self.options.pointer_lock).map(|pos| {
+            let movements: Vec<(i32, i32)> = coalesced
+                .iter()
+                .map(|e| e.dyn_ref::<web_sys::MouseEvent>().map_or((0, 0), |e| (e.movement_x(), e.movement_y())))
+                .collect();
+            move_cursor(pos, (pointer_evt.movement_x(), pointer_evt.movement_y()), &movements)
+        });
+        let position = |i: usize| positions.as_ref().and_then(|p| p.get(i).copied());
+
+        // 最后一个合并采样就是派发的事件本身
+        if let Some((_, earlier)) = coalesced.split_last() {
+            for (i, sample) in earlier.iter().enumerate() {
+                self.record_sample(event_type, sample, Sample::Coalesced, position(i));
+            }
+        }
+        let last = positions.as_ref().and_then(|p| p.last().copied());
+        self.record_sample(event_type, event, Sample::Dispatched, last);
+        if self.options.predicted_events {
+            for sample in pointer_events(pointer_evt, "getPredictedEvents") {
+                self.record_sample(event_type, &sample, Sample::Predicted, None);
+            }
+        }
+    }
+
+    /// 触摸产生的指针事件是否与 touch* 事件重复，见 [`is_duplicate_touch`]
+    fn is_duplicate_touch(&self, event_type: &str, event: &web_sys::Event) -> bool {
+        let pointer_type = event.dyn_ref::<web_sys::PointerEvent>().map(|p| p.pointer_type());
+        is_duplicate_touch(event_type, pointer_type.as_deref(), &self.options.event_types)
+    }
+
+    /// 键盘事件的记录粒度
+    fn keystroke_mode(&self) -> KeystrokeMode {
+        let class_only = self
+            .handle
+            .client_config
+            .with_untracked(|c| c.as_ref().is_some_and(|c| c.keystroke_class_only));
+        effective_keystroke_mode(class_only, self.state.keystroke_mode)
+    }
+
+    /// 记录一个采样并安排发送；`position` 为已由 [`move_cursor`] 算好的虚拟光标位置
+    fn record_sample(
+        self: &Rc<Self>,
+        event_type: &'static str,
+        event: &web_sys::Event,
+        sample: Sample,
+        position: Option<(f64, f64)>,
+    ) {
+        let Some((mut x, mut y, mut extra)) = event_fields(event_type, event, self.keystroke_mode()) else {
+            return;
+        };
+        if self.options.pointer_lock {
+            if event_type == "pointerlockchange" {
+                let locked = self.update_pointer_lock();
+                extra.insert("pointer_locked".to_string(), locked.to_string());
+            }
+            let mut cursor = self.state.virtual_cursor.borrow_mut();
+            if let (Some(pos), Some(mouse_evt)) = (cursor.as_mut(), event.dyn_ref::<web_sys::MouseEvent>()) {
+                let next = match position {
+                    Some(next) => next,
+                    None => {
+                        let next = (pos.0 + mouse_evt.movement_x() as f64, pos.1 + mouse_evt.movement_y() as f64);
+                        // 预测的位置不累加到虚拟光标
+                        if sample != Sample::Predicted {
+                            *pos = next;
+                        }
+                        next
+                    }
+                };
+                (x, y) = (next.0.round() as i32, next.1.round() as i32);
+                // 锁定期间浏览器的其他坐标停在锁定时的位置，没有意义
+                for key in ["screen_x", "screen_y", "page_x", "page_y", "offset_x", "offset_y"] {
+                    extra.remove(key);
+                }
+            }
+            let mode = if cursor.is_some() { "virtual" } else { "absolute" };
+            extra.insert("coordinate_mode".to_string(), mode.to_string());
+        }
+        match sample {
+            Sample::Dispatched => {}
+            Sample::Coalesced | Sample::Predicted => {
+                // 合并和预测的采样使用各自的时间
+                extra.insert("timestamp".to_string(), event_time(event).to_string());
+                let flag = if sample == Sample::Coalesced { "coalesced" } else { "predicted" };
+                extra.insert(flag.to_string(), "true".to_string());
+            }
+        }
+        if let Some(area) = self.options.area.get_untracked().filter(|_| !PAGE_EVENT_TYPES.contains(&event_type)) {
+            extra.insert("in_tracking_area".to_string(), in_tracking_area(event, &area).to_string());
+        }
+
+        let mut mouse_event = create_mouse_event(event_type, x, y, &self.state, Some(extra), event);
+        match event_type {
+            // 记录拖拽开始事件 ID，之后的 drag/dragend 以它为父事件
+            "dragstart" => *self.state.drag_state.borrow_mut() = Some(mouse_event.event_id.clone()),
+            "dragend" => *self.state.drag_state.borrow_mut() = None,
+            _ => {
+                let parent = link_press(
+                    &mut self.state.presses.borrow_mut(),
+                    event_type,
+                    mouse_event.button.as_deref(),
+                    &mouse_event.event_id,
+                );
+                if mouse_event.parent_event_id.is_none() {
+                    mouse_event.parent_event_id = parent;
+                }
+            }
+        }
+        let mut gestures = match event.dyn_ref::<web_sys::TouchEvent>() {
+            Some(touch_evt) => self.state.track_touches(&mut mouse_event, touch_evt),
+            None => Vec::new(),
+        };
+        gestures.retain(|g| self.options.event_types.contains(&g.event_type));
+        let recorded = 1 + gestures.len() as u64;
+
+        let pending = {
+            let mut buffer = self.buffer.borrow_mut();
+            buffer.push(mouse_event);
+            buffer.extend(gestures);
+            buffer.len()
+        };
+        self.handle.stats.update(|s| {
+            s.recorded += recorded;
+            s.pending = pending;
+        });
+
+        if pending >= self.options.max_batch {
+            self.flush();
+        } else {
+            self.schedule();
+        }
+    }
+
+    /// 根据当前锁定元素更新虚拟光标：锁定到追踪区域时从上一个事件的位置开始，解除时清空
+    fn update_pointer_lock(&self) -> bool {
+        let locked = match (self.options.area.get_untracked(), web_sys::window().and_then(|w| w.document())) {
+            (Some(area), Some(document)) => document.pointer_lock_element().is_some_and(|el| el == **area),
+            _ => false,
+        };
+        let mut cursor = self.state.virtual_cursor.borrow_mut();
+        match (locked, cursor.is_some()) {
+            (true, false) => {
+                let last = self.state.last_event.borrow().as_ref().map(|(e, _)| (e.x as f64, e.y as f64));
+                *cursor = Some(last.unwrap_or_default());
+            }
+            (false, true) => *cursor = None,
+            _ => {}
+        }
+        locked
+    }
+
+    /// 重新开始防抖计时
+    fn schedule(self: &Rc<Self>) {
+        let window = web_sys::window().expect("Window not available");
+        if let Some(handle) = self.timeout.borrow_mut().take() {
+            window.clear_timeout_with_handle(handle);
+        }
+
+        let this = self.clone();
+        let callback = Closure::once_into_js(move || this.flush());
+        let timeout_id = window
+            .set_timeout_with_callback_and_timeout_and_arguments_0(callback.unchecked_ref(), self.options.debounce_ms)
+            .expect("Failed to set timeout");
+        *self.timeout.borrow_mut() = Some(timeout_id);
+    }
+
+    fn cancel_timeout(&self) {
+        if let (Some(handle), Some(window)) = (self.timeout.borrow_mut().take(), web_sys::window()) {
+            window.clear_timeout_with_handle(handle);
+        }
+    }
+
+    /// 立即发送缓冲中的事件
+    fn flush(&self) {
+        self.cancel_timeout();
+        let events = self.buffer.borrow_mut().drain(..).collect::<Vec<_>>();
+        if events.is_empty() {
+            return;
+        }
+        self.handle.stats.update(|s| s.pending = 0);
+
+        let stats = self.handle.stats;
+        let endpoint = self.options.endpoint.clone();
+        let with_token = self.options.participant_token;
+        wasm_bindgen_futures::spawn_local(async move {
+            let count = events.len() as u64;
+            match send_events_batch(&endpoint, &events, with_token).await {
+                Ok(()) => stats.update(|s| s.sent += count),
+                Err(e) => {
+                    web_sys::console::log_1(&format!("Failed to send events: {:?}", e).into());
+                    stats.update(|s| s.failed += count);
+                }
+            }
+        });
+    }
+
+    /// 用 `navigator.sendBeacon` 发送缓冲中的事件（页面隐藏或关闭时）
+    ///
+    /// 浏览器在页面关闭后仍会完成 beacon 请求，但它不能带自定义请求头，这一批事件不附参与者令牌；
+    /// 浏览器拒绝排队（例如数据过大）时改用普通请求。
+    fn flush_beacon(&self) {
+        self.cancel_timeout();
+        let events = self.buffer.borrow_mut().drain(..).collect::<Vec<_>>();
+        if events.is_empty() {
+            return;
+        }
+
+        let count = events.len() as u64;
+        match send_events_beacon(&self.options.endpoint, &events) {
+            Ok(true) => self.handle.stats.update(|s| {
+                s.pending = 0;
+                s.sent += count;
+            }),
+            _ => {
+                self.buffer.borrow_mut().extend(events);
+                self.flush();
+            }
+        }
+    }
+
+    /// 丢弃尚未发送的事件
+    fn discard(&self) {
+        self.cancel_timeout();
+        self.buffer.borrow_mut().clear();
+        self.handle.stats.update(|s| s.pending = 0);
+    }
+}
+
+/// 事件目标是否位于不记录的元素内
+///
+/// 选择器无效时 `closest` 报错，此时按不记录处理：`exclude` 与 [`EXCLUDED_SELECTOR`] 拼在一起，
+/// 写错的额外选择器不能让密码框的按键被记录下来。
+#[cfg(feature = "hydrate")]
+fn is_excluded(event: &web_sys::Event, selector: &str) -> bool {
+    let Some(el) = event.target().and_then(|t| t.dyn_into::<web_sys::Element>().ok()) else {
+        return false;
+    };
+    !matches!(el.closest(selector), Ok(None))
+}
+
+/// 事件是否发生在追踪区域内：鼠标类和触摸事件按位置判断（拖拽移出区域时目标仍是被拖拽的元素），
+/// 键盘事件按目标元素判断
+#[cfg(feature = "hydrate")]
+fn in_tracking_area(event: &web_sys::Event, area: &web_sys::Element) -> bool {
+    let point = if let Some(mouse_evt) = event.dyn_ref::<web_sys::MouseEvent>() {
+        Some((mouse_evt.client_x(), mouse_evt.client_y()))
+    } else {
+        // 触摸事件按本次变化的第一个触点判断
+        event
+            .dyn_ref::<web_sys::TouchEvent>()
+            .and_then(|t| t.changed_touches().get(0))
+            .map(|t| (t.client_x(), t.client_y()))
+    };
+    if let Some((x, y)) = point {
+        let rect = area.get_bounding_client_rect();
+        let (x, y) = (x as f64, y as f64);
+        return x >= rect.left() && x < rect.right() && y >= rect.top() && y < rect.bottom();
+    }
+    event
+        .target()
+        .and_then(|t| t.dyn_into::<web_sys::Node>().ok())
+        .is_some_and(|node| area.contains(Some(&node)))
+}
+
+/// 按事件类型取出坐标和附加字段
+#[cfg(feature = "hydrate")]
+fn event_fields(
+    event_type: &str,
+    event: &web_sys::Event,
+    mode: KeystrokeMode,
+) -> Option<(i32, i32, HashMap<String, String>)> {
+    let mut extra = HashMap::new();
+
+    if let Some(keyboard_evt) = event.dyn_ref::<KeyboardEvent>() {
+        insert_key_fields(&mut extra, keyboard_evt, mode);
+        extra.insert("ctrl_key".to_string(), keyboard_evt.ctrl_key().to_string());
+        extra.insert("shift_key".to_string(), keyboard_evt.shift_key().to_string());
+        extra.insert("alt_key".to_string(), keyboard_evt.alt_key().to_string());
+        extra.insert("meta_key".to_string(), keyboard_evt.meta_key().to_string());
+        return Some((0, 0, extra));
+    }
+
+    // 页面事件没有坐标；滚动位置和视口大小由 create_mouse_event 填写
+    if PAGE_EVENT_TYPES.contains(&event_type) {
+        if event_type == "visibilitychange" {
+            let hidden = web_sys::window().and_then(|w| w.document()).is_some_and(|d| d.hidden());
+            extra.insert("visibility_state".to_string(), if hidden { "hidden" } else { "visible" }.to_string());
+        }
+        return Some((0, 0, extra));
+    }
+
+    // 坐标取本次变化的第一个触点，全部触点由 TrackingState::track_touches 填写
+    if let Some(touch_evt) = event.dyn_ref::<web_sys::TouchEvent>() {
+        let touch = touch_evt.changed_touches().get(0)?;
+        extra.insert("ctrl_key".to_string(), touch_evt.ctrl_key().to_string());
+        extra.insert("shift_key".to_string(), touch_evt.shift_key().to_string());
+        extra.insert("alt_key".to_string(), touch_evt.alt_key().to_string());
+        extra.insert("meta_key".to_string(), touch_evt.meta_key().to_string());
+        extra.insert("screen_x".to_string(), touch.screen_x().to_string());
+        extra.insert("screen_y".to_string(), touch.screen_y().to_string());
+        extra.insert("page_x".to_string(), touch.page_x().to_string());
+        extra.insert("page_y".to_string(), touch.page_y().to_string());
+        return Some((touch.client_x(), touch.client_y(), extra));
+    }
+
+    if let Some(pointer_evt) = event.dyn_ref::<web_sys::PointerEvent>() {
+        insert_pointer_fields(&mut extra, pointer_evt);
+    }
+
+    // PointerEvent、WheelEvent 和 DragEvent 都继承自 MouseEvent
+    let mouse_evt = event.dyn_ref::<web_sys::MouseEvent>()?;
+    extra.insert("screen_x".to_string(), mouse_evt.screen_x().to_string());
+    extra.insert("screen_y".to_string(), mouse_evt.screen_y().to_string());
+    extra.insert("page_x".to_string(), mouse_evt.page_x().to_string());
+    extra.insert("page_y".to_string(), mouse_evt.page_y().to_string());
+    extra.insert("offset_x".to_string(), mouse_evt.offset_x().to_string());
+    extra.insert("offset_y".to_string(), mouse_evt.offset_y().to_string());
+    extra.insert("movement_x".to_string(), mouse_evt.movement_x().to_string());
+    extra.insert("movement_y".to_string(), mouse_evt.movement_y().to_string());
+    match event_type {
+        "mousemove" | "pointercancel" => {
+            extra.insert("buttons".to_string(), mouse_evt.buttons().to_string());
+        }
+        "mousedown" | "mouseup" | "click" | "dblclick" | "auxclick" | "contextmenu" => {
+            let button = match mouse_evt.button() {
+                0 => "left",
+                1 => "middle",
+                2 => "right",
+                _ => "unknown",
+            };
+            extra.insert("button".to_string(), button.to_string());
+            extra.insert("buttons".to_string(), mouse_evt.buttons().to_string());
+            if event_type != "mousedown" && event_type != "mouseup" {
+                extra.insert("detail".to_string(), mouse_evt.detail().to_string());
+            }
+        }
+        "wheel" => {
+            let wheel_evt = event.dyn_ref::<web_sys::WheelEvent>()?;
+            extra.insert("scroll_y".to_string(), wheel_evt.delta_y().to_string());
+            extra.insert("scroll_x".to_string(), wheel_evt.delta_x().to_string());
+        }
+        _ => {}
+    }
+    Some((mouse_evt.client_x(), mouse_evt.client_y(), extra))
+}
+
+/// 写入指针类型、压力、倾斜和接触区域
+#[cfg(feature = "hydrate")]
+fn insert_pointer_fields(extra: &mut HashMap<String, String>, pointer_evt: &web_sys::PointerEvent) {
+    extra.insert("pointer_type".to_string(), pointer_evt.pointer_type());
+    extra.insert("pointer_id".to_string(), pointer_evt.pointer_id().to_string());
+    extra.insert("pressure".to_string(), pointer_evt.pressure().to_string());
+    extra.insert("tangential_pressure".to_string(), pointer_evt.tangential_pressure().to_string());
+    extra.insert("tilt_x".to_string(), pointer_evt.tilt_x().to_string());
+    extra.insert("tilt_y".to_string(), pointer_evt.tilt_y().to_string());
+    extra.insert("twist".to_string(), pointer_evt.twist().to_string());
+    extra.insert("width".to_string(), pointer_evt.width().to_string());
+    extra.insert("height".to_string(), pointer_evt.height().to_string());
+    extra.insert("is_primary".to_string(), pointer_evt.is_primary().to_string());
+}
+
+/// 键盘事件的记录粒度：服务器设置要求只记录类别时覆盖组件属性
+#[cfg(any(feature = "hydrate", test))]
+fn effective_keystroke_mode(server_class_only: bool, mode: KeystrokeMode) -> KeystrokeMode {
+    match server_class_only {
+        true => KeystrokeMode::ClassOnly,
+        false => mode,
+    }
+}
+
+/// 按记录粒度生成按键字段：总是有 `key_class`，只记录类别时不含 `key` 和 `code`
+#[cfg(any(feature = "hydrate", test))]
+fn key_fields(key: String, code: String, mode: KeystrokeMode) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("key_class", KeyClass::of(&key).as_str().to_string())];
+    if mode == KeystrokeMode::Full {
+        fields.push(("key", key));
+        fields.push(("code", code));
+    }
+    fields
+}
+
+/// 按记录粒度写入按键字段
+#[cfg(feature = "hydrate")]
+fn insert_key_fields(extra: &mut HashMap<String, String>, keyboard_evt: &KeyboardEvent, mode: KeystrokeMode) {
+    for (name, value) in key_fields(keyboard_evt.key(), keyboard_evt.code(), mode) {
+        extra.insert(name.to_string(), value);
+    }
+}
+
+/// 获取目标元素信息
+#[cfg(feature = "hydrate")]
+fn get_target_info(event: &web_sys::Event) -> (Option<String>, Option<String>, Option<String>, Option<String>) {
+    let target = event.target();
+    let mut tag_name = None;
+    let mut id = None;
+    let mut class_name = None;
+    let mut text = None;
+
+    if let Some(target) = target {
+        if let Some(element) = target.dyn_ref::<web_sys::Element>() {
+            tag_name = Some(element.tag_name().to_lowercase());
+            id = element.get_attribute("id");
+            class_name = element.get_attribute("class");
+
+            // 获取文本内容（限制长度）
+            if let Some(html_element) = element.dyn_ref::<web_sys::HtmlElement>() {
+                let txt = html_element.inner_text();
+                // 按字符而非字节截断，避免在中文字符中间切分
+                let truncated: String = txt.chars().take(50).collect();
+                if txt.chars().count() > 50 {
+                    text = Some(format!("{}...", truncated));
+                } else if !txt.is_empty() {
+                    text = Some(txt);
+                }
+            }
+        }
+    }
+
+    (tag_name, id, class_name, text)
+}
+
+/// 计算速度和距离；`last` 为上一个事件的位置和单调时钟时间，时间不前进时不计算
+#[cfg(any(feature = "hydrate", test))]
+fn calculate_velocity_and_distance(
+    last: Option<(i32, i32, f64)>,
+    current_x: i32,
+    current_y: i32,
+    current_time: f64,
+) -> (Option<f64>, Option<f64>, Option<f64>) {
+    if let Some((last_x, last_y, last_time)) = last {
+        let dx = (current_x - last_x) as f64;
+        let dy = (current_y - last_y) as f64;
+        let dt = current_time - last_time;
+
+        if dt > 0.0 {
+            let distance = (dx * dx + dy * dy).sqrt();
+            let velocity_x = dx / dt;
+            let velocity_y = dy / dt;
+
+            return (Some(velocity_x), Some(velocity_y), Some(distance));
+        }
+    }
+
+    (None, None, None)
+}
+
+/// 创建完整的事件对象
+#[cfg(feature = "hydrate")]
+fn create_mouse_event(
+    event_type: &str,
+    x: i32,
+    y: i32,
+    tracking_state: &TrackingState,
+    extra: Option<std::collections::HashMap<String, String>>,
+    event: &web_sys::Event,
+) -> MouseEvent {
+    // 墙上时间只作参考；速度和距离按单调时钟计算，同一毫秒内的事件也能区分
+    let timestamp = extra
+        .as_ref()
+        .and_then(|e| e.get("timestamp"))
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or_else(js_sys::Date::now);
+    let (event_timestamp, time_origin) = monotonic_time(event);
+    let current_time = event_timestamp;
+    // 预测的位置和没有坐标的页面事件不参与速度计算
+    let kinematic = !extra.as_ref().is_some_and(|e| e.contains_key("predicted")) && !PAGE_EVENT_TYPES.contains(&event_type);
+
+    // 生成事件 ID
+    let event_id = tracking_state.generate_event_id();
+
+    // 获取目标元素信息
+    let (target_tag, target_id, target_class, target_text) = get_target_info(event);
+
+    // 安全地获取上一个事件数据（使用 try_borrow 避免冲突）
+    let last_event_data = tracking_state
+        .last_event
+        .try_borrow()
+        .ok()
+        .and_then(|b| b.as_ref().map(|(e, time)| (e.x, e.y, *time)));
+
+    // 计算速度和距离
+    let (vel_x, vel_y, dist) = calculate_velocity_and_distance(
+        last_event_data,
+        x,
+        y,
+        current_time,
+    );
+
+    // 获取视口信息
+    let window = web_sys::window().expect("Window not available");
+    // resize 时已在 Recorder::record 中刷新
+    let viewport = tracking_state.viewport(false);
+    let viewport_width = viewport.map(|(w, _)| w);
+    let viewport_height = viewport.map(|(_, h)| h);
+    let device_pixel_ratio = Some(window.device_pixel_ratio());
+    let scroll_offset_x = window.scroll_x().ok();
+    let scroll_offset_y = window.scroll_y().ok();
+
+    // 安全地获取拖拽状态
+    let parent_event_id = tracking_state.drag_state.try_borrow().ok().and_then(|b| b.as_ref().cloned());
+
+    // 处理额外信息
+    let mut screen_x = None;
+    let mut screen_y = None;
+    let mut page_x = None;
+    let mut page_y = None;
+    let mut offset_x = None;
+    let mut offset_y = None;
+    let mut movement_x = None;
+    let mut movement_y = None;
+    let mut button = None;
+    let mut detail = None;
+    let mut buttons = None;
+    let mut pointer_type = None;
+    let mut pointer_id = None;
+    let mut pressure = None;
+    let mut tangential_pressure = None;
+    let mut tilt_x = None;
+    let mut tilt_y = None;
+    let mut twist = None;
+    let mut width = None;
+    let mut height = None;
+    let mut is_primary = None;
+    let mut scroll_y = None;
+    let mut scroll_x = None;
+    let mut key = None;
+    let mut code = None;
+    let mut ctrl_key = None;
+    let mut shift_key = None;
+    let mut alt_key = None;
+    let mut meta_key = None;
+    let mut key_class = None;
+    let mut in_tracking_area = None;
+    let mut coalesced = None;
+    let mut predicted = None;
+    let mut visibility_state = None;
+    let mut pointer_locked = None;
+    let mut coordinate_mode = None;
+
+    if let Some(extra) = extra {
+        if let Some(v) = extra.get("screen_x") { screen_x = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("screen_y") { screen_y = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("page_x") { page_x = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("page_y") { page_y = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("offset_x") { offset_x = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("offset_y") { offset_y = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("movement_x") { movement_x = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("movement_y") { movement_y = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("button") { button = Some(v.clone()); }
+        if let Some(v) = extra.get("detail") { detail = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("buttons") { buttons = v.parse::<u16>().ok(); }
+        if let Some(v) = extra.get("pointer_type") { pointer_type = Some(v.clone()); }
+        if let Some(v) = extra.get("pointer_id") { pointer_id = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("pressure") { pressure = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("tangential_pressure") { tangential_pressure = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("tilt_x") { tilt_x = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("tilt_y") { tilt_y = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("twist") { twist = v.parse::<i32>().ok(); }
+        if let Some(v) = extra.get("width") { width = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("height") { height = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("is_primary") { is_primary = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("scroll_y") { scroll_y = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("scroll_x") { scroll_x = v.parse::<f64>().ok(); }
+        if let Some(v) = extra.get("key") { key = Some(v.clone()); }
+        if let Some(v) = extra.get("code") { code = Some(v.clone()); }
+        if let Some(v) = extra.get("ctrl_key") { ctrl_key = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("shift_key") { shift_key = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("alt_key") { alt_key = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("meta_key") { meta_key = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("key_class") { key_class = Some(v.clone()); }
+        if let Some(v) = extra.get("in_tracking_area") { in_tracking_area = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("coalesced") { coalesced = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("predicted") { predicted = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("visibility_state") { visibility_state = Some(v.clone()); }
+        if let Some(v) = extra.get("pointer_locked") { pointer_locked = v.parse::<bool>().ok(); }
+        if let Some(v) = extra.get("coordinate_mode") { coordinate_mode = Some(v.clone()); }
+    }
+
+    let mouse_event = MouseEvent {
+        event_type: event_type.to_string(),
+        timestamp: timestamp as u64,
+        event_timestamp: Some(event_timestamp),
+        time_origin,
+        x,
+        y,
+        screen_x,
+        screen_y,
+        page_x,
+        page_y,
+        offset_x,
+        offset_y,
+        movement_x,
+        movement_y,
+        button,
+        buttons,
+        detail,
+        pointer_type,
+        pointer_id,
+        pressure,
+        tangential_pressure,
+        tilt_x,
+        tilt_y,
+        twist,
+        width,
+        height,
+        is_primary,
+        scroll_y,
+        scroll_x,
+        target: None,
+        target_tag,
+        target_id,
+        target_class,
+        target_text,
+        session_id: tracking_state.session_id.clone(),
+        event_id: event_id.clone(),
+        parent_event_id,
+        participant_id: None, // 由服务器根据参与者令牌填写
+        origin: None,         // 由服务器根据 Origin 请求头填写
+        velocity_x: vel_x,
+        velocity_y: vel_y,
+        distance: dist,
+        key,
+        code,
+        ctrl_key,
+        shift_key,
+        alt_key,
+        meta_key,
+        key_class,
+        touches: None,
+        gesture_scale: None,
+        gesture_rotation: None,
+        swipe_direction: None,
+        visibility_state,
+        pointer_locked,
+        coordinate_mode,
+        coalesced,
+        predicted,
+        in_tracking_area,
+        viewport_width,
+        viewport_height,
+        device_pixel_ratio,
+        scroll_offset_x,
+        scroll_offset_y,
+        metadata: None,
+    };
+
+    // 尝试更新上一个事件（如果借用失败则跳过）
+    if let (true, Ok(mut last)) = (kinematic, tracking_state.last_event.try_borrow_mut()) {
+        *last = Some((mouse_event.clone(), current_time));
+    }
+
+    mouse_event
+}
+
+/// 批量发送事件到服务器
+#[cfg(feature = "hydrate")]
+async fn send_events_batch(endpoint: &str, events: &[MouseEvent], with_token: bool) -> Result<(), JsValue> {
+    let events_json = serde_json::to_string(events).unwrap();
+    send_to_server(&format!("{}/mouse", endpoint), &events_json, with_token).await
+}
+
+/// 用 `navigator.sendBeacon` 提交事件，返回浏览器是否接受排队
+#[cfg(feature = "hydrate")]
+fn send_events_beacon(endpoint: &str, events: &[MouseEvent]) -> Result<bool, JsValue> {
+    let window = web_sys::window().ok_or(JsValue::from_str("Window not available"))?;
+    let parts = js_sys::Array::of1(&JsValue::from_str(&serde_json::to_string(events).unwrap()));
+    let options = web_sys::BlobPropertyBag::new();
+    // 服务器按 JSON 解析请求体，需要正确的 Content-Type
+    options.set_type("application/json");
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    window
+        .navigator()
+        .send_beacon_with_opt_blob(&format!("{}/mouse", endpoint), Some(&blob))
+}
+
+/// 浏览器中保存参与者令牌的 localStorage 键
+#[cfg(feature = "hydrate")]
+const PARTICIPANT_STORAGE_KEY: &str = "mouse_tracker_participant";
+
+/// 读取参与者令牌，不存在时生成一个随机令牌并保存
+///
+/// 令牌只用于让服务器计算假名，本身不包含任何个人信息。
+#[cfg(feature = "hydrate")]
+fn participant_token() -> Option<String> {
+    let window = web_sys::window()?;
+    let storage = window.local_storage().ok()??;
+    if let Ok(Some(token)) = storage.get_item(PARTICIPANT_STORAGE_KEY) {
+        return Some(token);
+    }
+
+    let token = random_id()?;
+    storage.set_item(PARTICIPANT_STORAGE_KEY, &token).ok()?;
+    Some(token)
+}
+
+/// 丢弃当前参与者令牌，下次提交时生成新令牌
+#[cfg(feature = "hydrate")]
+pub(crate) fn reset_participant_token() {
+    if let Some(Ok(Some(storage))) = web_sys::window().map(|w| w.local_storage()) {
+        let _ = storage.remove_item(PARTICIPANT_STORAGE_KEY);
+    }
+}
+
+/// 请求服务器清除当前参与者令牌对应的假名
+#[cfg(feature = "hydrate")]
+pub(crate) async fn send_unlink(endpoint: &str) -> Result<(), JsValue> {
+    send_to_server(&format!("{}/participant/unlink", endpoint), "{}", true).await
+}
+
+/// 提交同意或撤回决定，返回服务器的回应（同意时包含撤回密钥）
+#[cfg(feature = "hydrate")]
+pub(crate) async fn send_consent(endpoint: &str, decision: &ConsentDecision) -> Result<ConsentResponse, JsValue> {
+    let decision_json = serde_json::to_string(decision).unwrap();
+    let resp = post_json(&format!("{}/consent", endpoint), &decision_json, true).await?;
+    let text = JsFuture::from(resp.text()?).await?;
+    serde_json::from_str(&text.as_string().unwrap_or_default()).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// 读取服务器下发的采集设置
+#[cfg(feature = "hydrate")]
+async fn fetch_client_config(endpoint: &str) -> Result<ClientConfig, JsValue> {
+    let window = web_sys::window().ok_or(JsValue::from_str("Window not available"))?;
+    let resp: Response = JsFuture::from(window.fetch_with_str(&format!("{}/client-config", endpoint)))
+        .await?
+        .dyn_into()?;
+    if !resp.ok() {
+        return Err(JsValue::from_str("Request failed"));
+    }
+    let text = JsFuture::from(resp.text()?).await?;
+    serde_json::from_str(&text.as_string().unwrap_or_default()).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// 发送 JSON 数据到服务器，`with_token` 为 true 时附带参与者令牌
+#[cfg(feature = "hydrate")]
+async fn send_to_server(url: &str, event_json: &str, with_token: bool) -> Result<(), JsValue> {
+    post_json(url, event_json, with_token).await.map(|_| ())
+}
+
+/// POST JSON 并返回成功的响应
+#[cfg(feature = "hydrate")]
+async fn post_json(url: &str, event_json: &str, with_token: bool) -> Result<Response, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_mode(RequestMode::Cors);
+
+    let headers = Headers::new()?;
+    headers.append("Content-Type", "application/json")?;
+    if let Some(token) = participant_token().filter(|_| with_token) {
+        headers.append(PARTICIPANT_HEADER, &token)?;
+    }
+    opts.set_headers(&headers);
+
+    opts.set_body(&JsValue::from_str(event_json));
+
+    let request = Request::new_with_str_and_init(url, &opts)?;
+    let window = web_sys::window().ok_or(JsValue::from_str("Window not available"))?;
+
+    let promise = window.fetch_with_request(&request);
+    let resp_value: JsValue = JsFuture::from(promise).await?;
+    let resp: Response = resp_value.dyn_into()?;
+
+    if resp.ok() {
+        Ok(resp)
+    } else {
+        Err(JsValue::from_str("Request failed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn class_only_keystrokes_never_carry_the_character() {
+        for (key, code, class) in [
+            ("a", "KeyA", "letter"),
+            ("Ж", "Semicolon", "letter"),
+            ("7", "Digit7", "digit"),
+            (" ", "Space", "whitespace"),
+            ("Enter", "Enter", "whitespace"),
+            ("?", "Slash", "punctuation"),
+            ("ArrowLeft", "ArrowLeft", "navigation"),
+            ("Shift", "ShiftLeft", "modifier"),
+            ("Backspace", "Backspace", "editing"),
+            ("F12", "F12", "function"),
+            ("Dead", "Quote", "other"),
+        ] {
+            let fields = key_fields(key.to_string(), code.to_string(), KeystrokeMode::ClassOnly);
+            assert_eq!(fields, vec![("key_class", class.to_string())], "{key}");
+
+            let full = key_fields(key.to_string(), code.to_string(), KeystrokeMode::Full);
+            assert_eq!(full[1..], [("key", key.to_string()), ("code", code.to_string())]);
+        }
+    }
+
+    #[test]
+    fn server_setting_forces_class_only_keystrokes() {
+        assert_eq!(effective_keystroke_mode(true, KeystrokeMode::Full), KeystrokeMode::ClassOnly);
+        assert_eq!(effective_keystroke_mode(false, KeystrokeMode::Full), KeystrokeMode::Full);
+        assert_eq!(effective_keystroke_mode(false, KeystrokeMode::ClassOnly), KeystrokeMode::ClassOnly);
+    }
+
+    #[test]
+    fn touch_pointer_events_are_dropped_only_when_touch_events_are_recorded() {
+        let with_touch = vec!["mousedown".to_string(), "touchstart".to_string()];
+        let without_touch = vec!["mousedown".to_string()];
+        for event_type in ["mousemove", "mousedown", "mouseup", "pointercancel"] {
+            assert!(is_duplicate_touch(event_type, Some("touch"), &with_touch));
+            assert!(!is_duplicate_touch(event_type, Some("touch"), &without_touch));
+            assert!(!is_duplicate_touch(event_type, Some("pen"), &with_touch));
+            assert!(!is_duplicate_touch(event_type, None, &with_touch));
+        }
+        // click 不是 touch* 事件的重复，总是记录
+        assert!(!is_duplicate_touch("click", Some("touch"), &with_touch));
+    }
+
+    #[test]
+    fn spreading_and_twisting_two_fingers_ends_as_pinch_and_rotate() {
+        let mut pinch = Pinch::start(Some("e1".to_string()), span((0, 0), (100, 0)));
+        pinch.update(span((0, 0), (0, 150)));
+        assert_eq!(pinch.finish(), (Some(1.5), Some(90.0)));
+        assert_eq!(pinch.parent.as_deref(), Some("e1"));
+
+        // 跨过 ±180° 时取较小的转角
+        let mut pinch = Pinch::start(None, span((0, 0), (-100, 1)));
+        pinch.update(span((0, 0), (-100, -1)));
+        assert_eq!(pinch.finish(), (None, None));
+        assert!(pinch.rotation.abs() < 2.0, "{}", pinch.rotation);
+    }
+
+    #[test]
+    fn small_two_finger_movements_are_not_gestures() {
+        let mut pinch = Pinch::start(None, span((0, 0), (100, 0)));
+        pinch.update(span((0, 0), (105, 10)));
+        assert_eq!(pinch.finish(), (None, None));
+
+        // 两指重合时无法计算比例，保持 1.0
+        let mut pinch = Pinch::start(None, span((5, 5), (5, 5)));
+        pinch.update(span((0, 0), (100, 0)));
+        assert_eq!(pinch.scale, 1.0);
+    }
+
+    #[test]
+    fn swipes_need_distance_and_speed() {
+        assert_eq!(swipe_direction(80.0, 10.0, 200.0), Some("right"));
+        assert_eq!(swipe_direction(-80.0, 10.0, 200.0), Some("left"));
+        assert_eq!(swipe_direction(10.0, 80.0, 200.0), Some("down"));
+        assert_eq!(swipe_direction(10.0, -80.0, 200.0), Some("up"));
+        assert_eq!(swipe_direction(30.0, 30.0, 100.0), None);
+        assert_eq!(swipe_direction(80.0, 0.0, SWIPE_MAX_MS + 1.0), None);
+        assert_eq!(swipe_direction(SWIPE_MIN_DISTANCE, 0.0, SWIPE_MAX_MS), Some("right"));
+    }
+
+    #[test]
+    fn velocity_uses_sub_millisecond_monotonic_time() {
+        // 墙上时间同为一毫秒内的两个事件，单调时钟相差 0.25 毫秒
+        let (vx, vy, distance) = calculate_velocity_and_distance(Some((10, 20, 1000.5)), 13, 16, 1000.75);
+        assert_eq!((vx, vy, distance), (Some(12.0), Some(-16.0), Some(5.0)));
+
+        assert_eq!(calculate_velocity_and_distance(None, 13, 16, 1000.75), (None, None, None));
+        // 时间不前进时不产生无穷大的速度
+        assert_eq!(calculate_velocity_and_distance(Some((10, 20, 1000.5)), 13, 16, 1000.5), (None, None, None));
+        assert_eq!(calculate_velocity_and_distance(Some((10, 20, 1000.5)), 13, 16, 999.0), (None, None, None));
+    }
+
+    #[test]
+    fn synthetic_events_without_a_time_stamp_use_the_current_time() {
+        assert_eq!(time_stamp_or_now(1234.567, || panic!("not needed")), 1234.567);
+        assert_eq!(time_stamp_or_now(0.0, || 99.5), 99.5);
+    }
+
+    #[test]
+    fn clicks_link_to_the_mousedown_of_the_same_button() {
+        let mut presses = std::collections::HashMap::new();
+        let mut link = |event_type: &str, button: Option<&str>, event_id: &str| link_press(&mut presses, event_type, button, event_id);
+
+        assert_eq!(link("mousedown", Some("0"), "e1"), None);
+        assert_eq!(link("mousedown", Some("2"), "e2"), None);
+        assert_eq!(link("mouseup", Some("0"), "e3").as_deref(), Some("e1"));
+        assert_eq!(link("click", Some("0"), "e4").as_deref(), Some("e1"));
+        assert_eq!(link("contextmenu", Some("2"), "e5").as_deref(), Some("e2"));
+        assert_eq!(link("auxclick", Some("1"), "e6"), None);
+
+        // 双击时第二次按下开始新的点击，dblclick 以它为父事件
+        assert_eq!(link("mousedown", Some("0"), "e7"), None);
+        assert_eq!(link("click", Some("0"), "e8").as_deref(), Some("e7"));
+        assert_eq!(link("dblclick", Some("0"), "e9").as_deref(), Some("e7"));
+
+        assert_eq!(link("mousemove", Some("0"), "e10"), None);
+        assert_eq!(link("click", None, "e11"), None);
+    }
+
+    #[test]
+    fn coalesced_movement_is_counted_once() {
+        let mut pos = (100.0, 100.0);
+        // 派发事件的 movement 是三个合并采样之和
+        let positions = move_cursor(&mut pos, (6, -3), &[(1, -1), (2, -1), (3, -1)]);
+        assert_eq!(positions, vec![(101.0, 99.0), (103.0, 98.0), (106.0, 97.0)]);
+        assert_eq!(pos, (106.0, 97.0));
+    }
+
+    #[test]
+    fn dispatched_movement_is_used_without_coalesced_samples() {
+        let mut pos = (0.0, 0.0);
+        assert_eq!(move_cursor(&mut pos, (4, 5), &[]), vec![(4.0, 5.0)]);
+        assert_eq!(move_cursor(&mut pos, (-1, 2), &[]), vec![(3.0, 7.0)]);
+        assert_eq!(pos, (3.0, 7.0));
+    }
+}
@@ -1,10 +1,72 @@
 use serde::{Deserialize, Serialize};
 
+/// 当前同意书版本；同意书内容变化时递增，旧版本的同意不再有效
+pub const CONSENT_VERSION: &str = "2025-02";
+
+/// 客户端提交的同意或撤回决定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsentDecision {
+    pub session_id: String,
+    pub version: String,
+    pub granted: bool,
+    /// 会话同意时服务器签发的撤回密钥；该会话之后的每次决定都必须附上
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdraw_token: Option<String>,
+}
+
+/// 服务器对同意决定的回应（`POST /api/consent`）
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsentResponse {
+    pub session_id: String,
+    pub version: String,
+    pub granted: bool,
+    /// 同意时新签发的撤回密钥，替换之前的密钥；服务器只保存其哈希
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdraw_token: Option<String>,
+}
+
+/// 服务器下发给采集页面的设置（`GET /api/client-config`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientConfig {
+    /// 当前同意书版本
+    pub consent_version: String,
+    /// 是否只接收已同意会话的事件
+    pub consent_required: bool,
+    /// 键盘事件只记录按键类别
+    pub keystroke_class_only: bool,
+}
+
+impl Default for ClientConfig {
+    /// 服务器不提供设置时（例如旧版本服务器）的设置：内置同意书版本，不强制同意，完整记录按键
+    fn default() -> Self {
+        Self {
+            consent_version: CONSENT_VERSION.to_string(),
+            consent_required: false,
+            keystroke_class_only: false,
+        }
+    }
+}
+
+/// 携带浏览器参与者令牌的请求头，服务器据此计算 `participant_id`
+pub const PARTICIPANT_HEADER: &str = "x-participant-token";
+
+/// 客户端会产生的全部事件类型，顺序固定（导出的独热编码列依赖此顺序）
+pub const EVENT_TYPES: &[&str] = &[
+    "mousemove", "mousedown", "mouseup", "wheel", "dragstart", "drag", "dragend", "keydown", "keyup",
+    "pointercancel", "touchstart", "touchmove", "touchend", "touchcancel", "pinch", "rotate", "swipe",
+    "click", "dblclick", "contextmenu", "auxclick", "scroll", "resize", "visibilitychange", "focus", "blur",
+    "pointerlockchange",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MouseEvent {
     // 基础事件信息
-    pub event_type: String,  // "mousemove", "mousedown", "mouseup", "wheel", "dragstart", "drag", "dragend", "keydown", "keyup", "click"
+    pub event_type: String,  // "mousemove", "mousedown", "mouseup", "wheel", "dragstart", "drag", "dragend", "keydown", "keyup", "pointercancel", "touchstart", "pinch", "swipe", "click", "dblclick"
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_timestamp: Option<f64>, // 浏览器事件的 timeStamp：相对 time_origin 的单调时钟（毫秒，带小数），速度按它计算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_origin: Option<f64>,     // performance.timeOrigin（Unix 毫秒），同一页面内不变
 
     // 位置信息
     pub x: i32,
@@ -13,10 +75,42 @@ pub struct MouseEvent {
     pub screen_y: Option<i32>,
     pub page_x: Option<i32>,      // 页面坐标
     pub page_y: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset_x: Option<i32>,    // 相对目标元素内边距边缘的坐标
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset_y: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub movement_x: Option<i32>,  // 相对上一个同类事件的移动量（指针锁定时仍有效）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub movement_y: Option<i32>,
 
     // 鼠标按钮状态
     pub button: Option<String>,    // "left", "middle", "right"
     pub buttons: Option<u16>,      // 位掩码：1=左键, 2=右键, 4=中键
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<i32>,       // click/dblclick/auxclick 的连续点击次数（UIEvent.detail）
+
+    // 指针信息（Pointer Events），旧数据中没有这些字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer_type: Option<String>,        // "mouse", "pen", "touch"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<f64>,               // 压力 0..1，不支持压力的设备按下时为 0.5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tangential_pressure: Option<f64>,    // 切向压力（笔的滚轮）-1..1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilt_x: Option<i32>,                 // 笔的倾斜角度（度）-90..90
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tilt_y: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub twist: Option<i32>,                  // 笔绕自身轴的旋转（度）0..359
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<f64>,                  // 接触区域宽度（CSS 像素）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_primary: Option<bool>,            // 是否为该类型的主指针（多点触控时只有第一根手指为 true）
 
     // 滚轮信息
     pub scroll_y: Option<f64>,
@@ -33,6 +127,10 @@ pub struct MouseEvent {
     pub session_id: String,             // 会话 ID，用于关联同一会话的所有事件
     pub event_id: String,               // 事件唯一 ID
     pub parent_event_id: Option<String>, // 父事件 ID（用于拖拽等序列事件）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participant_id: Option<String>, // 参与者假名，由服务器根据参与者令牌计算，跨会话稳定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,         // 提交页面的来源（经服务器校验的 Origin 请求头）
 
     // 速度和方向（用于 mousemove 和 drag）
     pub velocity_x: Option<f64>,        // X 轴速度（像素/毫秒）
@@ -46,11 +144,128 @@ pub struct MouseEvent {
     pub shift_key: Option<bool>,
     pub alt_key: Option<bool>,
     pub meta_key: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_class: Option<String>,      // 按键类别（见 KeyClass），脱敏后替代 key/code
+
+    // 触摸事件：全部活动触点，以及本次抬起的触点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub touches: Option<Vec<TouchPoint>>,
+
+    // 手势（pinch、rotate、swipe），由触摸事件推导
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gesture_scale: Option<f64>,     // 两指距离相对开始时的比例
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gesture_rotation: Option<f64>,  // 两指连线相对开始时旋转的角度（度，顺时针为正）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swipe_direction: Option<String>, // "left", "right", "up", "down"
+
+    // 浏览器合并或预测的指针采样（getCoalescedEvents / getPredictedEvents）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalesced: Option<bool>,        // 被合并到同一帧、未单独派发的采样
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub predicted: Option<bool>,        // 浏览器预测的未来位置，不是真实输入
+
+    // 页面状态
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visibility_state: Option<String>, // visibilitychange 之后的可见性："visible", "hidden"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pointer_locked: Option<bool>,     // pointerlockchange 之后指针是否锁定在追踪区域
+
+    // 坐标含义：开启指针锁定模式时为 "absolute"（浏览器坐标）或 "virtual"（锁定期间由 movement 累加的虚拟光标），
+    // 未开启时为空，与 "absolute" 相同
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coordinate_mode: Option<String>,
+
+    // 整页监听时事件是否发生在追踪区域内
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_tracking_area: Option<bool>,
 
     // 视口信息
     pub viewport_width: Option<u32>,
     pub viewport_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_pixel_ratio: Option<f64>, // CSS 像素与设备像素之比
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll_offset_x: Option<f64>,    // 页面滚动位置（window.scrollX/Y），与滚轮增量 scroll_x/y 不同
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll_offset_y: Option<f64>,
 
     // 额外元数据
     pub metadata: Option<String>,       // JSON 字符串，存储额外信息
 }
+
+/// 触摸事件中的一个触点
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TouchPoint {
+    pub identifier: i32,                // 触点 ID，同一根手指从按下到抬起不变
+    pub x: i32,                         // 客户端坐标
+    pub y: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius_x: Option<f64>,          // 接触椭圆半径（像素）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius_y: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation_angle: Option<f64>,    // 接触椭圆旋转角度（度）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force: Option<f64>,             // 压力 0..1
+    #[serde(default)]
+    pub changed: bool,                  // 是否为本次事件中按下、移动或抬起的触点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stroke_id: Option<String>,      // 所属笔画：该触点 touchstart 事件的 ID
+}
+
+/// 按键类别，用于在不记录具体字符的情况下保留按键节奏信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyClass {
+    Letter,
+    Digit,
+    Whitespace,
+    Punctuation,
+    Navigation,
+    Modifier,
+    Editing,
+    Function,
+    Other,
+}
+
+impl KeyClass {
+    /// 根据 `KeyboardEvent.key` 判断类别
+    pub fn of(key: &str) -> Self {
+        let mut chars = key.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return if c.is_alphabetic() {
+                Self::Letter
+            } else if c.is_numeric() {
+                Self::Digit
+            } else if c.is_whitespace() {
+                Self::Whitespace
+            } else {
+                Self::Punctuation
+            };
+        }
+
+        match key {
+            "Enter" | "Tab" => Self::Whitespace,
+            "ArrowUp" | "ArrowDown" | "ArrowLeft" | "ArrowRight" | "Home" | "End" | "PageUp" | "PageDown" => Self::Navigation,
+            "Shift" | "Control" | "Alt" | "AltGraph" | "Meta" | "CapsLock" | "Fn" | "NumLock" | "ScrollLock" => Self::Modifier,
+            "Backspace" | "Delete" | "Insert" | "Cut" | "Copy" | "Paste" | "Undo" | "Redo" => Self::Editing,
+            "Escape" => Self::Function,
+            k if k.len() > 1 && k.starts_with('F') && k[1..].chars().all(|c| c.is_ascii_digit()) => Self::Function,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Letter => "letter",
+            Self::Digit => "digit",
+            Self::Whitespace => "whitespace",
+            Self::Punctuation => "punctuation",
+            Self::Navigation => "navigation",
+            Self::Modifier => "modifier",
+            Self::Editing => "editing",
+            Self::Function => "function",
+            Self::Other => "other",
+        }
+    }
+}